@@ -0,0 +1,211 @@
+/*
+ * Annotations of the moves of the PGN: their comments, NAGs ("$1" for a good move, "$4" for a
+ * blunder, …) and the arrows and circles drawn on the board.
+ *
+ * The shapes are read from the "[%cal Ge2e4,Rd1d8]" and "[%csl Gd5]" commands of the comments, as
+ * written by Lichess and ChessBase. The letter before the squares is the color: green, red, blue
+ * or yellow.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+use chessground::{DrawBrush, DrawShape};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+use shakmaty::Square;
+
+/// Annotation of the position once `ply` moves of the main line are played.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Annotation {
+    #[serde(default)]
+    pub comment: String,
+    /// Numeric annotation glyphs of the last move.
+    #[serde(default)]
+    pub nags: Vec<u8>,
+    pub ply: usize,
+    #[serde(default)]
+    pub shapes: Vec<Shape>,
+}
+
+/// Arrow, or circle when `orig` and `dest` are the same square.
+#[derive(Clone)]
+pub struct Shape {
+    pub orig: Square,
+    pub dest: Square,
+    pub brush: DrawBrush,
+}
+
+impl Shape {
+    pub fn to_draw_shape(&self) -> DrawShape {
+        DrawShape::new(self.orig, self.dest, self.brush)
+    }
+}
+
+impl PartialEq<DrawShape> for Shape {
+    fn eq(&self, rhs: &DrawShape) -> bool {
+        self.orig == rhs.orig() && self.dest == rhs.dest() && self.brush == rhs.brush()
+    }
+}
+
+/// Written as in the PGN commands, e.g. "Ge2e4" or "Rd5" for a circle.
+impl fmt::Display for Shape {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let color =
+            match self.brush {
+                DrawBrush::Green => 'G',
+                DrawBrush::Red => 'R',
+                DrawBrush::Blue => 'B',
+                DrawBrush::Yellow => 'Y',
+            };
+        if self.orig == self.dest {
+            write!(formatter, "{}{}", color, self.orig)
+        }
+        else {
+            write!(formatter, "{}{}{}", color, self.orig, self.dest)
+        }
+    }
+}
+
+impl FromStr for Shape {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = || format!("Invalid shape {}", text);
+        let brush =
+            match text.chars().next() {
+                Some('G') => DrawBrush::Green,
+                Some('R') => DrawBrush::Red,
+                Some('B') => DrawBrush::Blue,
+                Some('Y') => DrawBrush::Yellow,
+                _ => return Err(error()),
+            };
+        let squares = text.get(1..).ok_or_else(error)?;
+        let square = |name: Option<&str>| {
+            name.and_then(|name| Square::from_ascii(name.as_bytes()).ok())
+                .ok_or_else(error)
+        };
+        let (orig, dest) =
+            match squares.len() {
+                2 => {
+                    let square = square(Some(squares))?;
+                    (square, square)
+                },
+                4 => (square(squares.get(..2))?, square(squares.get(2..))?),
+                _ => return Err(error()),
+            };
+        Ok(Self {
+            orig,
+            dest,
+            brush,
+        })
+    }
+}
+
+impl Serialize for Shape {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Shape {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Shapes of the %cal and %csl commands of a comment. The invalid shapes are ignored.
+pub fn parse(comment: &str) -> Vec<Shape> {
+    let mut shapes = vec![];
+    let mut rest = comment;
+    while let Some(start) = rest.find("[%") {
+        rest = &rest[start + 2..];
+        let end = rest.find(']').unwrap_or_else(|| rest.len());
+        let mut command = rest[..end].split_whitespace();
+        if let Some("cal") | Some("csl") = command.next() {
+            for shape in command.flat_map(|arguments| arguments.split(',')) {
+                match shape.trim().parse() {
+                    Ok(shape) => shapes.push(shape),
+                    Err(error) => eprintln!("{}", error),
+                }
+            }
+        }
+        rest = &rest[end..];
+    }
+    shapes
+}
+
+/// Text of a comment without its commands.
+pub fn text(comment: &str) -> String {
+    let mut text = String::new();
+    let mut rest = comment;
+    while let Some(start) = rest.find("[%") {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        rest = &rest[rest.find(']').map(|end| end + 1).unwrap_or_else(|| rest.len())..];
+    }
+    text.push_str(rest);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Add a PGN comment to the annotation after `ply` moves.
+pub fn add_comment(annotations: &mut Vec<Annotation>, ply: usize, comment: &str) {
+    let shapes = parse(comment);
+    let text = text(comment);
+    if shapes.is_empty() && text.is_empty() {
+        return;
+    }
+    let annotation = at(annotations, ply);
+    annotation.shapes.extend(shapes);
+    if !text.is_empty() {
+        if !annotation.comment.is_empty() {
+            annotation.comment.push(' ');
+        }
+        annotation.comment.push_str(&text);
+    }
+}
+
+/// Add a NAG to the last move of the `ply` moves.
+pub fn add_nag(annotations: &mut Vec<Annotation>, ply: usize, nag: u8) {
+    at(annotations, ply).nags.push(nag);
+}
+
+/// Annotation after `ply` moves, added if there is none yet.
+fn at(annotations: &mut Vec<Annotation>, ply: usize) -> &mut Annotation {
+    let index =
+        match annotations.iter().position(|annotation| annotation.ply == ply) {
+            Some(index) => index,
+            None => {
+                annotations.push(Annotation {
+                    ply,
+                    ..Annotation::default()
+                });
+                annotations.len() - 1
+            },
+        };
+    &mut annotations[index]
+}
+
+/// Symbol of the usual NAGs, like "!?", or "$n" for the others.
+pub fn nag_symbol(nag: u8) -> String {
+    let symbol =
+        match nag {
+            1 => "!",
+            2 => "?",
+            3 => "!!",
+            4 => "??",
+            5 => "!?",
+            6 => "?!",
+            10 => "=",
+            14 => "+=",
+            15 => "=+",
+            16 => "±",
+            17 => "∓",
+            18 => "+-",
+            19 => "-+",
+            _ => return format!("${}", nag),
+        };
+    symbol.to_string()
+}
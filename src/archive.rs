@@ -0,0 +1,55 @@
+/*
+ * Decompress the game dumps, which are often shipped as zip, gzip or bzip2 archives.
+ *
+ * The format is detected from the first bytes of the data rather than from the file name, so that
+ * downloaded files are handled too.
+ */
+
+use std::io::{Cursor, Read};
+
+use bzip2::read::BzDecoder;
+use flate2::read::MultiGzDecoder;
+use zip::ZipArchive;
+
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Check if `name` is the name of a PGN file, compressed or not.
+pub fn is_pgn_file(name: &str) -> bool {
+    let name = name.to_lowercase();
+    let name = name.trim_end_matches(".gz").trim_end_matches(".bz2");
+    name.ends_with(".pgn") || name.ends_with(".bpgn") || name.ends_with(".zip")
+}
+
+/// Decompress `data` if it is an archive. The PGN files of a zip archive are concatenated.
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut decompressed = vec![];
+    if data.starts_with(GZIP_MAGIC) {
+        MultiGzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)
+            .map_err(|error| format!("Cannot decompress gzip archive: {}", error))?;
+    }
+    else if data.starts_with(BZIP2_MAGIC) {
+        BzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)
+            .map_err(|error| format!("Cannot decompress bzip2 archive: {}", error))?;
+    }
+    else if data.starts_with(ZIP_MAGIC) {
+        let mut archive = ZipArchive::new(Cursor::new(data))
+            .map_err(|error| format!("Cannot open zip archive: {}", error))?;
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)
+                .map_err(|error| format!("Cannot read zip archive: {}", error))?;
+            if file.is_dir() || !is_pgn_file(file.name()) {
+                continue;
+            }
+            file.read_to_end(&mut decompressed)
+                .map_err(|error| format!("Cannot decompress {}: {}", file.name(), error))?;
+            // Games of different files must not be glued together.
+            decompressed.extend_from_slice(b"\n\n");
+        }
+    }
+    else {
+        return Ok(data);
+    }
+    Ok(decompressed)
+}
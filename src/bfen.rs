@@ -0,0 +1,65 @@
+/*
+ * BFEN, the FEN of both boards of a bughouse game: "board A | board B", each board being a FEN
+ * with the holdings of both players.
+ *
+ * The holdings are written either in brackets after the board ("RNBQKBNR[Qn] w ...") or as a
+ * ninth rank ("RNBQKBNR/Qn w ..."), which can be empty. The tools writing BFEN often leave out the
+ * last fields of a board: the castling rights, en passant square and move counters default to
+ * none and the first move, and the side to move to white.
+ */
+
+use shakmaty::{
+    fen::{self, Fen},
+    FromSetup,
+    position::Bughouse,
+};
+
+/// Parse a BFEN, giving the position of the first board and the partner's board. A FEN of one
+/// board is also accepted.
+pub fn parse(bfen: &str) -> Result<(Bughouse, Option<Bughouse>), String> {
+    let mut boards = bfen.split('|');
+    let position = parse_board(boards.next().unwrap_or(""))?;
+    let partner =
+        match boards.next() {
+            Some(partner) => Some(parse_board(partner)?),
+            None => None,
+        };
+    if boards.next().is_some() {
+        return Err(format!("Too many boards in BFEN {}", bfen));
+    }
+    Ok((position, partner))
+}
+
+/// Format the BFEN of `position` and the partner's board, if any.
+pub fn format(position: &Bughouse, partner: Option<&Bughouse>) -> String {
+    match partner {
+        Some(partner) => format!("{} | {}", fen::fen(position), fen::fen(partner)),
+        None => fen::fen(position),
+    }
+}
+
+fn parse_board(text: &str) -> Result<Bughouse, String> {
+    let mut fields = text.split_whitespace();
+    let board = fields.next().ok_or("Empty board in BFEN")?;
+    let board =
+        match board.find('[') {
+            Some(_) => board.to_string(),
+            None => {
+                let ranks: Vec<&str> = board.split('/').collect();
+                if ranks.len() == 9 {
+                    format!("{}[{}]", ranks[..8].join("/"), ranks[8])
+                }
+                else {
+                    format!("{}[]", board)
+                }
+            },
+        };
+    let defaults = ["w", "-", "-", "0", "1"];
+    let mut fen = board;
+    for &default in &defaults {
+        fen.push(' ');
+        fen.push_str(fields.next().unwrap_or(default));
+    }
+    let setup = Fen::from_ascii(fen.as_bytes()).map_err(|error| format!("Error parsing FEN {}: {}", text.trim(), error))?;
+    Bughouse::from_setup(&setup).map_err(|error| format!("Error setup position {}: {}", text.trim(), error))
+}
@@ -0,0 +1,200 @@
+/*
+ * Parse the bughouse PGN (BPGN) of the dumps of FICS games, where the moves of both boards are
+ * interleaved in the order they were played: "1A." and "1a." number the moves of white and
+ * black on board A, "1B." and "1b." the moves of board B.
+ *
+ * The dumps have a few quirks handled here: the players are in WhiteA, BlackA, WhiteB and BlackB
+ * headers, the move numbers can be glued to the move ("1A.e4"), drops can be written with a star
+ * ("N*f3") and the clocks are in comments following the moves ("e4{179.9}"), sometimes with the
+ * "C:" prefix of the standard ("{C:2:59.9}"). The other comments starting with "C:" are the chat
+ * or the game result, not clocks.
+ *
+ * See https://bughousedb.com/Lieven_BPGN_Standard.txt
+ */
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Board {
+    A,
+    B,
+}
+
+impl Board {
+    pub fn index(self) -> usize {
+        match self {
+            Board::A => 0,
+            Board::B => 1,
+        }
+    }
+
+    pub fn other(self) -> Self {
+        match self {
+            Board::A => Board::B,
+            Board::B => Board::A,
+        }
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Board::A
+    }
+}
+
+pub struct BpgnGame {
+    pub headers: Vec<(String, String)>,
+    pub moves: Vec<BpgnMove>,
+}
+
+impl BpgnGame {
+    fn new() -> Self {
+        Self {
+            headers: vec![],
+            moves: vec![],
+        }
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header_key, _)| header_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct BpgnMove {
+    pub board: Board,
+    /// Messages of the chat sent after the move.
+    pub chat: Vec<String>,
+    /// Comment following the move, usually the clock.
+    pub comment: Option<String>,
+    pub san: String,
+}
+
+/// Check if `text` looks like BPGN rather than single board PGN.
+pub fn is_bpgn(text: &str) -> bool {
+    text.contains("[WhiteA ") || text.contains("[WhiteB ") || text.contains("1A.")
+}
+
+pub fn parse(text: &str) -> Vec<BpgnGame> {
+    let mut games = vec![];
+    let mut game = BpgnGame::new();
+    let mut board = Board::A;
+    let mut chars = text.chars().peekable();
+    while let Some(&character) = chars.peek() {
+        match character {
+            '[' => {
+                chars.next();
+                let header: String = chars.by_ref().take_while(|&character| character != ']').collect();
+                // The headers of the next game follow the moves of the previous one.
+                if !game.moves.is_empty() {
+                    games.push(game);
+                    game = BpgnGame::new();
+                    board = Board::A;
+                }
+                if let Some(header) = parse_header(&header) {
+                    game.headers.push(header);
+                }
+            },
+            '{' => {
+                chars.next();
+                let comment: String = chars.by_ref().take_while(|&character| character != '}').collect();
+                if let Some(last_move) = game.moves.last_mut() {
+                    if is_chat(&comment) {
+                        last_move.chat.push(comment[2..].trim().to_string());
+                    }
+                    else if last_move.comment.is_none() {
+                        last_move.comment = Some(comment.trim().to_string());
+                    }
+                }
+            },
+            ';' => {
+                // Comment until the end of the line.
+                chars.by_ref().take_while(|&character| character != '\n').for_each(drop);
+            },
+            '(' => {
+                // Variations are not part of the game.
+                let mut depth = 0;
+                for character in chars.by_ref() {
+                    match character {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => (),
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            },
+            _ if character.is_whitespace() => {
+                chars.next();
+            },
+            _ => {
+                let mut token = String::new();
+                while let Some(&character) = chars.peek() {
+                    if character.is_whitespace() || "[{(;".contains(character) {
+                        break;
+                    }
+                    token.push(character);
+                    chars.next();
+                }
+                let san =
+                    match parse_move_number(&token) {
+                        Some((move_board, rest)) => {
+                            board = move_board;
+                            rest
+                        },
+                        None => &token,
+                    };
+                if !san.is_empty() && !is_result(san) {
+                    game.moves.push(BpgnMove {
+                        board,
+                        chat: vec![],
+                        comment: None,
+                        san: san.replace('*', "@"),
+                    });
+                }
+            },
+        }
+    }
+    if !game.moves.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+/// Parse a header like `WhiteA "name"`.
+fn parse_header(header: &str) -> Option<(String, String)> {
+    let header = header.trim();
+    let space = header.find(char::is_whitespace)?;
+    let value = header[space..].trim().trim_matches('"').replace("\\\"", "\"");
+    Some((header[..space].to_string(), value))
+}
+
+fn is_chat(comment: &str) -> bool {
+    comment.starts_with("C:") && !comment[2..].trim_start().starts_with(|character: char| character.is_ascii_digit())
+}
+
+fn is_result(token: &str) -> bool {
+    token == "1-0" || token == "0-1" || token == "1/2-1/2" || token == "*"
+}
+
+/// Parse a move number like "12A." or "12b...", giving the board and what follows the dots.
+fn parse_move_number(token: &str) -> Option<(Board, &str)> {
+    let digits = token.find(|character: char| !character.is_ascii_digit())?;
+    if digits == 0 {
+        return None;
+    }
+    let rest = &token[digits..];
+    let board =
+        match rest.chars().next()? {
+            'A' | 'a' => Board::A,
+            'B' | 'b' => Board::B,
+            _ => return None,
+        };
+    let rest = &rest[1..];
+    if !rest.starts_with('.') {
+        return None;
+    }
+    Some((board, rest.trim_start_matches('.')))
+}
@@ -0,0 +1,58 @@
+/*
+ * Guess the character encoding of PGN files, which is not specified: the servers export in UTF-8,
+ * but older databases and the FICS dumps are in Latin-1 or Windows-1252. A file starting with a
+ * byte order mark is decoded as it says, otherwise each line that is valid UTF-8 is kept as such
+ * and the others are decoded as a single byte encoding, since files are often made by
+ * concatenating games from different sources.
+ */
+
+use std::str;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1252};
+
+// Bytes which are not characters in Windows-1252, but control characters in Latin-1.
+const UNDEFINED_WINDOWS_1252: [u8; 5] = [0x81, 0x8D, 0x8F, 0x90, 0x9D];
+
+pub fn decode(data: &[u8]) -> String {
+    if let Some((encoding, _)) = Encoding::for_bom(data) {
+        let (result, _) = encoding.decode_with_bom_removal(data);
+        return result.into_owned();
+    }
+    if let Some(encoding) = utf16_without_bom(data) {
+        let (result, _) = encoding.decode_without_bom_handling(data);
+        return result.into_owned();
+    }
+    if let Ok(text) = str::from_utf8(data) {
+        return text.to_string();
+    }
+
+    let latin1 = data.iter().any(|byte| UNDEFINED_WINDOWS_1252.contains(byte));
+    let mut result = String::with_capacity(data.len());
+    for line in data.split_inclusive(|&byte| byte == b'\n') {
+        match str::from_utf8(line) {
+            Ok(line) => result.push_str(line),
+            Err(_) if latin1 => result.extend(line.iter().map(|&byte| byte as char)),
+            Err(_) => result.push_str(&WINDOWS_1252.decode_without_bom_handling(line).0),
+        }
+    }
+    result
+}
+
+/// Detect UTF-16 from the zero bytes of ASCII characters, which is all the PGN syntax.
+fn utf16_without_bom(data: &[u8]) -> Option<&'static Encoding> {
+    let start = &data[..data.len().min(1000) & !1];
+    if start.is_empty() {
+        return None;
+    }
+    let zeros = |offset| start.iter().skip(offset).step_by(2).filter(|&&byte| byte == 0).count();
+    let pairs = start.len() / 2;
+    if zeros(1) * 10 > pairs * 9 && zeros(0) == 0 {
+        Some(UTF_16LE)
+    }
+    else if zeros(0) * 10 > pairs * 9 && zeros(1) == 0 {
+        Some(UTF_16BE)
+    }
+    else {
+        None
+    }
+}
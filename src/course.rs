@@ -0,0 +1,101 @@
+/*
+ * Training course: the puzzles are grouped into named chapters, in the order of their first
+ * puzzle, and each chapter is unlocked once enough puzzles of the previous one are solved.
+ *
+ * The puzzles without a chapter are not part of the course. The puzzles of the course solved are
+ * saved as JSON in the user data directory, by id, so that the progress is kept across runs.
+ */
+
+use std::collections::BTreeSet;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+use crate::puzzle::Puzzle;
+
+pub const DEFAULT_UNLOCK_RATE: u32 = 80;
+
+pub struct Chapter {
+    pub name: String,
+    /// Indices of the puzzles of the chapter, in the order of the puzzle set.
+    pub puzzles: Vec<usize>,
+    /// Number of puzzles of the chapter solved at least once.
+    pub solved: usize,
+    /// Whether the previous chapter was completed with the success rate required to unlock this one.
+    pub unlocked: bool,
+}
+
+impl Chapter {
+    /// Share of the puzzles of the chapter solved at least once, in percent.
+    pub fn success_rate(&self) -> u32 {
+        if self.puzzles.is_empty() {
+            return 0;
+        }
+
+        (self.solved * 100 / self.puzzles.len()) as u32
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CourseProgress {
+    /// Ids of the puzzles of the course solved at least once.
+    solved: BTreeSet<String>,
+}
+
+impl CourseProgress {
+    pub fn load() -> Self {
+        data::load_json("course.json", "course progress")
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        data::save_json("course.json", self)
+    }
+
+    pub fn is_solved(&self, id: &str) -> bool {
+        self.solved.contains(id)
+    }
+
+    pub fn solve(&mut self, id: String) {
+        self.solved.insert(id);
+    }
+}
+
+/// Chapters of the course, the first one being always unlocked and the next ones unlocked when
+/// the success rate of the previous chapter reaches `unlock_rate`, in percent. `is_solved` tells
+/// if the puzzle at an index was solved at least once.
+pub fn chapters<F: Fn(usize) -> bool>(puzzles: &[Puzzle], unlock_rate: u32, is_solved: F) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = vec![];
+    for (index, puzzle) in puzzles.iter().enumerate() {
+        let name =
+            match puzzle.chapter {
+                Some(ref name) => name,
+                None => continue,
+            };
+        let position =
+            match chapters.iter().position(|chapter| &chapter.name == name) {
+                Some(position) => position,
+                None => {
+                    chapters.push(Chapter {
+                        name: name.clone(),
+                        puzzles: vec![],
+                        solved: 0,
+                        unlocked: false,
+                    });
+                    chapters.len() - 1
+                },
+            };
+        let chapter = &mut chapters[position];
+        chapter.puzzles.push(index);
+        if is_solved(index) {
+            chapter.solved += 1;
+        }
+    }
+    let mut unlocked = true;
+    for chapter in &mut chapters {
+        chapter.unlocked = unlocked;
+        unlocked = unlocked && chapter.success_rate() >= unlock_rate;
+    }
+    chapters
+}
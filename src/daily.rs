@@ -0,0 +1,92 @@
+/*
+ * Puzzle of the day: a puzzle of the library picked from the date and its id, the same for the
+ * whole day, shown on startup.
+ *
+ * The puzzle of the day and the days it was solved in a row are saved as JSON in the user data
+ * directory, apart from the statistics of the other puzzles, so that it is still shown when other
+ * puzzles are loaded later in the day.
+ */
+
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+use crate::hash;
+use crate::puzzle::Puzzle;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Number of the current day since the Unix epoch, in UTC.
+pub fn today() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
+
+/// Index of the puzzle of the `day` among the puzzles of these `ids`. The day is hashed with the
+/// ids, so that the pick does not depend on the order of the puzzles and the puzzles of
+/// consecutive days are unrelated.
+pub fn puzzle_index<I: IntoIterator<Item=String>>(day: u64, ids: I) -> Option<usize> {
+    ids.into_iter()
+        .enumerate()
+        .min_by_key(|(_, id)| hash::fnv1a(format!("{} {}", id, day).as_bytes()))
+        .map(|(index, _)| index)
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct DailyPuzzle {
+    /// Day the puzzle was picked.
+    pub day: u64,
+    pub id: String,
+    pub puzzle: Puzzle,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DailyStats {
+    /// Last day the puzzle of the day was solved.
+    pub last_solved: Option<u64>,
+    pub longest_streak: u32,
+    /// Last puzzle of the day picked.
+    pub puzzle: Option<DailyPuzzle>,
+    /// Days the puzzle of the day was solved in a row, up to `last_solved`.
+    streak: u32,
+}
+
+impl DailyStats {
+    pub fn load() -> Self {
+        data::load_json("daily.json", "daily statistics")
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        data::save_json("daily.json", self)
+    }
+
+    /// Puzzle of the `day`, if it was already picked.
+    pub fn puzzle(&self, day: u64) -> Option<&DailyPuzzle> {
+        self.puzzle.as_ref().filter(|daily| daily.day == day)
+    }
+
+    /// Record that the puzzle of the `day` is solved, extending the streak if the puzzle of the
+    /// day before was solved too.
+    pub fn solve(&mut self, day: u64) {
+        match self.last_solved {
+            Some(last_solved) if last_solved == day => return,
+            Some(last_solved) if last_solved + 1 == day => self.streak += 1,
+            _ => self.streak = 1,
+        }
+        self.last_solved = Some(day);
+        self.longest_streak = self.longest_streak.max(self.streak);
+    }
+
+    /// Current streak on the `day`, which is broken if the puzzle of the day before was not
+    /// solved.
+    pub fn streak(&self, day: u64) -> u32 {
+        match self.last_solved {
+            Some(last_solved) if last_solved + 1 >= day => self.streak,
+            _ => 0,
+        }
+    }
+}
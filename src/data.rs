@@ -0,0 +1,50 @@
+/*
+ * Files of buzzle in the user data directory (e.g. ~/.local/share/buzzle), like the statistics,
+ * the recent files and the generation queue, saved as JSON.
+ */
+
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Path of the file `name` in the data directory.
+pub fn path(name: &str) -> Option<PathBuf> {
+    glib::get_user_data_dir()
+        .map(|dir| dir.join("buzzle").join(name))
+}
+
+/// Load the JSON file `name`, falling back to the default value when it does not exist or cannot
+/// be read. `what` is its content in the error messages, e.g. "best scores".
+pub fn load_json<T: DeserializeOwned + Default>(name: &str, what: &str) -> T {
+    let path =
+        match path(name) {
+            Some(path) => path,
+            None => return T::default(),
+        };
+    match File::open(&path) {
+        Ok(file) =>
+            serde_json::from_reader(file).unwrap_or_else(|error| {
+                eprintln!("Cannot parse {} {}: {}", what, path.display(), error);
+                T::default()
+            }),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => T::default(),
+        Err(error) => {
+            eprintln!("Cannot open {} {}: {}", what, path.display(), error);
+            T::default()
+        },
+    }
+}
+
+/// Save `value` in the JSON file `name`, creating the data directory if needed.
+pub fn save_json<T: Serialize>(name: &str, value: &T) -> io::Result<()> {
+    let path = path(name).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = File::create(path)?;
+    serde_json::to_writer(file, value)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+}
@@ -0,0 +1,83 @@
+/*
+ * Estimate the difficulty of a puzzle from the depth the engine needs to find the solution, the
+ * number of quiet moves (neither captures nor checks) and the number of sacrifices in it.
+ */
+
+use std::io;
+
+use shakmaty::{
+    Position,
+    Setup,
+};
+
+use crate::engine::{Engine, SearchLimits};
+use crate::material::material_balance;
+use crate::puzzle::Puzzle;
+
+const BASE_RATING: u32 = 800;
+const DEPTH_RATING: u32 = 60;
+const LENGTH_RATING: u32 = 100;
+const MAX_DEPTH: u32 = 20;
+const QUIET_MOVE_RATING: u32 = 150;
+const SACRIFICE_RATING: u32 = 200;
+
+pub fn estimate(engine: Option<&mut dyn Engine>, puzzle: &Puzzle) -> io::Result<u32> {
+    let depth =
+        match engine {
+            Some(engine) => depth_needed(engine, puzzle)?,
+            None => 0,
+        };
+    let (quiet_moves, sacrifices) = count_moves(puzzle);
+    let player_moves = (puzzle.solution.len() as u32 + 1) / 2;
+    Ok(BASE_RATING + depth * DEPTH_RATING + player_moves * LENGTH_RATING + quiet_moves * QUIET_MOVE_RATING +
+       sacrifices * SACRIFICE_RATING)
+}
+
+/// Minimum depth at which the engine finds one of the first moves of the solution.
+fn depth_needed(engine: &mut dyn Engine, puzzle: &Puzzle) -> io::Result<u32> {
+    if puzzle.solution.is_empty() {
+        return Ok(0);
+    }
+    engine.set_position(&puzzle.position)?;
+    for depth in 1..=MAX_DEPTH {
+        engine.search(&SearchLimits::depth(depth))?;
+        let found = engine.best_move()
+            .and_then(|uci| uci.to_move(&puzzle.position).ok())
+            .map(|mov| puzzle.solution.first_moves().any(|first_move| *first_move == mov))
+            .unwrap_or(false);
+        if found {
+            return Ok(depth);
+        }
+    }
+    Ok(MAX_DEPTH)
+}
+
+/// Count the quiet moves and the sacrifices of the player in the main line of the solution.
+pub fn count_moves(puzzle: &Puzzle) -> (u32, u32) {
+    let player = puzzle.position.turn();
+    let mut position = puzzle.position.clone();
+    let mut quiet_moves = 0;
+    let mut sacrifices = 0;
+    let main_line = puzzle.solution.main_line();
+    for (index, mov) in main_line.iter().enumerate() {
+        let balance = material_balance(&position, player);
+        position.play_unchecked(mov);
+        if index % 2 != 0 {
+            continue;
+        }
+
+        if !mov.is_capture() && !position.is_check() {
+            quiet_moves += 1;
+        }
+
+        // Losing material after the opponent's reply is a sacrifice.
+        if let Some(reply) = main_line.get(index + 1) {
+            let mut after_reply = position.clone();
+            after_reply.play_unchecked(reply);
+            if material_balance(&after_reply, player) < balance - 1 && !after_reply.is_checkmate() {
+                sacrifices += 1;
+            }
+        }
+    }
+    (quiet_moves, sacrifices)
+}
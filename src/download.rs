@@ -0,0 +1,246 @@
+/*
+ * Download games from online databases to generate puzzles from.
+ *
+ * The downloaded files are kept in a cache directory: asking again for the same games doesn't
+ * download them again, and the files can be queued like the local ones.
+ *
+ * Big downloads are resumed when asked again after being interrupted, e.g. by closing the
+ * application: a file is continued with a range request when the server gave an ETag or a
+ * modification date to check that it did not change, and the games downloaded one by one are
+ * appended to a partial file along with their ids, so that they are not downloaded again.
+ */
+
+pub mod bughousedb;
+pub mod chesscom;
+pub mod lichess;
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Time given to a server to answer.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Download the text at `url`. This blocks: it should not run in the GUI thread.
+pub fn fetch(url: &str) -> Result<String, String> {
+    get(url)?.into_string()
+        .map_err(|error| format!("Cannot read {}: {}", url, error))
+}
+
+/// Download the file at `url`, without decoding it.
+pub fn fetch_bytes(url: &str) -> Result<Vec<u8>, String> {
+    let mut data = vec![];
+    get(url)?.into_reader().read_to_end(&mut data)
+        .map_err(|error| format!("Cannot read {}: {}", url, error))?;
+    Ok(data)
+}
+
+/// Parse the URL of a file to download, which must be HTTP or HTTPS.
+pub fn parse_url(text: &str) -> Result<String, String> {
+    let url = text.trim();
+    if url.starts_with("http://") || url.starts_with("https://") {
+        Ok(url.to_string())
+    }
+    else {
+        Err(format!("Invalid URL {}: only http and https are supported", url))
+    }
+}
+
+/// Path of the file `name` in the cache, downloaded with `download` if it is not there yet.
+pub fn cached<F>(name: &str, download: F) -> Result<PathBuf, String>
+where F: FnOnce() -> Result<String, String>
+{
+    let path = cache_dir().ok_or("No cache directory")?.join(file_name(name));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    save(name, &download()?)
+}
+
+/// Path of the file `name` in the cache, downloaded from `url` if it is not there yet. This is
+/// meant for big files: an interrupted download is resumed.
+pub fn cached_url(name: &str, url: &str) -> Result<PathBuf, String> {
+    let dir = cache_dir().ok_or("No cache directory")?;
+    let path = dir.join(file_name(name));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    fs::create_dir_all(&dir).map_err(|error| error.to_string())?;
+    let partial_path = path.with_extension("part");
+    let validator_path = path.with_extension("validator");
+    let offset = fs::metadata(&partial_path).map(|metadata| metadata.len()).unwrap_or(0);
+    let mut request = ureq::get(url);
+    // The whole download can take longer than the timeout.
+    request.timeout_connect(TIMEOUT.as_millis() as u64);
+    request.timeout_read(TIMEOUT.as_millis() as u64);
+    if offset > 0 {
+        if let Ok(validator) = fs::read_to_string(&validator_path) {
+            request.set("Range", &format!("bytes={}-", offset));
+            // The whole file is sent again if it changed.
+            request.set("If-Range", validator.trim());
+        }
+    }
+    let response = check(request.call(), url)?;
+    let resumed = response.status() == 206;
+    match response.header("ETag").or_else(|| response.header("Last-Modified")) {
+        Some(validator) => fs::write(&validator_path, validator).map_err(|error| error.to_string())?,
+        None => remove_file(&validator_path)?,
+    }
+    let mut options = OpenOptions::new();
+    if resumed {
+        options.append(true);
+    }
+    else {
+        options.write(true).create(true).truncate(true);
+    }
+    let mut file = options.open(&partial_path).map_err(|error| error.to_string())?;
+    io::copy(&mut response.into_reader(), &mut file)
+        .map_err(|error| format!("Cannot download {}: {}", url, error))?;
+    fs::rename(&partial_path, &path).map_err(|error| error.to_string())?;
+    remove_file(&validator_path)?;
+    Ok(path)
+}
+
+/// Download of a file of the cache made of games downloaded one by one. The games are written as
+/// soon as they are downloaded, so that they are not downloaded again after an interruption.
+pub struct Partial {
+    /// Ids of the games already downloaded, including those which were skipped.
+    done: HashSet<u64>,
+    done_file: File,
+    file: File,
+    path: PathBuf,
+    size: u64,
+}
+
+impl Partial {
+    /// Continue the download of the file `name` of the cache, or start it.
+    fn open(name: &str) -> Result<Self, String> {
+        let dir = cache_dir().ok_or("No cache directory")?;
+        fs::create_dir_all(&dir).map_err(|error| error.to_string())?;
+        let path = dir.join(file_name(name));
+        let partial_path = path.with_extension("part");
+        let done_path = path.with_extension("done");
+        let open = |path: &PathBuf| OpenOptions::new().append(true).create(true).open(path)
+            .map_err(|error| error.to_string());
+        let file = open(&partial_path)?;
+        let done_file = open(&done_path)?;
+        let done = fs::read_to_string(&done_path).map_err(|error| error.to_string())?
+            .lines()
+            .filter_map(|line| line.parse().ok())
+            .collect();
+        let size = file.metadata().map_err(|error| error.to_string())?.len();
+        Ok(Self {
+            done,
+            done_file,
+            file,
+            path,
+            size,
+        })
+    }
+
+    pub fn is_done(&self, id: u64) -> bool {
+        self.done.contains(&id)
+    }
+
+    /// Append the `content` downloaded for the games `ids`.
+    pub fn add(&mut self, ids: &[u64], content: &str) -> Result<(), String> {
+        self.file.write_all(content.as_bytes())
+            .and_then(|()| self.file.flush())
+            .map_err(|error| error.to_string())?;
+        self.size += content.len() as u64;
+        for &id in ids {
+            writeln!(self.done_file, "{}", id).map_err(|error| error.to_string())?;
+            self.done.insert(id);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    fn finish(self) -> Result<PathBuf, String> {
+        fs::rename(self.path.with_extension("part"), &self.path).map_err(|error| error.to_string())?;
+        remove_file(&self.path.with_extension("done"))?;
+        Ok(self.path)
+    }
+}
+
+/// Path of the file `name` in the cache, downloaded with `download` if it is not there yet or if
+/// `refresh` is set. An interrupted download continues with the games not downloaded yet.
+pub fn cached_partial<F>(name: &str, refresh: bool, download: F) -> Result<PathBuf, String>
+where F: FnOnce(&mut Partial) -> Result<(), String>
+{
+    let path = cache_dir().ok_or("No cache directory")?.join(file_name(name));
+    if path.exists() && !refresh {
+        return Ok(path);
+    }
+
+    let mut partial = Partial::open(name)?;
+    download(&mut partial)?;
+    partial.finish()
+}
+
+/// Save `content` as the file `name` of the cache, replacing it.
+pub fn save(name: &str, content: &str) -> Result<PathBuf, String> {
+    let dir = cache_dir().ok_or("No cache directory")?;
+    let path = dir.join(file_name(name));
+    fs::create_dir_all(&dir).map_err(|error| error.to_string())?;
+    // Write to another file first, so that an interrupted download is not taken for a complete one.
+    let partial_path = path.with_extension("part");
+    fs::write(&partial_path, content).map_err(|error| error.to_string())?;
+    fs::rename(&partial_path, &path).map_err(|error| error.to_string())?;
+    Ok(path)
+}
+
+/// Encode `text` to be used as a value in the query of a URL.
+pub fn encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn get(url: &str) -> Result<ureq::Response, String> {
+    let response = ureq::get(url)
+        .timeout(TIMEOUT)
+        .call();
+    check(response, url)
+}
+
+fn check(response: ureq::Response, url: &str) -> Result<ureq::Response, String> {
+    if let Some(error) = response.synthetic_error() {
+        return Err(format!("Cannot download {}: {}", url, error));
+    }
+    if response.error() {
+        return Err(format!("Cannot download {}: {}", url, response.status_line()));
+    }
+    Ok(response)
+}
+
+fn remove_file(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.map_err(|error| error.to_string()),
+    }
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    glib::get_user_cache_dir()
+        .map(|dir| dir.join("buzzle").join("downloads"))
+}
+
+/// Replace the characters which are not safe in a file name.
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|character| if character.is_ascii_alphanumeric() || "-_.".contains(character) { character } else { '_' })
+        .collect()
+}
@@ -0,0 +1,83 @@
+/*
+ * Games of bughousedb.com, the database of the bughouse games played on FICS, exported in BPGN.
+ */
+
+use std::path::PathBuf;
+
+use super::{cached_url, encode};
+
+const EXPORT_URL: &str = "https://www.bughousedb.com/export.php";
+const GAME_URL: &str = "https://www.bughousedb.com/game.php";
+
+/// Page of the game with the number of the BughouseDBGameNo header.
+pub fn game_url(number: &str) -> String {
+    format!("{}?gameno={}", GAME_URL, encode(number))
+}
+
+/// Games to download: the dates are inclusive, in the "YYYY-MM-DD" format.
+pub struct Query {
+    pub from: Option<String>,
+    /// Only the games of these players, or every game when empty.
+    pub players: Vec<String>,
+    pub to: Option<String>,
+}
+
+impl Query {
+    /// Parse the fields of the download dialog, where the players are separated by commas.
+    pub fn parse(from: &str, to: &str, players: &str) -> Result<Self, String> {
+        let query = Self {
+            from: parse_date(from)?,
+            players: players.split(',')
+                .map(str::trim)
+                .filter(|player| !player.is_empty())
+                .map(str::to_string)
+                .collect(),
+            to: parse_date(to)?,
+        };
+        if query.from.is_none() && query.to.is_none() && query.players.is_empty() {
+            return Err("Choose a date range or players, the whole database is too big".to_string());
+        }
+        Ok(query)
+    }
+
+    fn url(&self) -> String {
+        let mut url = format!("{}?format=bpgn", EXPORT_URL);
+        if let Some(ref from) = self.from {
+            url.push_str(&format!("&from={}", from));
+        }
+        if let Some(ref to) = self.to {
+            url.push_str(&format!("&to={}", to));
+        }
+        if !self.players.is_empty() {
+            url.push_str(&format!("&players={}", encode(&self.players.join(","))));
+        }
+        url
+    }
+}
+
+/// Download the games matching `query`, or take them from the cache, giving the path of the BPGN
+/// file. An interrupted download is resumed.
+pub fn download(query: &Query) -> Result<PathBuf, String> {
+    let name = format!("bughousedb-{}-{}-{}.bpgn",
+        query.from.as_ref().map(String::as_str).unwrap_or("start"),
+        query.to.as_ref().map(String::as_str).unwrap_or("now"),
+        query.players.join("+"));
+    cached_url(&name, &query.url())
+}
+
+fn parse_date(date: &str) -> Result<Option<String>, String> {
+    let date = date.trim();
+    if date.is_empty() {
+        return Ok(None);
+    }
+
+    let parts: Vec<&str> = date.split('-').collect();
+    let valid = parts.len() == 3 &&
+        parts.iter().zip(&[4, 2, 2]).all(|(part, &length)| part.len() == length && part.bytes().all(|byte| byte.is_ascii_digit()));
+    if valid {
+        Ok(Some(date.to_string()))
+    }
+    else {
+        Err(format!("Invalid date {}, expected YYYY-MM-DD", date))
+    }
+}
@@ -0,0 +1,351 @@
+/*
+ * Bughouse games played live on chess.com, by game id or by player.
+ *
+ * The games of a player are listed in their monthly archives, from the published API.
+ *
+ * The page https://www.chess.com/live/game/<ID> loads the game from a JSON callback, which also
+ * gives the id of the partner's game. The moves are in the compact encoding of chess.com, two
+ * characters per move, and the clocks are in tenths of seconds.
+ *
+ * Since a game only has the moves of its board, a piece dropped without being in the pocket came
+ * from the partner: the game is then cut there and continued from a FEN position where the
+ * player has the piece, so that each part can be imported on its own.
+ */
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use shakmaty::{
+    fen,
+    Move,
+    Position,
+    position::Bughouse,
+    Role,
+    san::San,
+    Setup,
+    Square,
+    uci::Uci,
+};
+
+use crate::partner;
+use super::{cached_partial, encode, fetch, Partial};
+
+const ARCHIVES_URL: &str = "https://api.chess.com/pub/player";
+const CALLBACK_URL: &str = "https://www.chess.com/callback/live/game";
+const GAME_URL: &str = "https://www.chess.com/live/game";
+// Downloading more games at once would be abusive.
+const MAX_GAMES: u64 = 1000;
+// Characters of the move encoding: the index of a character is a square, or a promotion or a drop.
+const MOVE_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!?{~}(^)[_]@#$,./&-*++=";
+const MOVE_ROLES: [Role; 6] = [Role::Queen, Role::Knight, Role::Rook, Role::Bishop, Role::King, Role::Pawn];
+
+#[derive(Deserialize)]
+struct Archive {
+    games: Vec<ArchivedGame>,
+}
+
+#[derive(Deserialize)]
+struct ArchivedGame {
+    #[serde(default)]
+    rules: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct Archives {
+    /// URLs of the monthly archives, the last one being the current month.
+    archives: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Callback {
+    game: LiveGame,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct LiveGame {
+    move_list: String,
+    /// Clock of the player after each move, in tenths of seconds, separated by commas.
+    move_timestamps: Option<String>,
+    partner_game_id: Option<u64>,
+    pgn_headers: BTreeMap<String, serde_json::Value>,
+}
+
+impl LiveGame {
+    fn header(&self, name: &str) -> String {
+        match self.pgn_headers.get(name) {
+            Some(serde_json::Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => "?".to_string(),
+        }
+    }
+
+    fn is_bughouse(&self) -> bool {
+        self.header("Variant").to_lowercase().contains("bughouse")
+    }
+}
+
+/// Parse a game id or a range of ids, like "1234-1250".
+pub fn parse_ids(text: &str) -> Result<RangeInclusive<u64>, String> {
+    let parse_id = |id: &str| id.trim().parse::<u64>().map_err(|_| format!("Invalid game id {}", id.trim()));
+    let ids =
+        match text.find('-') {
+            Some(index) => parse_id(&text[..index])?..=parse_id(&text[index + 1..])?,
+            None => {
+                let id = parse_id(text)?;
+                id..=id
+            },
+        };
+    if ids.is_empty() {
+        return Err("The first game id is after the last one".to_string());
+    }
+    if ids.end() - ids.start() >= MAX_GAMES {
+        return Err(format!("Cannot download more than {} games at once", MAX_GAMES));
+    }
+    Ok(ids)
+}
+
+/// Download the bughouse games of `ids`, with their partner games, or take them from the cache,
+/// giving the path of the PGN file. The other games are skipped.
+pub fn download(ids: RangeInclusive<u64>) -> Result<PathBuf, String> {
+    let name = format!("chesscom-{}-{}.pgn", ids.start(), ids.end());
+    cached_partial(&name, false, |partial| {
+        for id in ids {
+            add_game(id, partial);
+        }
+        if partial.is_empty() {
+            return Err("No bughouse game found".to_string());
+        }
+        Ok(())
+    })
+}
+
+/// Download the bughouse games of `username`, with their partner games, giving the path of one
+/// PGN file per month. The past months are taken from the cache when already downloaded.
+pub fn download_player(username: &str) -> Result<Vec<PathBuf>, String> {
+    let username = username.trim().to_lowercase();
+    let json = fetch(&format!("{}/{}/games/archives", ARCHIVES_URL, encode(&username)))?;
+    let archives: Archives = serde_json::from_str(&json)
+        .map_err(|error| format!("Invalid archive list: {}", error))?;
+    let mut paths = vec![];
+    for (index, url) in archives.archives.iter().enumerate() {
+        // The URL ends with the year and the month, e.g. ".../games/2020/05".
+        let mut parts = url.rsplit('/');
+        let month = parts.next().unwrap_or("");
+        let year = parts.next().unwrap_or("");
+        let name = format!("chesscom-{}-{}-{}.pgn", username, year, month);
+        let is_current_month = index + 1 == archives.archives.len();
+        match cached_partial(&name, is_current_month, |partial| download_archive(url, partial)) {
+            Ok(path) => paths.push(path),
+            Err(error) => eprintln!("Skipping archive {}: {}", url, error),
+        }
+    }
+    if paths.is_empty() {
+        return Err(format!("No bughouse game found for {}", username));
+    }
+    Ok(paths)
+}
+
+/// Download the bughouse games of a monthly archive in PGN.
+fn download_archive(url: &str, partial: &mut Partial) -> Result<(), String> {
+    let json = fetch(url)?;
+    let archive: Archive = serde_json::from_str(&json)
+        .map_err(|error| format!("Invalid archive: {}", error))?;
+    let ids = archive.games.iter()
+        .filter(|game| game.rules == "bughouse")
+        // The URL of a game ends with its id.
+        .filter_map(|game| game.url.rsplit('/').next()?.parse::<u64>().ok());
+    for id in ids {
+        add_game(id, partial);
+    }
+    if partial.is_empty() {
+        return Err("no bughouse game".to_string());
+    }
+    Ok(())
+}
+
+/// Download the game `id` with its partner game, unless already done. A game which cannot be
+/// downloaded is skipped: it is only tried again when resuming a download interrupted before the
+/// end, since the finished file is then taken from the cache.
+fn add_game(id: u64, partial: &mut Partial) {
+    // The partner games are downloaded with the games.
+    if partial.is_done(id) {
+        return;
+    }
+
+    let result = game_pgn(id).and_then(|(ids, pgn)| partial.add(&ids, &pgn));
+    if let Err(error) = result {
+        eprintln!("Skipping chess.com game {}: {}", id, error);
+    }
+}
+
+fn fetch_game(id: u64) -> Result<LiveGame, String> {
+    let json = fetch(&format!("{}/{}", CALLBACK_URL, id))?;
+    let callback: Callback = serde_json::from_str(&json)
+        .map_err(|error| format!("Invalid game data: {}", error))?;
+    Ok(callback.game)
+}
+
+/// PGN of the game `id` and of its partner game, with their ids. A game which is not bughouse
+/// gives no PGN.
+fn game_pgn(id: u64) -> Result<(Vec<u64>, String), String> {
+    let game = fetch_game(id)?;
+    if !game.is_bughouse() {
+        return Ok((vec![id], String::new()));
+    }
+
+    let board = Board::replay(&game)?;
+    let partner_id =
+        match game.partner_game_id {
+            Some(partner_id) => partner_id,
+            None => return Ok((vec![id], to_pgn(id, &game, &board, None))),
+        };
+    let partner_game = fetch_game(partner_id)?;
+    let partner_board = Board::replay(&partner_game)?;
+    let mut pgn = to_pgn(id, &game, &board, Some(&partner_board));
+    pgn.push_str(&to_pgn(partner_id, &partner_game, &partner_board, Some(&board)));
+    Ok((vec![id, partner_id], pgn))
+}
+
+/// Positions of one board through a game.
+struct Board {
+    moves: Vec<Move>,
+    /// Position before each move, with the pieces received from the partner to drop it.
+    positions: Vec<Bughouse>,
+    /// Whether the player received a piece from the partner before each move.
+    received: Vec<bool>,
+}
+
+impl Board {
+    fn replay(game: &LiveGame) -> Result<Self, String> {
+        let mut board = Self {
+            moves: vec![],
+            positions: vec![],
+            received: vec![],
+        };
+        let mut position = Bughouse::default();
+        for uci in decode_moves(&game.move_list)? {
+            let mut received = false;
+            if let Uci::Put { role, .. } = uci {
+                if uci.to_move(&position).is_err() {
+                    position = partner::give_pieces(&position, position.turn(), &[role])?;
+                    received = true;
+                }
+            }
+            let mov = uci.to_move(&position).map_err(|_| format!("Illegal move {}", uci))?;
+            board.positions.push(position.clone());
+            board.received.push(received);
+            position.play_unchecked(&mov);
+            board.moves.push(mov);
+        }
+        Ok(board)
+    }
+
+    /// Position of the board at the same move number as the position at `index` of another
+    /// board. The boards are not synchronized by time, so this is only an estimate.
+    fn position_at(&self, index: usize) -> Bughouse {
+        match self.positions.get(index) {
+            Some(position) => position.clone(),
+            None => {
+                let mut position = self.positions.last().cloned().unwrap_or_default();
+                if let Some(mov) = self.moves.last() {
+                    position.play_unchecked(mov);
+                }
+                position
+            },
+        }
+    }
+}
+
+/// Convert a game to PGN, with a new part every time the player receives a piece from the partner.
+fn to_pgn(id: u64, game: &LiveGame, board: &Board, partner: Option<&Board>) -> String {
+    let clocks: Vec<Option<f32>> = game.move_timestamps.as_ref()
+        .map(|timestamps| timestamps.split(',')
+            .map(|tenths| tenths.trim().parse::<f32>().ok().map(|tenths| tenths / 10.0))
+            .collect())
+        .unwrap_or_default();
+    let mut pgn = String::new();
+    for (index, mov) in board.moves.iter().enumerate() {
+        let position = &board.positions[index];
+        let new_part = index == 0 || board.received[index];
+        if new_part {
+            if index > 0 {
+                pgn.push_str("*\n\n");
+            }
+            pgn.push_str(&format!("[Event \"chess.com bughouse\"]\n[Site \"{}/{}\"]\n", GAME_URL, id));
+            pgn.push_str(&format!("[White \"{}\"]\n[Black \"{}\"]\n", game.header("White"), game.header("Black")));
+            let mut fen = fen::fen(position);
+            if let Some(partner) = partner {
+                fen = format!("{} | {}", fen, fen::fen(&partner.position_at(index)));
+            }
+            pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n\n", fen));
+        }
+
+        if position.turn().is_white() {
+            pgn.push_str(&format!("{}. ", position.fullmoves()));
+        }
+        else if new_part {
+            pgn.push_str(&format!("{}... ", position.fullmoves()));
+        }
+        pgn.push_str(&San::from_move(position, mov).to_string());
+        if let Some(Some(clock)) = clocks.get(index) {
+            pgn.push_str(&format!(" {{{:.1}}}", clock));
+        }
+        pgn.push(' ');
+    }
+    if !board.moves.is_empty() {
+        pgn.push_str("*\n\n");
+    }
+    pgn
+}
+
+/// Decode the moves of chess.com: the first character of a move is the origin square, or the
+/// piece dropped, and the second is the destination square, or the promotion with the direction
+/// of the pawn.
+fn decode_moves(move_list: &str) -> Result<Vec<Uci>, String> {
+    let indexes: Vec<usize> = move_list.bytes()
+        .map(|byte| MOVE_ALPHABET.iter().position(|&character| character == byte))
+        .collect::<Option<_>>()
+        .ok_or("Invalid move list")?;
+    if indexes.len() % 2 != 0 {
+        return Err("Invalid move list".to_string());
+    }
+
+    let mut moves = vec![];
+    for chunk in indexes.chunks(2) {
+        let (from, to) = (chunk[0] as i32, chunk[1] as i32);
+        let (to, promotion) =
+            if to > 63 {
+                let role = MOVE_ROLES.get(((to - 64) / 3) as usize).ok_or("Invalid promotion")?;
+                let rank_offset = if from < 16 { -8 } else { 8 };
+                (from + rank_offset + (to - 1) % 3 - 1, Some(*role))
+            }
+            else {
+                (to, None)
+            };
+        if to < 0 || to > 63 {
+            return Err("Invalid move list".to_string());
+        }
+        let to = Square::new(to as u32);
+        let uci =
+            if from > 75 {
+                let role = MOVE_ROLES.get((from - 79) as usize).ok_or("Invalid drop")?;
+                Uci::Put {
+                    role: *role,
+                    to,
+                }
+            }
+            else {
+                Uci::Normal {
+                    from: Square::new(from as u32),
+                    to,
+                    promotion,
+                }
+            };
+        moves.push(uci);
+    }
+    Ok(moves)
+}
@@ -0,0 +1,32 @@
+/*
+ * Crazyhouse studies of Lichess, exported in PGN.
+ *
+ * A crazyhouse position is a bughouse position without partner board, so the chapters starting
+ * from a FEN position are imported as puzzles like the local PGN files.
+ */
+
+use std::path::PathBuf;
+
+use super::{cached, fetch};
+
+const STUDY_URL: &str = "https://lichess.org/api/study";
+
+/// Parse a study id, or the URL of a study or of one of its chapters.
+pub fn parse_study_id(text: &str) -> Result<String, String> {
+    let text = text.trim().trim_end_matches('/');
+    let id =
+        match text.find("/study/") {
+            Some(index) => text[index + "/study/".len()..].split('/').next().unwrap_or(""),
+            None => text,
+        };
+    if id.is_empty() || !id.chars().all(|character| character.is_ascii_alphanumeric()) {
+        return Err(format!("Invalid Lichess study {}", text));
+    }
+    Ok(id.to_string())
+}
+
+/// Download the chapters of the study `id`, or take them from the cache, giving the path of the
+/// PGN file.
+pub fn download_study(id: &str) -> Result<PathBuf, String> {
+    cached(&format!("lichess-study-{}.pgn", id), || fetch(&format!("{}/{}.pgn", STUDY_URL, id)))
+}
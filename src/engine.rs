@@ -0,0 +1,511 @@
+/*
+ * Chess engines used to analyze bughouse positions.
+ *
+ * Both UCI engines (Fairy-Stockfish) and xboard/CECP engines (Sjeng, Sunsetter) are supported
+ * behind the Engine trait: the rest of the application should not care which one is running.
+ */
+
+mod cache;
+mod discovery;
+mod uci;
+mod xboard;
+
+use std::env;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    Move,
+    Position,
+    position::Bughouse,
+    Setup,
+    san::San,
+    uci::Uci,
+};
+
+use crate::material::material_balance;
+
+pub use self::cache::{Cache, CachedEngine, DEFAULT_CACHE_SIZE, SharedCache};
+pub use self::discovery::{DiscoveredEngine, discover};
+pub use self::uci::UciEngine;
+pub use self::xboard::XboardEngine;
+
+const DEFAULT_ENGINE: &str = "fairy-stockfish";
+pub const ANALYSIS_DEPTH: u32 = 16;
+// Depth used when a search has no limit at all.
+const DEFAULT_DEPTH: u32 = 12;
+// Depth of the shallow search checking that won material is kept.
+const QUIESCENCE_DEPTH: u32 = 6;
+// Plies of the principal variation followed to see if the material is regained.
+const QUIESCENCE_PLIES: usize = 4;
+pub const VALIDATION_DEPTH: u32 = 14;
+// Minimum evaluation, in centipawns, for a position to be considered won.
+pub const WINNING_SCORE: i32 = 250;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Score {
+    Centipawns(i32),
+    Mate(i32),
+}
+
+impl Score {
+    /// The same score, from the point of view of the other side.
+    pub fn negate(self) -> Self {
+        match self {
+            Score::Centipawns(centipawns) => Score::Centipawns(-centipawns),
+            Score::Mate(moves) => Score::Mate(-moves),
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Score::Centipawns(centipawns) => write!(formatter, "{:+.2}", f64::from(centipawns) / 100.0),
+            Score::Mate(moves) => write!(formatter, "#{}", moves),
+        }
+    }
+}
+
+pub trait Engine {
+    fn start(&mut self) -> io::Result<()>;
+    fn set_position(&mut self, position: &Bughouse) -> io::Result<()>;
+    /// Options like the hash size, which apply to every later search.
+    fn set_options(&mut self, options: &EngineOptions) -> io::Result<()>;
+    /// Search the current position, blocking until the engine gives its best move.
+    fn search(&mut self, limits: &SearchLimits) -> io::Result<()>;
+    fn best_move(&self) -> Option<&Uci>;
+    /// Lines of the last search, best first. There's only one line unless multi-PV is enabled.
+    fn lines(&self) -> &[PvLine];
+    /// Number of lines to search, if supported by the engine.
+    fn set_multi_pv(&mut self, count: usize) -> io::Result<()>;
+
+    /// Principal variation of the last search, starting with the best move.
+    fn principal_variation(&self) -> &[Uci] {
+        self.lines().first()
+            .map(|line| line.pv.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Score of the last search, from the point of view of the side to move.
+    fn score(&self) -> Option<Score> {
+        self.lines().first()
+            .and_then(|line| line.score)
+    }
+
+    /// Handle that can be used from another thread to make the engine stop searching.
+    fn stop_handle(&self) -> Option<StopHandle>;
+    /// Whether the last search was stopped before reaching its limits.
+    fn was_stopped(&self) -> bool;
+}
+
+/// Ask the engine to stop the current search: it will then give its best move so far.
+#[derive(Clone)]
+pub struct StopHandle {
+    commands: &'static [&'static str],
+    stdin: Arc<Mutex<ChildStdin>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Ok(mut stdin) = self.stdin.lock() {
+            for command in self.commands {
+                let _ = writeln!(stdin, "{}", command);
+            }
+            let _ = stdin.flush();
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PvLine {
+    pub pv: Vec<Uci>,
+    pub score: Option<Score>,
+}
+
+/// The search stops as soon as one of the limits is reached.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    /// Time per position, in milliseconds.
+    pub movetime: Option<u32>,
+    pub nodes: Option<u64>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u32) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Self::default()
+        }
+    }
+
+    /// Depth to search to, falling back to a default depth when there's no limit at all.
+    pub fn depth_or_default(&self) -> Option<u32> {
+        if self.depth.is_none() && self.movetime.is_none() && self.nodes.is_none() {
+            Some(DEFAULT_DEPTH)
+        }
+        else {
+            self.depth
+        }
+    }
+}
+
+/// None means the engine's default.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct EngineOptions {
+    /// Size of the hash table, in MB.
+    pub hash: Option<u32>,
+    pub threads: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Protocol {
+    Uci,
+    Xboard,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Protocol::Uci => write!(formatter, "UCI"),
+            Protocol::Xboard => write!(formatter, "xboard"),
+        }
+    }
+}
+
+/// Engine to run.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EngineConfig {
+    pub path: String,
+    pub protocol: Protocol,
+}
+
+impl fmt::Display for EngineConfig {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} ({})", self.path, self.protocol)
+    }
+}
+
+/// The engine named in the BUZZLE_ENGINE environment variable, or Fairy-Stockfish.
+/// BUZZLE_ENGINE_PROTOCOL can be set to "xboard" for CECP engines.
+pub fn config_from_env() -> EngineConfig {
+    let path = env::var("BUZZLE_ENGINE").unwrap_or_else(|_| DEFAULT_ENGINE.to_string());
+    let protocol =
+        match env::var("BUZZLE_ENGINE_PROTOCOL").as_ref().map(String::as_str) {
+            Ok("xboard") | Ok("cecp") => Protocol::Xboard,
+            _ => Protocol::Uci,
+        };
+    EngineConfig {
+        path,
+        protocol,
+    }
+}
+
+/// Create the engine, without starting it.
+pub fn new(config: &EngineConfig) -> Box<dyn Engine + Send> {
+    match config.protocol {
+        Protocol::Uci => Box::new(UciEngine::new(&config.path)),
+        Protocol::Xboard => Box::new(XboardEngine::new(&config.path)),
+    }
+}
+
+struct Process {
+    process: Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    stdout: BufReader<ChildStdout>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Process {
+    fn spawn(path: &str) -> io::Result<Self> {
+        let mut process = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = process.stdin.take().ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "no engine stdin"))?;
+        let stdout = process.stdout.take().ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "no engine stdout"))?;
+        Ok(Self {
+            process,
+            stdin: Arc::new(Mutex::new(stdin)),
+            stdout: BufReader::new(stdout),
+            stopped: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    fn stop_handle(&self, commands: &'static [&'static str]) -> StopHandle {
+        StopHandle {
+            commands,
+            stdin: self.stdin.clone(),
+            stopped: self.stopped.clone(),
+        }
+    }
+
+    /// Send the command starting a search.
+    fn start_search(&mut self, command: &str) -> io::Result<()> {
+        self.stopped.store(false, Ordering::SeqCst);
+        self.send(command)
+    }
+
+    fn was_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "engine exited"));
+        }
+        Ok(line)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        let mut stdin = self.stdin.lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "engine stdin poisoned"))?;
+        writeln!(stdin, "{}", command)?;
+        stdin.flush()
+    }
+
+    fn wait_for(&mut self, expected: &str) -> io::Result<()> {
+        while self.read_line()?.trim() != expected {
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.process.wait();
+    }
+}
+
+fn not_started() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "engine not started")
+}
+
+pub fn best_move(engine: &mut dyn Engine, position: &Bughouse, limits: &SearchLimits) -> io::Result<Move> {
+    engine.set_position(position)?;
+    engine.search(limits)?;
+    let uci = engine.best_move()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "engine gave no best move"))?;
+    uci.to_move(position)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "engine gave an illegal move"))
+}
+
+/// Convert the moves of a principal variation starting at `position` to SAN, stopping at the first
+/// illegal move.
+pub fn pv_to_san(position: &Bughouse, pv: &[Uci]) -> Vec<String> {
+    let mut position = position.clone();
+    let mut moves = vec![];
+    for uci in pv {
+        match uci.to_move(&position) {
+            Ok(mov) => {
+                moves.push(San::from_move(&position, &mov).to_string());
+                position.play_unchecked(&mov);
+            },
+            Err(_) => break,
+        }
+    }
+    moves
+}
+
+/// Check that `mov` delays the mate as long as possible.
+pub fn survives_longest(engine: &mut dyn Engine, position: &Bughouse, mov: &Move, limits: &SearchLimits) -> io::Result<bool> {
+    engine.set_position(position)?;
+    engine.search(limits)?;
+    let longest =
+        match engine.score() {
+            Some(Score::Mate(moves)) if moves < 0 => -moves,
+            _ => return Ok(false),
+        };
+
+    let mut after_move = position.clone();
+    after_move.play_unchecked(mov);
+    engine.set_position(&after_move)?;
+    engine.search(limits)?;
+    Ok(engine.score() == Some(Score::Mate(longest)))
+}
+
+/// Check that the opponent has no forced mate after `mov`.
+pub fn avoids_mate(engine: &mut dyn Engine, position: &Bughouse, mov: &Move, limits: &SearchLimits) -> io::Result<bool> {
+    let mut after_move = position.clone();
+    after_move.play_unchecked(mov);
+    if after_move.is_checkmate() {
+        return Ok(true);
+    }
+
+    engine.set_position(&after_move)?;
+    engine.search(limits)?;
+    match engine.score() {
+        Some(Score::Mate(moves)) if moves > 0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Check that `mov` keeps a forced mate, faster than before the move.
+pub fn keeps_mate(engine: &mut dyn Engine, position: &Bughouse, mov: &Move, limits: &SearchLimits) -> io::Result<bool> {
+    engine.set_position(position)?;
+    engine.search(limits)?;
+    let mate =
+        match engine.score() {
+            Some(Score::Mate(moves)) if moves > 0 => moves,
+            _ => return Ok(false),
+        };
+
+    let mut after_move = position.clone();
+    after_move.play_unchecked(mov);
+    if after_move.is_checkmate() {
+        return Ok(true);
+    }
+    engine.set_position(&after_move)?;
+    engine.search(limits)?;
+    // The score is from the point of view of the opponent, who is to move.
+    match engine.score() {
+        Some(Score::Mate(moves)) => Ok(moves < 0 && -moves < mate),
+        _ => Ok(false),
+    }
+}
+
+/// Check that `mov` still forces mate, when `keeps_mate` failed, i.e. that the mate is slower.
+pub fn forces_slower_mate(engine: &mut dyn Engine, position: &Bughouse, mov: &Move, limits: &SearchLimits) -> io::Result<bool> {
+    let mut after_move = position.clone();
+    after_move.play_unchecked(mov);
+    if after_move.is_checkmate() {
+        return Ok(false);
+    }
+
+    engine.set_position(&after_move)?;
+    engine.search(limits)?;
+    // The score is from the point of view of the opponent, who is to move.
+    match engine.score() {
+        Some(Score::Mate(moves)) => Ok(moves < 0),
+        _ => Ok(false),
+    }
+}
+
+/// Check that `mov` wins at least `gain` pawns or keeps a winning position.
+pub fn keeps_material_win(engine: &mut dyn Engine, position: &Bughouse, mov: &Move, gain: i32, limits: &SearchLimits)
+    -> io::Result<bool>
+{
+    let player = position.turn();
+    let mut after_move = position.clone();
+    after_move.play_unchecked(mov);
+    if after_move.is_checkmate() ||
+        material_balance(&after_move, player) - material_balance(position, player) >= gain
+    {
+        return Ok(true);
+    }
+
+    engine.set_position(&after_move)?;
+    engine.search(limits)?;
+    match engine.score() {
+        Some(Score::Centipawns(centipawns)) => Ok(-centipawns >= WINNING_SCORE),
+        Some(Score::Mate(moves)) => Ok(moves < 0),
+        None => Ok(false),
+    }
+}
+
+/// Check that the material won by `moves` is not regained right away, e.g. by a drop from the
+/// opponent's pocket forking two pieces, with a shallow search after the solution.
+pub fn keeps_won_material(engine: &mut dyn Engine, position: &Bughouse, moves: &[Move], gain: i32) -> io::Result<bool> {
+    let player = position.turn();
+    let target_balance = material_balance(position, player) + gain;
+    let mut position = position.clone();
+    for mov in moves {
+        position.play_unchecked(mov);
+    }
+    if position.is_checkmate() {
+        return Ok(true);
+    }
+    if position.is_game_over() {
+        return Ok(false);
+    }
+
+    engine.set_position(&position)?;
+    engine.search(&SearchLimits::depth(QUIESCENCE_DEPTH))?;
+    // The score is from the point of view of the opponent, who is to move.
+    let winning =
+        match engine.score() {
+            Some(Score::Centipawns(centipawns)) => -centipawns >= WINNING_SCORE,
+            Some(Score::Mate(moves)) => moves < 0,
+            None => false,
+        };
+    if !winning {
+        return Ok(false);
+    }
+
+    let pv = engine.principal_variation().to_vec();
+    let mut dropped = false;
+    for uci in pv.iter().take(QUIESCENCE_PLIES) {
+        let mov =
+            match uci.to_move(&position) {
+                Ok(mov) => mov,
+                Err(_) => break,
+            };
+        if position.turn() != player {
+            if let Move::Put { .. } = mov {
+                dropped = true;
+            }
+        }
+        position.play_unchecked(&mov);
+        if dropped && position.turn() == player && material_balance(&position, player) < target_balance {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Check that every legal move loses, i.e. that the opponent has a forced mate after it.
+pub fn all_moves_lose(engine: &mut dyn Engine, position: &Bughouse, limits: &SearchLimits) -> io::Result<bool> {
+    let legals = position.legals();
+    if legals.is_empty() {
+        return Ok(false);
+    }
+
+    for mov in &legals {
+        let mut after_move = position.clone();
+        after_move.play_unchecked(mov);
+        if after_move.is_checkmate() {
+            return Ok(false);
+        }
+
+        engine.set_position(&after_move)?;
+        engine.search(limits)?;
+        match engine.score() {
+            Some(Score::Mate(moves)) if moves > 0 => (),
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+/// Check that every move of the player in `moves` keeps a forced mate, i.e. that the
+/// opponent cannot refute the puzzle.
+pub fn is_forced(engine: &mut dyn Engine, position: &Bughouse, moves: &[Move], limits: &SearchLimits) -> io::Result<bool> {
+    let mut position = position.clone();
+    for (index, mov) in moves.iter().enumerate() {
+        position.play_unchecked(mov);
+        if index % 2 == 1 || position.is_checkmate() {
+            continue;
+        }
+
+        engine.set_position(&position)?;
+        engine.search(limits)?;
+        // The score is from the point of view of the opponent, who is to move.
+        match engine.score() {
+            Some(Score::Mate(moves)) if moves < 0 => (),
+            _ => return Ok(false),
+        }
+    }
+    Ok(position.is_checkmate())
+}
@@ -0,0 +1,248 @@
+/*
+ * Cache of the engine searches, saved to disk, so that running the generation or the validation
+ * again on overlapping games doesn't redo the same searches.
+ *
+ * Searches are identified by a hash of the position, the search limits and the number of lines.
+ * The position is hashed with its Zobrist hash, which includes the pockets, and the rest with
+ * FNV-1a: unlike the default hasher of the standard library, they are stable between runs.
+ */
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    position::Bughouse,
+    Setup,
+    uci::Uci,
+};
+
+use crate::hash;
+use crate::zobrist;
+
+use super::{Engine, EngineOptions, PvLine, Score, SearchLimits, StopHandle};
+
+pub const DEFAULT_CACHE_SIZE: usize = 100_000;
+
+pub type SharedCache = Arc<Mutex<Cache>>;
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Cache {
+    /// Maximum number of searches kept.
+    #[serde(skip)]
+    capacity: usize,
+    /// Used to know when each entry was last used.
+    clock: u64,
+    entries: HashMap<u64, Entry>,
+    /// Engine and settings which made the searches: the cache is cleared when they change.
+    fingerprint: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    best_move: Option<String>,
+    last_used: u64,
+    lines: Vec<Line>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Line {
+    pv: Vec<String>,
+    score: Option<Score>,
+}
+
+impl Cache {
+    pub fn load(fingerprint: &str, capacity: usize) -> Self {
+        let cache =
+            match path().map(File::open) {
+                Some(Ok(file)) =>
+                    serde_json::from_reader(file).unwrap_or_else(|error| {
+                        eprintln!("Cannot parse engine cache: {}", error);
+                        Self::default()
+                    }),
+                Some(Err(ref error)) if error.kind() == io::ErrorKind::NotFound => Self::default(),
+                Some(Err(error)) => {
+                    eprintln!("Cannot open engine cache: {}", error);
+                    Self::default()
+                },
+                None => Self::default(),
+            };
+        let mut cache = Self {
+            capacity,
+            ..cache
+        };
+        cache.invalidate(fingerprint);
+        cache.evict();
+        cache
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cache directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer(file, self)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+    }
+
+    /// Clear the cache if the searches were made with another engine or other settings.
+    pub fn invalidate(&mut self, fingerprint: &str) {
+        if self.fingerprint != fingerprint {
+            self.entries.clear();
+            self.fingerprint = fingerprint.to_string();
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn get(&mut self, key: u64) -> Option<(Option<Uci>, Vec<PvLine>)> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&key)?;
+        entry.last_used = self.clock;
+        let best_move = entry.best_move.as_ref()
+            .and_then(|mov| Uci::from_ascii(mov.as_bytes()).ok());
+        let lines = entry.lines.iter()
+            .map(|line| PvLine {
+                pv: line.pv.iter()
+                    .filter_map(|mov| Uci::from_ascii(mov.as_bytes()).ok())
+                    .collect(),
+                score: line.score,
+            })
+            .collect();
+        Some((best_move, lines))
+    }
+
+    fn insert(&mut self, key: u64, best_move: Option<&Uci>, lines: &[PvLine]) {
+        self.clock += 1;
+        self.entries.insert(key, Entry {
+            best_move: best_move.map(Uci::to_string),
+            last_used: self.clock,
+            lines: lines.iter()
+                .map(|line| Line {
+                    pv: line.pv.iter().map(Uci::to_string).collect(),
+                    score: line.score,
+                })
+                .collect(),
+        });
+        self.evict();
+    }
+
+    /// Remove the least recently used entries, to go back to 90% of the capacity when it is
+    /// exceeded.
+    fn evict(&mut self) {
+        if self.entries.len() <= self.capacity {
+            return;
+        }
+
+        let mut last_used: Vec<u64> = self.entries.values().map(|entry| entry.last_used).collect();
+        last_used.sort_unstable();
+        let removed = self.entries.len() - self.capacity * 9 / 10;
+        let threshold = last_used[removed - 1];
+        self.entries.retain(|_, entry| entry.last_used > threshold);
+    }
+}
+
+/// Engine answering from the cache when the same search was already made.
+pub struct CachedEngine {
+    best_move: Option<Uci>,
+    cache: SharedCache,
+    engine: Box<dyn Engine + Send>,
+    lines: Vec<PvLine>,
+    multi_pv: usize,
+    // The position is only sent to the engine if the search is not in the cache.
+    position: Option<Bughouse>,
+}
+
+impl CachedEngine {
+    pub fn new(engine: Box<dyn Engine + Send>, cache: SharedCache) -> Self {
+        Self {
+            best_move: None,
+            cache,
+            engine,
+            lines: vec![],
+            multi_pv: 1,
+            position: None,
+        }
+    }
+}
+
+impl Engine for CachedEngine {
+    fn start(&mut self) -> io::Result<()> {
+        self.engine.start()
+    }
+
+    fn set_position(&mut self, position: &Bughouse) -> io::Result<()> {
+        self.position = Some(position.clone());
+        Ok(())
+    }
+
+    fn set_options(&mut self, options: &EngineOptions) -> io::Result<()> {
+        self.engine.set_options(options)
+    }
+
+    fn search(&mut self, limits: &SearchLimits) -> io::Result<()> {
+        let position = self.position.as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no position to search"))?;
+        let key = key(position, limits, self.multi_pv);
+        let cached = self.cache.lock().ok().and_then(|mut cache| cache.get(key));
+        if let Some((best_move, lines)) = cached {
+            self.best_move = best_move;
+            self.lines = lines;
+            return Ok(());
+        }
+
+        self.engine.set_position(position)?;
+        self.engine.search(limits)?;
+        self.best_move = self.engine.best_move().cloned();
+        self.lines = self.engine.lines().to_vec();
+        // A stopped search is incomplete.
+        if !self.engine.was_stopped() {
+            if let Ok(mut cache) = self.cache.lock() {
+                cache.insert(key, self.best_move.as_ref(), &self.lines);
+            }
+        }
+        Ok(())
+    }
+
+    fn best_move(&self) -> Option<&Uci> {
+        self.best_move.as_ref()
+    }
+
+    fn lines(&self) -> &[PvLine] {
+        &self.lines
+    }
+
+    fn set_multi_pv(&mut self, count: usize) -> io::Result<()> {
+        self.multi_pv = count;
+        self.engine.set_multi_pv(count)
+    }
+
+    fn stop_handle(&self) -> Option<StopHandle> {
+        self.engine.stop_handle()
+    }
+
+    fn was_stopped(&self) -> bool {
+        self.engine.was_stopped()
+    }
+}
+
+/// Key of the search of `position`. The castling rights and the en passant square are not part of
+/// the Zobrist hash, so they are hashed with the limits.
+fn key(position: &Bughouse, limits: &SearchLimits, multi_pv: usize) -> u64 {
+    let search = format!("{} {:?} {:?} {:?} {:?} {}", u64::from(position.castling_rights()), position.ep_square(),
+        limits.depth, limits.movetime, limits.nodes, multi_pv);
+    zobrist::hash(position) ^ hash::fnv1a(search.as_bytes())
+}
+
+fn path() -> Option<PathBuf> {
+    glib::get_user_cache_dir()
+        .map(|dir| dir.join("buzzle").join("engine-cache.json"))
+}
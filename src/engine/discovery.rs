@@ -0,0 +1,204 @@
+/*
+ * Find the engines installed on this machine which can play bughouse or, at least, crazyhouse.
+ *
+ * The executables with the name of a known engine, in the PATH and in the common install
+ * locations, are started to ask which protocol and variants they support. Engines which don't
+ * answer the handshake quickly are skipped.
+ */
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{EngineConfig, Protocol, uci, xboard};
+
+const ENGINE_NAMES: &[&str] = &["fairy-stockfish", "imortal", "pulsar", "sjeng", "stockfish", "sunsetter", "tjchess"];
+const INSTALL_DIRS: &[&str] = &["/usr/games", "/usr/local/games", "/usr/local/bin", "/opt/local/bin", "/snap/bin"];
+// Time given to an engine to answer the handshake.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// These engines are asked for xboard first, since they don't speak UCI.
+const XBOARD_ENGINE_NAMES: &[&str] = &["imortal", "pulsar", "sjeng", "sunsetter", "tjchess"];
+
+#[derive(Clone, Debug)]
+pub struct DiscoveredEngine {
+    /// Whether the engine only plays crazyhouse, which it is used for instead of bughouse.
+    pub crazyhouse_only: bool,
+    pub config: EngineConfig,
+}
+
+impl DiscoveredEngine {
+    pub fn label(&self) -> String {
+        let mut label = self.config.to_string();
+        if self.crazyhouse_only {
+            label.push_str(", crazyhouse only");
+        }
+        label
+    }
+}
+
+/// Probe the candidate executables. This takes a few seconds: it should not run in the GUI
+/// thread.
+pub fn discover() -> Vec<DiscoveredEngine> {
+    candidates().iter()
+        .filter_map(|path| probe(path))
+        .collect()
+}
+
+fn candidates() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default();
+    dirs.extend(INSTALL_DIRS.iter().map(PathBuf::from));
+    dirs.extend(glib::get_home_dir().map(|home| home.join(".local").join("bin")));
+
+    let mut candidates = vec![];
+    // The same executable can be in several directories through symbolic links.
+    let mut seen = HashSet::new();
+    for dir in dirs {
+        let entries =
+            match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            let path = entry.path();
+            if ENGINE_NAMES.iter().any(|engine| name.starts_with(engine)) && is_executable(&path) {
+                let real_path = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if seen.insert(real_path) {
+                    candidates.push(path);
+                }
+            }
+        }
+    }
+    candidates
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn probe(path: &Path) -> Option<DiscoveredEngine> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    let protocols =
+        if XBOARD_ENGINE_NAMES.iter().any(|engine| name.starts_with(engine)) {
+            [Protocol::Xboard, Protocol::Uci]
+        }
+        else {
+            [Protocol::Uci, Protocol::Xboard]
+        };
+    protocols.iter()
+        .filter_map(|&protocol| probe_protocol(path, protocol))
+        .next()
+}
+
+fn probe_protocol(path: &Path, protocol: Protocol) -> Option<DiscoveredEngine> {
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let variants =
+        match (child.stdin.take(), child.stdout.take()) {
+            (Some(mut stdin), Some(stdout)) => handshake(&mut stdin, &read_lines(stdout), protocol),
+            _ => None,
+        };
+    let _ = child.kill();
+    let _ = child.wait();
+
+    let variants = variants?;
+    let bughouse = variants.iter().any(|variant| variant == "bughouse");
+    let crazyhouse = variants.iter().any(|variant| variant == "crazyhouse");
+    if !bughouse && !crazyhouse {
+        return None;
+    }
+    Some(DiscoveredEngine {
+        crazyhouse_only: !bughouse,
+        config: EngineConfig {
+            path: path.to_string_lossy().into_owned(),
+            protocol,
+        },
+    })
+}
+
+/// Lines written by the engine, read on another thread so that they can be waited for with a
+/// timeout.
+fn read_lines(stdout: ChildStdout) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                },
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+/// Start the protocol and give the variants announced by the engine.
+fn handshake(stdin: &mut ChildStdin, lines: &Receiver<String>, protocol: Protocol) -> Option<Vec<String>> {
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    let next_line = || {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        lines.recv_timeout(timeout).ok()
+    };
+    let mut variants = vec![];
+    match protocol {
+        Protocol::Uci => {
+            writeln!(stdin, "uci").ok()?;
+            stdin.flush().ok()?;
+            loop {
+                let line = next_line()?;
+                let line = line.trim();
+                if line == "uciok" {
+                    return Some(variants);
+                }
+                if let Some(line_variants) = uci::parse_variants(line) {
+                    variants = line_variants;
+                }
+            }
+        },
+        Protocol::Xboard => {
+            writeln!(stdin, "xboard\nprotover 2").ok()?;
+            stdin.flush().ok()?;
+            loop {
+                let line = next_line()?;
+                let line = line.trim();
+                if !line.starts_with("feature ") {
+                    continue;
+                }
+
+                for (name, value) in xboard::parse_features(&line["feature ".len()..]) {
+                    if name == "variants" {
+                        variants = value.split(',').map(|variant| variant.trim().to_string()).collect();
+                    }
+                    else if name == "done" && value == "1" {
+                        return Some(variants);
+                    }
+                }
+            }
+        },
+    }
+}
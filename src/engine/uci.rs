@@ -0,0 +1,186 @@
+use std::io;
+
+use shakmaty::{
+    fen,
+    position::Bughouse,
+    uci::Uci,
+};
+
+use super::{Engine, EngineOptions, Process, PvLine, Score, SearchLimits, StopHandle, not_started};
+
+pub struct UciEngine {
+    best_move: Option<Uci>,
+    lines: Vec<PvLine>,
+    path: String,
+    process: Option<Process>,
+}
+
+impl UciEngine {
+    pub fn new(path: &str) -> Self {
+        Self {
+            best_move: None,
+            lines: vec![],
+            path: path.to_string(),
+            process: None,
+        }
+    }
+
+    fn process(&mut self) -> io::Result<&mut Process> {
+        self.process.as_mut().ok_or_else(not_started)
+    }
+}
+
+impl Engine for UciEngine {
+    fn start(&mut self) -> io::Result<()> {
+        let mut process = Process::spawn(&self.path)?;
+        process.send("uci")?;
+        let mut variants = vec![];
+        loop {
+            let line = process.read_line()?;
+            let line = line.trim();
+            if line == "uciok" {
+                break;
+            }
+            if let Some(line_variants) = parse_variants(line) {
+                variants = line_variants;
+            }
+        }
+        // Crazyhouse has the same drops: it's the next best thing when bughouse is not supported.
+        let has_variant = |name: &str| variants.iter().any(|variant| variant == name);
+        let variant =
+            if !has_variant("bughouse") && has_variant("crazyhouse") {
+                "crazyhouse"
+            }
+            else {
+                "bughouse"
+            };
+        process.send(&format!("setoption name UCI_Variant value {}", variant))?;
+        process.send("isready")?;
+        process.wait_for("readyok")?;
+        self.process = Some(process);
+        Ok(())
+    }
+
+    fn set_position(&mut self, position: &Bughouse) -> io::Result<()> {
+        self.process()?.send(&format!("position fen {}", fen::fen(position)))
+    }
+
+    fn set_options(&mut self, options: &EngineOptions) -> io::Result<()> {
+        let process = self.process()?;
+        if let Some(hash) = options.hash {
+            process.send(&format!("setoption name Hash value {}", hash))?;
+        }
+        if let Some(threads) = options.threads {
+            process.send(&format!("setoption name Threads value {}", threads))?;
+        }
+        process.send("isready")?;
+        process.wait_for("readyok")
+    }
+
+    fn search(&mut self, limits: &SearchLimits) -> io::Result<()> {
+        self.best_move = None;
+        self.lines.clear();
+        let mut command = "go".to_string();
+        if let Some(depth) = limits.depth_or_default() {
+            command.push_str(&format!(" depth {}", depth));
+        }
+        if let Some(movetime) = limits.movetime {
+            command.push_str(&format!(" movetime {}", movetime));
+        }
+        if let Some(nodes) = limits.nodes {
+            command.push_str(&format!(" nodes {}", nodes));
+        }
+        let process = self.process()?;
+        process.start_search(&command)?;
+        let mut lines: Vec<PvLine> = vec![];
+        let best_move = loop {
+            let line = process.read_line()?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("info") => {
+                    if let Some(score) = parse_score(&line) {
+                        let index = parse_multi_pv(&line).unwrap_or(1).max(1) - 1;
+                        if lines.len() <= index {
+                            lines.resize(index + 1, PvLine::default());
+                        }
+                        lines[index] = PvLine {
+                            pv: parse_pv(&line).unwrap_or_default(),
+                            score: Some(score),
+                        };
+                    }
+                },
+                Some("bestmove") => {
+                    break words.next()
+                        .and_then(|mov| Uci::from_ascii(mov.as_bytes()).ok());
+                },
+                _ => (),
+            }
+        };
+        self.best_move = best_move;
+        self.lines = lines;
+        Ok(())
+    }
+
+    fn best_move(&self) -> Option<&Uci> {
+        self.best_move.as_ref()
+    }
+
+    fn lines(&self) -> &[PvLine] {
+        &self.lines
+    }
+
+    fn set_multi_pv(&mut self, count: usize) -> io::Result<()> {
+        self.process()?.send(&format!("setoption name MultiPV value {}", count))
+    }
+
+    fn stop_handle(&self) -> Option<StopHandle> {
+        self.process.as_ref().map(|process| process.stop_handle(&["stop"]))
+    }
+
+    fn was_stopped(&self) -> bool {
+        self.process.as_ref().map(Process::was_stopped).unwrap_or(false)
+    }
+}
+
+fn parse_multi_pv(line: &str) -> Option<usize> {
+    line.split_whitespace()
+        .skip_while(|&word| word != "multipv")
+        .nth(1)?
+        .parse().ok()
+}
+
+fn parse_pv(line: &str) -> Option<Vec<Uci>> {
+    let mut words = line.split_whitespace().skip_while(|&word| word != "pv");
+    words.next()?;
+    Some(words
+        .filter_map(|mov| Uci::from_ascii(mov.as_bytes()).ok())
+        .collect())
+}
+
+fn parse_score(line: &str) -> Option<Score> {
+    let mut words = line.split_whitespace().skip_while(|&word| word != "score").skip(1);
+    let kind = words.next()?;
+    let value = words.next()?.parse().ok()?;
+    match kind {
+        "cp" => Some(Score::Centipawns(value)),
+        "mate" => Some(Score::Mate(value)),
+        _ => None,
+    }
+}
+
+/// Parse the variants of an option line like:
+/// option name UCI_Variant type combo default chess var chess var bughouse var crazyhouse
+pub fn parse_variants(line: &str) -> Option<Vec<String>> {
+    if !line.starts_with("option name UCI_Variant ") {
+        return None;
+    }
+
+    let mut variants = vec![];
+    let mut words = line.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "var" {
+            variants.extend(words.next().map(str::to_string));
+        }
+    }
+    Some(variants)
+}
@@ -0,0 +1,379 @@
+/*
+ * Adapter for the xboard/CECP protocol, spoken by Sjeng and Sunsetter.
+ *
+ * The engine must support protocol version 2: its features are negotiated when starting it. When
+ * the engine has the analyze and ping features, searches are done in analyze mode and stopped
+ * once a limit is reached; otherwise, the engine is asked to think and move with sd and st.
+ */
+
+use std::io;
+
+use shakmaty::{
+    fen,
+    MaterialSide,
+    position::Bughouse,
+    Setup,
+    uci::Uci,
+};
+
+use super::{DEFAULT_DEPTH, Engine, EngineOptions, Process, PvLine, Score, SearchLimits, StopHandle, not_started};
+
+// Engines report mates as scores close to this value.
+const MATE_SCORE: i32 = 100_000;
+// xboard limits stay set until changed, so these are sent to remove a limit.
+const MAX_DEPTH: u32 = 100;
+const MAX_SECONDS: u32 = 24 * 60 * 60;
+// Leave analyze mode and send a ping whose pong tells that the search is over.
+const STOP_ANALYSIS: &[&str] = &["exit", "ping 0"];
+const STOP_PONG: &str = "pong 0";
+// Make the engine move now.
+const STOP_THINKING: &[&str] = &["?"];
+
+/// Features announced by the engine, with the defaults of the protocol.
+struct Features {
+    analyze: bool,
+    ping: bool,
+    setboard: bool,
+    /// Supported variants, if announced.
+    variants: Option<Vec<String>>,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            analyze: true,
+            ping: false,
+            setboard: false,
+            variants: None,
+        }
+    }
+}
+
+/// A line of thinking output, of the form: ply score time nodes pv.
+struct Thinking {
+    depth: u32,
+    nodes: u64,
+    pv: Vec<Uci>,
+    score: Score,
+    /// In milliseconds.
+    time: u64,
+}
+
+pub struct XboardEngine {
+    best_move: Option<Uci>,
+    features: Features,
+    last_ping: u32,
+    lines: Vec<PvLine>,
+    path: String,
+    process: Option<Process>,
+}
+
+impl XboardEngine {
+    pub fn new(path: &str) -> Self {
+        Self {
+            best_move: None,
+            features: Features::default(),
+            last_ping: 0,
+            lines: vec![],
+            path: path.to_string(),
+            process: None,
+        }
+    }
+
+    fn process(&mut self) -> io::Result<&mut Process> {
+        self.process.as_mut().ok_or_else(not_started)
+    }
+
+    fn uses_analyze_mode(&self) -> bool {
+        self.features.analyze && self.features.ping
+    }
+
+    /// Search in analyze mode, until a limit is reached or the search is stopped.
+    fn analyze(&mut self, limits: &SearchLimits) -> io::Result<()> {
+        let ping = self.last_ping + 1;
+        let exit_ping = self.last_ping + 2;
+        self.last_ping += 2;
+        let process = self.process()?;
+        // Wait for the engine to process the previous commands, including a stop sent while it
+        // was idle.
+        process.send(&format!("ping {}", ping))?;
+        process.wait_for(&format!("pong {}", ping))?;
+
+        process.start_search("analyze")?;
+        let mut last_thinking = None;
+        loop {
+            let line = process.read_line()?;
+            if line.trim() == STOP_PONG {
+                break;
+            }
+
+            if let Some(thinking) = parse_thinking(&line) {
+                let done = limit_reached(limits, &thinking);
+                last_thinking = Some(thinking);
+                if done {
+                    process.send("exit")?;
+                    process.send(&format!("ping {}", exit_ping))?;
+                    process.wait_for(&format!("pong {}", exit_ping))?;
+                    break;
+                }
+            }
+        }
+        process.send("force")?;
+        self.set_result(last_thinking);
+        Ok(())
+    }
+
+    /// Search by letting the engine think and move.
+    fn think(&mut self, limits: &SearchLimits) -> io::Result<()> {
+        let depth =
+            match (limits.depth, limits.movetime) {
+                (Some(depth), _) => depth,
+                (None, Some(_)) => MAX_DEPTH,
+                (None, None) => DEFAULT_DEPTH,
+            };
+        // The time per move is in seconds.
+        let seconds = limits.movetime
+            .map(|movetime| ((movetime + 999) / 1000).max(1))
+            .unwrap_or(MAX_SECONDS);
+        let process = self.process()?;
+        process.send(&format!("sd {}", depth))?;
+        process.send(&format!("st {}", seconds))?;
+        process.start_search("go")?;
+        let mut last_thinking = None;
+        let best_move = loop {
+            let line = process.read_line()?;
+            let mut words = line.split_whitespace();
+            if words.next() == Some("move") {
+                break words.next()
+                    .and_then(|mov| Uci::from_ascii(mov.as_bytes()).ok());
+            }
+            if let Some(thinking) = parse_thinking(&line) {
+                last_thinking = Some(thinking);
+            }
+        };
+        process.send("force")?;
+        self.set_result(last_thinking);
+        if best_move.is_some() {
+            self.best_move = best_move;
+        }
+        Ok(())
+    }
+
+    fn set_result(&mut self, thinking: Option<Thinking>) {
+        match thinking {
+            Some(thinking) => {
+                self.best_move = thinking.pv.first().cloned();
+                self.lines = vec![PvLine {
+                    pv: thinking.pv,
+                    score: Some(thinking.score),
+                }];
+            },
+            None => {
+                self.best_move = None;
+                self.lines = vec![PvLine::default()];
+            },
+        }
+    }
+}
+
+impl Engine for XboardEngine {
+    fn start(&mut self) -> io::Result<()> {
+        let mut process = Process::spawn(&self.path)?;
+        process.send("xboard")?;
+        process.send("protover 2")?;
+        let features = negotiate(&mut process)?;
+        if !features.setboard {
+            return Err(io::Error::new(io::ErrorKind::Other, "the engine does not support setboard"));
+        }
+        // Crazyhouse has the same drops: it's the next best thing when bughouse is not supported.
+        let variant =
+            match features.variants {
+                Some(ref variants) if !variants.iter().any(|variant| variant == "bughouse") => {
+                    if !variants.iter().any(|variant| variant == "crazyhouse") {
+                        return Err(io::Error::new(io::ErrorKind::Other, "the engine does not support bughouse"));
+                    }
+                    "crazyhouse"
+                },
+                _ => "bughouse",
+            };
+        process.send(&format!("variant {}", variant))?;
+        process.send("post")?;
+        process.send("force")?;
+        self.features = features;
+        self.process = Some(process);
+        Ok(())
+    }
+
+    fn set_position(&mut self, position: &Bughouse) -> io::Result<()> {
+        let process = self.process()?;
+        process.send("force")?;
+        process.send(&format!("setboard {}", fen::fen(position)))?;
+        if let Some(pockets) = position.pockets() {
+            process.send(&format!("holding [{}] [{}]", holding(&pockets.white), holding(&pockets.black)))?;
+        }
+        Ok(())
+    }
+
+    fn set_options(&mut self, options: &EngineOptions) -> io::Result<()> {
+        let process = self.process()?;
+        if let Some(hash) = options.hash {
+            process.send(&format!("memory {}", hash))?;
+        }
+        if let Some(threads) = options.threads {
+            process.send(&format!("cores {}", threads))?;
+        }
+        Ok(())
+    }
+
+    /// The node limit is only supported in analyze mode.
+    fn search(&mut self, limits: &SearchLimits) -> io::Result<()> {
+        self.best_move = None;
+        self.lines.clear();
+        if self.uses_analyze_mode() {
+            self.analyze(limits)
+        }
+        else {
+            self.think(limits)
+        }
+    }
+
+    fn best_move(&self) -> Option<&Uci> {
+        self.best_move.as_ref()
+    }
+
+    fn lines(&self) -> &[PvLine] {
+        &self.lines
+    }
+
+    /// xboard has no multi-PV mode: only the best line is given.
+    fn set_multi_pv(&mut self, _count: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn stop_handle(&self) -> Option<StopHandle> {
+        let commands =
+            if self.uses_analyze_mode() {
+                STOP_ANALYSIS
+            }
+            else {
+                STOP_THINKING
+            };
+        self.process.as_ref().map(|process| process.stop_handle(commands))
+    }
+
+    fn was_stopped(&self) -> bool {
+        self.process.as_ref().map(Process::was_stopped).unwrap_or(false)
+    }
+}
+
+/// Read the features sent by the engine until it says it's done, accepting the ones supported.
+fn negotiate(process: &mut Process) -> io::Result<Features> {
+    let mut features = Features::default();
+    loop {
+        let line = process.read_line()?;
+        let line = line.trim();
+        if !line.starts_with("feature ") {
+            continue;
+        }
+
+        let mut done = false;
+        for (name, value) in parse_features(&line["feature ".len()..]) {
+            let enabled = value == "1";
+            match name.as_str() {
+                "analyze" => features.analyze = enabled,
+                "done" => done = enabled,
+                "ping" => features.ping = enabled,
+                "setboard" => features.setboard = enabled,
+                "variants" => features.variants = Some(value.split(',').map(|variant| variant.trim().to_string()).collect()),
+                _ => (),
+            }
+            // Moves are parsed in coordinate notation.
+            if name == "san" && enabled {
+                process.send("rejected san")?;
+            }
+            else if name != "done" {
+                process.send(&format!("accepted {}", name))?;
+            }
+        }
+        if done {
+            return Ok(features);
+        }
+    }
+}
+
+/// Parse features like: ping=1 myname="Sjeng 11.2" variants="normal,bughouse".
+pub fn parse_features(features: &str) -> Vec<(String, String)> {
+    let mut result = vec![];
+    let mut rest = features.trim_start();
+    while let Some(equal) = rest.find('=') {
+        let name = rest[..equal].trim().to_string();
+        rest = &rest[equal + 1..];
+        let value =
+            if rest.starts_with('"') {
+                let end = rest[1..].find('"').map(|index| index + 1).unwrap_or(rest.len());
+                let value = rest[1..end].to_string();
+                rest = rest.get(end + 1..).unwrap_or("");
+                value
+            }
+            else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let value = rest[..end].to_string();
+                rest = &rest[end..];
+                value
+            };
+        result.push((name, value));
+        rest = rest.trim_start();
+    }
+    result
+}
+
+fn limit_reached(limits: &SearchLimits, thinking: &Thinking) -> bool {
+    let depth_reached = limits.depth_or_default().map(|depth| thinking.depth >= depth).unwrap_or(false);
+    let time_reached = limits.movetime.map(|movetime| thinking.time >= u64::from(movetime)).unwrap_or(false);
+    let nodes_reached = limits.nodes.map(|nodes| thinking.nodes >= nodes).unwrap_or(false);
+    depth_reached || time_reached || nodes_reached
+}
+
+fn parse_thinking(line: &str) -> Option<Thinking> {
+    let mut words = line.split_whitespace();
+    // Some engines add a dot or other marks after the ply.
+    let depth = words.next()?.trim_end_matches(|character: char| !character.is_ascii_digit()).parse().ok()?;
+    let score = parse_score(words.next()?.parse().ok()?);
+    let centiseconds: u64 = words.next()?.parse().ok()?;
+    let nodes = words.next()?.parse().ok()?;
+    let pv = words
+        .map(|mov| mov.trim_end_matches(|character: char| character == '+' || character == '#'))
+        .filter_map(|mov| Uci::from_ascii(mov.as_bytes()).ok())
+        .collect();
+    Some(Thinking {
+        depth,
+        nodes,
+        pv,
+        score,
+        time: centiseconds * 10,
+    })
+}
+
+fn parse_score(score: i32) -> Score {
+    if score.abs() >= MATE_SCORE / 2 {
+        let plies = MATE_SCORE - score.abs();
+        let moves = (plies + 1) / 2;
+        Score::Mate(if score > 0 { moves } else { -moves })
+    }
+    else {
+        Score::Centipawns(score)
+    }
+}
+
+fn holding(side: &MaterialSide) -> String {
+    let pieces = [
+        ('P', side.pawns),
+        ('N', side.knights),
+        ('B', side.bishops),
+        ('R', side.rooks),
+        ('Q', side.queens),
+    ];
+    pieces.iter()
+        .flat_map(|&(piece, count)| (0..count).map(move |_| piece))
+        .collect()
+}
@@ -0,0 +1,378 @@
+/*
+ * Export the puzzles to share them with other bughouse tools, or to print them.
+ *
+ * In BPGN, each puzzle is a game starting from the BFEN of both boards, with the moves of the
+ * solution on board A and those of the partner on board B. The other solution lines are
+ * variations, and the goal, the difficulty, the chapter and the piece to request are in headers
+ * which other tools ignore. The clocks of the game the puzzle comes from, when known, are the
+ * "{C:1:58.9}" comments of the first move of the player and of the opponent's reply, likewise for
+ * the first moves of each side on the partner's board, and the messages of the partner are in the
+ * chat comments "{C:need a knight}".
+ *
+ * In LaTeX, the puzzles are diagrams drawn by the skak package, followed by the solutions in an
+ * appendix. skak knows nothing of the pockets, which are written below the diagrams.
+ *
+ * In EPD, each puzzle is a position with its first move as best move (bm). The pockets, written in
+ * the board in crazyhouse FEN, are moved to the custom "holdings" opcode so that chess tools can
+ * read the board, as is the goal.
+ *
+ * For Anki, the deck is a text file of notes to import, whose front is the image of the position
+ * with the side to move and whose back is the solution. The images are written next to it, to be
+ * copied to the collection.media folder of the Anki profile.
+ */
+
+use std::fs;
+use std::path::Path;
+
+use shakmaty::{
+    Color,
+    fen,
+    Move,
+    position::Bughouse,
+    san::San,
+    Setup,
+};
+
+use crate::bfen;
+use crate::image;
+use crate::material::POCKET_ROLES;
+use crate::puzzle::{self, Puzzle};
+
+// Length of the lines of moves.
+const LINE_LENGTH: usize = 80;
+
+pub fn to_bpgn(puzzles: &[Puzzle]) -> String {
+    let mut bpgn = String::new();
+    for (index, puzzle) in puzzles.iter().enumerate() {
+        write_headers(&mut bpgn, index + 1, puzzle);
+        bpgn.push('\n');
+        let mut tokens = moves_tokens(puzzle);
+        tokens.push("*".to_string());
+        write_lines(&mut bpgn, &tokens);
+        bpgn.push('\n');
+    }
+    bpgn
+}
+
+pub fn to_latex(puzzles: &[Puzzle]) -> String {
+    let mut latex = String::from("\\documentclass{article}\n\\usepackage[utf8]{inputenc}\n\\usepackage{skak}\n\n\\begin{document}\n\n");
+    latex.push_str("\\section*{Puzzles}\n\n");
+    for (index, puzzle) in puzzles.iter().enumerate() {
+        let turn = puzzle.position.turn();
+        latex.push_str(&format!("\\subsection*{{Puzzle {}}}\n\n", index + 1));
+        latex.push_str(&format!("\\fenboard{{{}}}\n", fen_without_pockets(&puzzle.position)));
+        // The player to move is at the bottom.
+        latex.push_str(if turn == Color::White { "\\showboard\n\n" } else { "\\showinverseboard\n\n" });
+        latex.push_str(&format!("{} to move. Goal: {}.", color_name(turn), escape_latex(&puzzle.goal.to_string())));
+        if let Some(difficulty) = puzzle.difficulty {
+            latex.push_str(&format!(" Difficulty: {}.", difficulty));
+        }
+        latex.push_str("\n\n");
+        for &color in &[turn, !turn] {
+            latex.push_str(&format!("{} in hand: {}\\\\\n", color_name(color), pocket_text(&puzzle.position, color)));
+        }
+        latex.push('\n');
+    }
+
+    latex.push_str("\\appendix\n\\section*{Solutions}\n\n\\begin{enumerate}\n");
+    for puzzle in puzzles {
+        latex.push_str(&format!("\\item {}\n", escape_latex(&solution_text(&puzzle.position, &puzzle.solution.main_line()))));
+    }
+    latex.push_str("\\end{enumerate}\n\n\\end{document}\n");
+    latex
+}
+
+pub fn to_epd(puzzles: &[Puzzle]) -> String {
+    let mut epd = String::new();
+    for (index, puzzle) in puzzles.iter().enumerate() {
+        let fen = fen_without_pockets(&puzzle.position);
+        let fields: Vec<&str> = fen.split(' ').take(4).collect();
+        epd.push_str(&fields.join(" "));
+        // Every correct first move is a best move.
+        let best_moves: Vec<String> = puzzle.solution.first_moves()
+            .map(|mov| San::from_move(&puzzle.position, mov).to_string())
+            .collect();
+        if !best_moves.is_empty() {
+            epd.push_str(&format!(" bm {};", best_moves.join(" ")));
+        }
+        epd.push_str(&format!(" id \"buzzle {}\";", index + 1));
+        epd.push_str(&format!(" holdings \"{}\";", holdings(&puzzle.position)));
+        epd.push_str(&format!(" goal \"{}\";", puzzle.goal));
+        epd.push('\n');
+    }
+    epd
+}
+
+/// Pieces in the pockets in the crazyhouse FEN notation, e.g. "QNp".
+fn holdings(position: &Bughouse) -> String {
+    let mut holdings = String::new();
+    if let Some(pockets) = position.pockets() {
+        for &color in &[Color::White, Color::Black] {
+            for &role in POCKET_ROLES.iter().rev() {
+                for _ in 0..pockets.by_color(color).by_role(role) {
+                    holdings.push(role.of(color).char());
+                }
+            }
+        }
+    }
+    holdings
+}
+
+pub const ANKI_DECK: &str = "buzzle-anki.txt";
+
+/// Write the Anki deck and its images in `dir`.
+pub fn to_anki(puzzles: &[Puzzle], dir: &Path) -> Result<(), String> {
+    let mut deck = String::from("#separator:tab\n#html:true\n#tags column:3\n");
+    for (index, puzzle) in puzzles.iter().enumerate() {
+        let turn = puzzle.position.turn();
+        let image_name = format!("buzzle-{}.png", index + 1);
+        let shapes = puzzle.annotation(0).map(|annotation| annotation.shapes.as_slice()).unwrap_or(&[]);
+        image::export(&dir.join(&image_name), &puzzle.position, shapes, turn)?;
+        let front = format!("<img src=\"{}\"><br>{} to move: {}", image_name, color_name(turn), escape_html(&puzzle.goal.to_string()));
+        let back = escape_html(&solution_text(&puzzle.position, &puzzle.solution.main_line()));
+        let tags: Vec<String> = puzzle.tags.iter().map(|tag| tag.replace(char::is_whitespace, "_")).collect();
+        deck.push_str(&format!("{}\t{}\tbuzzle {}\n", front, back, tags.join(" ")));
+    }
+    let path = dir.join(ANKI_DECK);
+    fs::write(&path, deck).map_err(|error| format!("Cannot save {}: {}", path.display(), error))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// FEN of the board without the pockets and promoted pieces, which the chess tools do not read.
+fn fen_without_pockets(position: &Bughouse) -> String {
+    let fen = fen::fen(position);
+    let mut fields = fen.split(' ');
+    let board: String = fields.next().unwrap_or("")
+        .split('[').next().unwrap_or("")
+        .chars()
+        .filter(|&character| character != '~')
+        .collect();
+    let rest: Vec<&str> = fields.collect();
+    format!("{} {}", board, rest.join(" "))
+}
+
+fn pocket_text(position: &Bughouse, color: Color) -> String {
+    let pieces: Vec<String> = position.pockets()
+        .map(|pockets| {
+            POCKET_ROLES.iter()
+                .filter(|&&role| pockets.by_color(color).by_role(role) > 0)
+                .map(|&role| format!("{}{}", pockets.by_color(color).by_role(role), role.upper_char()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if pieces.is_empty() {
+        "nothing".to_string()
+    }
+    else {
+        pieces.join(", ")
+    }
+}
+
+fn solution_text(position: &Bughouse, moves: &[Move]) -> String {
+    let mut position = position.clone();
+    let mut tokens = vec![];
+    for (index, mov) in moves.iter().enumerate() {
+        let number = u32::from(position.fullmoves());
+        if position.turn() == Color::White {
+            tokens.push(format!("{}.", number));
+        }
+        else if index == 0 {
+            tokens.push(format!("{}...", number));
+        }
+        tokens.push(San::from_move(&position, mov).to_string());
+        position.play_unchecked(mov);
+    }
+    tokens.join(" ")
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+fn escape_latex(text: &str) -> String {
+    let mut escaped = String::new();
+    for character in text.chars() {
+        match character {
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(character);
+            },
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+fn write_headers(bpgn: &mut String, number: usize, puzzle: &Puzzle) {
+    let mut headers = vec![
+        ("Event", "Buzzle puzzle".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", "????.??.??".to_string()),
+        ("Round", number.to_string()),
+    ];
+    let players = puzzle.players.as_ref();
+    let names = ["WhiteA", "BlackA", "WhiteB", "BlackB"];
+    for (index, &key) in names.iter().enumerate() {
+        let name = players.map(|players| {
+            if index < 2 { players.board[index].clone() } else { players.partner[index - 2].clone() }
+        });
+        headers.push((key, name.unwrap_or_else(|| "?".to_string())));
+    }
+    headers.push(("Result", "*".to_string()));
+    headers.push(("SetUp", "1".to_string()));
+    headers.push(("FEN", bfen::format(&puzzle.position, puzzle.partner.as_ref())));
+    headers.push(("Goal", puzzle.goal.to_string()));
+    if let Some(ref chapter) = puzzle.chapter {
+        headers.push(("Chapter", chapter.clone()));
+    }
+    if let Some(difficulty) = puzzle.difficulty {
+        headers.push(("Difficulty", difficulty.to_string()));
+    }
+    if let Some(role) = puzzle.request {
+        headers.push(("Request", role.upper_char().to_string()));
+    }
+    for (key, value) in headers {
+        bpgn.push_str(&format!("[{} \"{}\"]\n", key, value.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+}
+
+/// Moves of the main line with the partner's moves, followed by the variations where they leave
+/// the main line.
+fn moves_tokens(puzzle: &Puzzle) -> Vec<String> {
+    // The messages before the first move can only be read by people.
+    let mut tokens: Vec<String> = puzzle.messages.iter()
+        .filter(|message| message.ply == 0)
+        .map(|message| format!("{{C:{}}}", message.text.replace('}', ")")))
+        .collect();
+    // Index in the tokens of each move of the main line.
+    let mut move_tokens = vec![];
+    // Sides whose first move on the partner's board is written, with its clock.
+    let mut partner_movers = vec![];
+    let plies: Vec<usize> = puzzle.partner_moves.iter().map(|partner_move| partner_move.ply).collect();
+    let main_line = puzzle.solution.main_line();
+    let result = puzzle::replay(&puzzle.position, puzzle.partner.as_ref(), main_line.len(), &plies, |on_partner, index, position| {
+        let mov = if on_partner { &puzzle.partner_moves[index].mov } else { &main_line[index] };
+        let mut token = move_token(position, mov, on_partner);
+        if on_partner {
+            if !partner_movers.contains(&position.turn()) {
+                partner_movers.push(position.turn());
+                token.push_str(&partner_clock_comment(puzzle, position.turn()));
+            }
+        }
+        else {
+            move_tokens.push(tokens.len());
+            token.push_str(&clock_comment(puzzle, index));
+            token.push_str(&chat_comments(puzzle, index + 1));
+        }
+        tokens.push(token);
+        Ok(mov.clone())
+    });
+    if let Err(error) = result {
+        // The main line alone can still be written.
+        eprintln!("Cannot export the partner's moves: {}", error);
+        tokens.retain(|token| token.starts_with('{'));
+        move_tokens.clear();
+        let mut position = puzzle.position.clone();
+        for (index, mov) in main_line.iter().enumerate() {
+            move_tokens.push(tokens.len());
+            tokens.push(move_token(&position, mov, false) + &clock_comment(puzzle, index) + &chat_comments(puzzle, index + 1));
+            position.play_unchecked(mov);
+        }
+    }
+
+    // The variations are inserted from the last, to keep the indexes of the previous moves.
+    let mut variations = vec![];
+    for variation in puzzle.solution.variations() {
+        let start = puzzle.solution.main_line_ply(&variation);
+        if start < variation.len() && start < main_line.len() {
+            let mut position = puzzle.position.clone();
+            for mov in &variation[..start] {
+                position.play_unchecked(mov);
+            }
+            let mut variation_tokens = vec!["(".to_string()];
+            for mov in &variation[start..] {
+                variation_tokens.push(move_token(&position, mov, false));
+                position.play_unchecked(mov);
+            }
+            variation_tokens.push(")".to_string());
+            variations.push((move_tokens[start] + 1, variation_tokens));
+        }
+    }
+    variations.sort_by_key(|&(index, _)| index);
+    for (index, variation_tokens) in variations.into_iter().rev() {
+        tokens.splice(index..index, variation_tokens);
+    }
+    tokens
+}
+
+/// Clock comment of the move at `index` of the main line: the time left to the player after their
+/// first move and to the opponent after their reply, taken as the clocks at the start.
+fn clock_comment(puzzle: &Puzzle, index: usize) -> String {
+    let seconds =
+        match (puzzle.clock, index) {
+            (Some(clock), 0) => clock.player,
+            (Some(clock), 1) => clock.opponent,
+            _ => return String::new(),
+        };
+    time_comment(seconds)
+}
+
+/// Clock comment of the first move of the side `mover` on the partner's board.
+fn partner_clock_comment(puzzle: &Puzzle, mover: Color) -> String {
+    match puzzle.partner_clock {
+        // The partner plays the other color.
+        Some(clock) if mover != puzzle.position.turn() => time_comment(clock.player),
+        Some(clock) => time_comment(clock.opponent),
+        None => String::new(),
+    }
+}
+
+fn time_comment(seconds: f32) -> String {
+    let seconds = seconds.max(0.0);
+    format!(" {{C:{}:{:04.1}}}", (seconds / 60.0) as u32, seconds % 60.0)
+}
+
+/// Chat comments of the partner's messages shown after `ply` moves of the main line.
+fn chat_comments(puzzle: &Puzzle, ply: usize) -> String {
+    puzzle.messages.iter()
+        .filter(|message| message.ply == ply)
+        .map(|message| format!(" {{C:{}}}", message.text.replace('}', ")")))
+        .collect()
+}
+
+/// Move numbered like "12A." or "12b.", the letter of the board being lowercase for black.
+fn move_token(position: &Bughouse, mov: &Move, on_partner: bool) -> String {
+    let board = if on_partner { 'B' } else { 'A' };
+    let letter =
+        match position.turn() {
+            Color::White => board,
+            Color::Black => board.to_ascii_lowercase(),
+        };
+    format!("{}{}. {}", u32::from(position.fullmoves()), letter, San::from_move(position, mov))
+}
+
+fn write_lines(bpgn: &mut String, tokens: &[String]) {
+    let mut line_length = 0;
+    for token in tokens {
+        if line_length > 0 && line_length + 1 + token.len() > LINE_LENGTH {
+            bpgn.push('\n');
+            line_length = 0;
+        }
+        else if line_length > 0 {
+            bpgn.push(' ');
+            line_length += 1;
+        }
+        bpgn.push_str(token);
+        line_length += token.len();
+    }
+    bpgn.push('\n');
+}
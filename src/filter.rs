@@ -0,0 +1,93 @@
+/*
+ * Choose the games to generate puzzles from by their headers, since puzzles from the blunders of
+ * beginners or from bullet games are rarely interesting.
+ *
+ * The ratings are in WhiteElo and BlackElo in PGN, and in WhiteAElo, BlackAElo, WhiteBElo and
+ * BlackBElo in BPGN. The time control is the base time in seconds followed by the increment, e.g.
+ * "180+0". A game whose header needed by a criterion is missing is rejected.
+ *
+ * The players filter also applies to the imported puzzles, to train on positions of one's own
+ * games or of one's partner.
+ */
+
+use serde::{Deserialize, Serialize};
+
+const PLAYER_HEADERS: [&str; 6] = ["White", "Black", "WhiteA", "BlackA", "WhiteB", "BlackB"];
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct GameFilter {
+    /// Only keep the games won by a side.
+    pub decisive_only: bool,
+    /// Maximum base time of the time control, in seconds.
+    pub max_time: Option<u32>,
+    /// Minimum rating of every player of the game.
+    pub min_rating: Option<u32>,
+    /// Minimum base time of the time control, in seconds.
+    pub min_time: Option<u32>,
+    /// Only keep the games where one of these players played, if any.
+    pub players: Vec<String>,
+}
+
+impl GameFilter {
+    pub fn accepts(&self, headers: &[(String, String)]) -> bool {
+        let header = |key: &str| headers.iter()
+            .find(|(header_key, _)| header_key == key)
+            .map(|(_, value)| value.as_str());
+
+        if !has_player(headers, &self.players) {
+            return false;
+        }
+
+        if self.decisive_only {
+            match header("Result") {
+                Some("1-0") | Some("0-1") => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(min_rating) = self.min_rating {
+            let ratings: Vec<Option<u32>> = headers.iter()
+                .filter(|(key, _)| key.ends_with("Elo"))
+                .map(|(_, value)| value.parse().ok())
+                .collect();
+            if ratings.is_empty() || !ratings.iter().all(|rating| rating.map(|rating| rating >= min_rating).unwrap_or(false)) {
+                return false;
+            }
+        }
+
+        if self.min_time.is_some() || self.max_time.is_some() {
+            let base_time: Option<u32> = header("TimeControl")
+                .and_then(|time_control| time_control.split('+').next())
+                .and_then(|base_time| base_time.trim().parse().ok());
+            let base_time =
+                match base_time {
+                    Some(base_time) => base_time,
+                    None => return false,
+                };
+            if self.min_time.map(|min_time| base_time < min_time).unwrap_or(false) ||
+                self.max_time.map(|max_time| base_time > max_time).unwrap_or(false)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Check if one of the `players` played the game, ignoring the case of the names. Any game
+/// matches when there are no players.
+pub fn has_player(headers: &[(String, String)], players: &[String]) -> bool {
+    players.is_empty() || headers.iter()
+        .filter(|(key, _)| PLAYER_HEADERS.contains(&key.as_str()))
+        .any(|(_, name)| players.iter().any(|player| player.eq_ignore_ascii_case(name.trim())))
+}
+
+/// Parse a list of player names separated by commas or spaces.
+pub fn parse_players(text: &str) -> Vec<String> {
+    text.split(|character: char| character == ',' || character.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
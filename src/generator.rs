@@ -0,0 +1,542 @@
+/*
+ * Generate puzzles from full games by running the engine on every position and keeping the ones
+ * where the side to move has a forced checkmate or wins material by force.
+ *
+ * Material is usually won with tactics like a checked fork or a trapped piece (e.g. a rook on a8
+ * behind a pawn on b7): these puzzles are solved as soon as the piece is captured. They are only
+ * kept if a shallow search shows that the opponent cannot win the piece back, e.g. with a drop.
+ *
+ * When the partner's board is known, also look for captures that give the partner the piece they
+ * need to force mate.
+ *
+ * Finally, when every move loses but the opponent has nothing if we don't move, the solution is to
+ * sit, i.e. wait for a piece from the partner. Otherwise, if most moves lose immediately, the
+ * puzzle is to find the defense that survives the longest. When the position holds but the
+ * opponent threatens mate and a single move, often a blocking drop, avoids it, the puzzle is to
+ * find this move and hold for a few plies of the attack.
+ *
+ * Bughouse is played on the clock: when the clock comments of the game are known, long solutions
+ * are only kept if the player had the time to find them, and sitting only if they had time to spare.
+ * When the partner is about to lose on time, sitting would lose the game: the player must play on,
+ * and the mates are only kept if every move is a check, leaving no time to the opponent.
+ */
+
+use std::cmp::min;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    Move,
+    Position,
+    position::Bughouse,
+    Role,
+    Setup,
+};
+
+use crate::engine::{self, Engine, Score, SearchLimits, WINNING_SCORE};
+use crate::importer::Game;
+use crate::material::{POCKET_ROLES, material_balance, modify_pockets, pass_turn, pocket_mut};
+use crate::partner;
+use crate::puzzle::{Goal, PartnerMove, Puzzle};
+use crate::quality;
+use crate::solution::Solution;
+
+const GENERATION_DEPTH: u32 = 12;
+const MAX_MATE: i32 = 3;
+// Number of lines searched to find other solutions.
+const MULTI_PV: usize = 5;
+// Minimum number of moves to survive for a survival puzzle.
+const MIN_SURVIVAL: i32 = 2;
+// Plies of the scripted attack to hold in a defense puzzle, ending with a move of the player.
+const DEFENSE_PLIES: usize = 3;
+// Minimum gain, in pawns, for a material win puzzle.
+pub const MATERIAL_WIN: i32 = 3;
+const MAX_MATERIAL_WIN_PLIES: usize = 5;
+// Time needed by the player, in seconds, for each move of a solution longer than one move.
+const SECONDS_PER_MOVE: f32 = 3.0;
+// Minimum time left to the player, in seconds, for a sit puzzle.
+const SIT_SECONDS: f32 = 10.0;
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Options {
+    /// Assume the opponent has a full set of pieces in hand, since in bughouse, pieces can arrive
+    /// in their pocket at any time.
+    pub full_opponent_pocket: bool,
+    /// Limits of every search done while generating or validating puzzles.
+    pub limits: SearchLimits,
+    /// Puzzles of a lower quality are pruned, from 0 to 100. 0 keeps every puzzle.
+    pub min_quality: u32,
+    /// Only look for the known tactics of the pattern library, without searching with the engine.
+    pub patterns_only: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            full_opponent_pocket: false,
+            limits: SearchLimits::depth(GENERATION_DEPTH),
+            min_quality: quality::DEFAULT_MIN_QUALITY,
+            patterns_only: false,
+        }
+    }
+}
+
+pub fn generate(engine: &mut dyn Engine, game: &Game, options: Options) -> io::Result<Vec<Puzzle>> {
+    let limits = &options.limits;
+    let mut puzzles = vec![];
+    let mut position = game.position.clone();
+    let mut index = 0;
+    while index < game.moves.len() {
+        let analyzed_position =
+            if options.full_opponent_pocket {
+                fill_opponent_pocket(&position)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+            }
+            else {
+                position.clone()
+            };
+        let partner_clock = game.partner_clock(index);
+        let partner_low_on_time = partner_clock.map(|clock| clock.is_low()).unwrap_or(false);
+        let puzzle =
+            match find_puzzle(engine, &analyzed_position, partner_low_on_time, limits)? {
+                Some(puzzle) => Some(puzzle),
+                None =>
+                    match game.partner {
+                        Some(ref partner) => find_cross_board_puzzle(engine, &analyzed_position, partner, limits)?,
+                        None => None,
+                    },
+            };
+        let puzzle =
+            match (puzzle, &game.partner) {
+                (Some(mut puzzle), Some(partner)) => {
+                    if partner::is_fatal_to_partner(engine, &puzzle.position, &puzzle.solution.main_line(), partner, limits)? {
+                        None
+                    }
+                    else {
+                        puzzle.partner = Some(partner.clone());
+                        Some(puzzle)
+                    }
+                },
+                (puzzle, _) => puzzle,
+            };
+        let puzzle =
+            match puzzle {
+                Some(puzzle) if !is_sound(engine, &puzzle)? => None,
+                puzzle => puzzle,
+            };
+        let puzzle = puzzle
+            .map(|puzzle| Puzzle {
+                clock: game.clock(index),
+                partner_clock,
+                source: game.source.clone(),
+                ..puzzle
+            })
+            .filter(has_time);
+        match puzzle {
+            Some(puzzle) => {
+                // Don't create another puzzle from the positions of the solution.
+                let end = min(index + puzzle.solution.len(), game.moves.len());
+                puzzles.push(puzzle);
+                for mov in &game.moves[index..end] {
+                    position.play_unchecked(mov);
+                }
+                index = end;
+            },
+            None => {
+                position.play_unchecked(&game.moves[index]);
+                index += 1;
+            },
+        }
+    }
+    Ok(puzzles)
+}
+
+fn find_puzzle(engine: &mut dyn Engine, position: &Bughouse, partner_low_on_time: bool, limits: &SearchLimits)
+    -> io::Result<Option<Puzzle>>
+{
+    if position.is_game_over() {
+        return Ok(None);
+    }
+
+    engine.set_position(position)?;
+    engine.search(limits)?;
+    match engine.score() {
+        Some(Score::Mate(mate)) if mate > 0 && mate <= MAX_MATE => {
+            match mate_line(engine, position, limits)? {
+                Some(ref moves) if partner_low_on_time && !only_checks(position, moves) => Ok(None),
+                Some(moves) => {
+                    let mut solution = Solution::from_line(&moves);
+                    for variation in mate_variations(engine, position, mate, &moves, limits)? {
+                        solution.add_line(&variation);
+                    }
+                    for variation in defense_variations(engine, position, &moves, limits)? {
+                        solution.add_line(&variation);
+                    }
+                    Ok(Some(Puzzle::new(position.clone(), None, Goal::Mate, solution)))
+                },
+                None => Ok(None),
+            }
+        },
+        Some(Score::Centipawns(score)) if score >= WINNING_SCORE => find_capture_puzzle(engine, position, limits),
+        Some(Score::Centipawns(_)) => find_defense_puzzle(engine, position, limits),
+        Some(Score::Mate(moves)) if moves < 0 => {
+            let sit_puzzle =
+                if partner_low_on_time {
+                    None
+                }
+                else {
+                    find_sit_puzzle(engine, position, limits)?
+                };
+            match sit_puzzle {
+                Some(puzzle) => Ok(Some(puzzle)),
+                None => find_survival_puzzle(engine, position, -moves, limits),
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
+fn find_survival_puzzle(engine: &mut dyn Engine, position: &Bughouse, longest: i32, limits: &SearchLimits)
+    -> io::Result<Option<Puzzle>> {
+    if longest < MIN_SURVIVAL {
+        return Ok(None);
+    }
+
+    let legals = position.legals();
+    let mut immediate_mates = 0;
+    for mov in &legals {
+        let mut after_move = position.clone();
+        after_move.play_unchecked(mov);
+        engine.set_position(&after_move)?;
+        engine.search(limits)?;
+        if engine.score() == Some(Score::Mate(1)) {
+            immediate_mates += 1;
+        }
+    }
+    if immediate_mates * 2 < legals.len() {
+        return Ok(None);
+    }
+
+    // The solution is the best defense, until the opponent mates.
+    let mut moves = vec![];
+    let mut after_moves = position.clone();
+    while !after_moves.is_game_over() && moves.len() < longest as usize * 2 {
+        let mov = engine::best_move(engine, &after_moves, limits)?;
+        after_moves.play_unchecked(&mov);
+        moves.push(mov);
+    }
+    if !after_moves.is_checkmate() {
+        return Ok(None);
+    }
+
+    Ok(Some(Puzzle::new(position.clone(), None, Goal::Survive(longest), Solution::from_line(&moves))))
+}
+
+/// When the opponent threatens mate, e.g. with a check, find the only move avoiding the mate,
+/// often a blocking drop. The solution holds for a few plies of the attack of the engine.
+fn find_defense_puzzle(engine: &mut dyn Engine, position: &Bughouse, limits: &SearchLimits) -> io::Result<Option<Puzzle>> {
+    if !position.is_check() {
+        let sitting = pass_turn(position)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        engine.set_position(&sitting)?;
+        engine.search(limits)?;
+        if engine.score() != Some(Score::Mate(1)) {
+            return Ok(None);
+        }
+    }
+
+    let legals = position.legals();
+    if legals.len() < 2 {
+        return Ok(None);
+    }
+    let mut only_move = None;
+    for mov in &legals {
+        if engine::avoids_mate(engine, position, mov, limits)? {
+            if only_move.is_some() {
+                return Ok(None);
+            }
+            only_move = Some(mov.clone());
+        }
+    }
+    let only_move =
+        match only_move {
+            Some(mov) => mov,
+            None => return Ok(None),
+        };
+
+    // The attack is scripted with the best moves of both sides.
+    let mut moves = vec![only_move];
+    let mut after_moves = position.clone();
+    after_moves.play_unchecked(&moves[0]);
+    while moves.len() < DEFENSE_PLIES {
+        if after_moves.is_game_over() {
+            return Ok(None);
+        }
+        let mov = engine::best_move(engine, &after_moves, limits)?;
+        after_moves.play_unchecked(&mov);
+        moves.push(mov);
+    }
+    if after_moves.is_game_over() {
+        return Ok(None);
+    }
+
+    Ok(Some(Puzzle::new(position.clone(), None, Goal::Defend(DEFENSE_PLIES), Solution::from_line(&moves))))
+}
+
+fn find_sit_puzzle(engine: &mut dyn Engine, position: &Bughouse, limits: &SearchLimits) -> io::Result<Option<Puzzle>> {
+    let sitting = pass_turn(position)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    if sitting.is_check() {
+        return Ok(None);
+    }
+
+    engine.set_position(&sitting)?;
+    engine.search(limits)?;
+    if let Some(Score::Mate(moves)) = engine.score() {
+        if moves > 0 {
+            return Ok(None);
+        }
+    }
+
+    if !engine::all_moves_lose(engine, position, limits)? {
+        return Ok(None);
+    }
+
+    Ok(Some(Puzzle::new(position.clone(), None, Goal::Sit, Solution::new())))
+}
+
+fn find_capture_puzzle(engine: &mut dyn Engine, position: &Bughouse, limits: &SearchLimits) -> io::Result<Option<Puzzle>> {
+    let player = position.turn();
+    let initial_balance = material_balance(position, player);
+    Ok(material_win_line(engine, position, limits)?.map(|moves| {
+        let mut final_position = position.clone();
+        for mov in &moves {
+            final_position.play_unchecked(mov);
+        }
+        let gain = material_balance(&final_position, player) - initial_balance;
+        Puzzle::new(position.clone(), None, Goal::WinMaterial(gain.max(MATERIAL_WIN)), Solution::from_line(&moves))
+    }))
+}
+
+fn find_cross_board_puzzle(engine: &mut dyn Engine, position: &Bughouse, partner: &Bughouse, limits: &SearchLimits)
+    -> io::Result<Option<Puzzle>> {
+    let partner_color = !position.turn();
+    if partner::can_mate(engine, partner, partner_color, &[], limits)? {
+        return Ok(None);
+    }
+
+    let mut mate_roles = vec![];
+    for &role in &POCKET_ROLES {
+        if partner::can_mate(engine, partner, partner_color, &[role], limits)? {
+            mate_roles.push(role);
+        }
+    }
+
+    for mov in position.legals() {
+        let enables_mate = mov.capture().map(|role| mate_roles.contains(&role)).unwrap_or(false);
+        if !enables_mate {
+            continue;
+        }
+
+        // The capture must not lose the game on this board.
+        let mut after_capture = position.clone();
+        after_capture.play_unchecked(&mov);
+        engine.set_position(&after_capture)?;
+        engine.search(limits)?;
+        if let Some(Score::Mate(moves)) = engine.score() {
+            if moves > 0 {
+                continue;
+            }
+        }
+
+        // The partner's mate with the captured piece is the script checking the solution.
+        let roles: Vec<Role> = partner::captured_role(position, &mov).into_iter().collect();
+        let partner_moves = partner::mate_line(engine, partner, partner_color, &roles, limits)?
+            .into_iter()
+            .map(|mov| PartnerMove {
+                mov,
+                ply: 1,
+            })
+            .collect();
+        return Ok(Some(Puzzle {
+            partner_moves,
+            ..Puzzle::new(position.clone(), Some(partner.clone()), Goal::PartnerMate(mate_roles),
+                Solution::from_line(&[mov]))
+        }));
+    }
+    Ok(None)
+}
+
+fn mate_line(engine: &mut dyn Engine, position: &Bughouse, limits: &SearchLimits) -> io::Result<Option<Vec<Move>>> {
+    let mut position = position.clone();
+    let mut moves = vec![];
+    for _ in 0..MAX_MATE * 2 - 1 {
+        let mov = engine::best_move(engine, &position, limits)?;
+        position.play_unchecked(&mov);
+        moves.push(mov);
+        if position.is_checkmate() {
+            return Ok(Some(moves));
+        }
+    }
+    Ok(None)
+}
+
+/// Find the other first moves that mate as fast as the main line.
+fn mate_variations(engine: &mut dyn Engine, position: &Bughouse, mate: i32, main_line: &[Move], limits: &SearchLimits)
+    -> io::Result<Vec<Vec<Move>>>
+{
+    engine.set_multi_pv(MULTI_PV)?;
+    engine.set_position(position)?;
+    let result = engine.search(limits);
+    let first_moves: Vec<Move> = engine.lines().iter()
+        .filter(|line| line.score == Some(Score::Mate(mate)))
+        .filter_map(|line| line.pv.first())
+        .filter_map(|uci| uci.to_move(position).ok())
+        .filter(|mov| Some(mov) != main_line.first())
+        .collect();
+    engine.set_multi_pv(1)?;
+    result?;
+
+    let mut variations = vec![];
+    for mov in first_moves {
+        let mut after_move = position.clone();
+        after_move.play_unchecked(&mov);
+        let rest =
+            if after_move.is_checkmate() {
+                Some(vec![])
+            }
+            else {
+                mate_line(engine, &after_move, limits)?
+            };
+        if let Some(rest) = rest {
+            let mut line = vec![mov];
+            line.extend(rest);
+            if line.len() == main_line.len() {
+                variations.push(line);
+            }
+        }
+    }
+    Ok(variations)
+}
+
+/// Find the other replies of the opponent to the main line of a mate which lose as fast, so that
+/// the defense can change when the puzzle is solved again.
+fn defense_variations(engine: &mut dyn Engine, position: &Bughouse, main_line: &[Move], limits: &SearchLimits)
+    -> io::Result<Vec<Vec<Move>>>
+{
+    let mut variations = vec![];
+    let mut before_reply = position.clone();
+    for (ply, mov) in main_line.iter().enumerate() {
+        // The opponent plays the even plies of the line.
+        if ply % 2 == 1 {
+            engine.set_multi_pv(MULTI_PV)?;
+            engine.set_position(&before_reply)?;
+            let result = engine.search(limits);
+            let best_score = engine.lines().first().and_then(|line| line.score);
+            let replies: Vec<Move> = engine.lines().iter()
+                .filter(|line| line.score.is_some() && line.score == best_score)
+                .filter_map(|line| line.pv.first())
+                .filter_map(|uci| uci.to_move(&before_reply).ok())
+                .filter(|reply| reply != mov)
+                .collect();
+            engine.set_multi_pv(1)?;
+            result?;
+
+            for reply in replies {
+                let mut after_reply = before_reply.clone();
+                after_reply.play_unchecked(&reply);
+                if let Some(rest) = mate_line(engine, &after_reply, limits)? {
+                    let mut line = main_line[..ply].to_vec();
+                    line.push(reply);
+                    line.extend(rest);
+                    if line.len() == main_line.len() {
+                        variations.push(line);
+                    }
+                }
+            }
+        }
+        before_reply.play_unchecked(mov);
+    }
+    Ok(variations)
+}
+
+fn material_win_line(engine: &mut dyn Engine, position: &Bughouse, limits: &SearchLimits) -> io::Result<Option<Vec<Move>>> {
+    let player = position.turn();
+    let initial_balance = material_balance(position, player);
+    let mut position = position.clone();
+    let mut moves = vec![];
+    while moves.len() < MAX_MATERIAL_WIN_PLIES {
+        let mov = engine::best_move(engine, &position, limits)?;
+        position.play_unchecked(&mov);
+        moves.push(mov);
+        if position.is_checkmate() {
+            return Ok(Some(moves));
+        }
+        if position.is_game_over() {
+            break;
+        }
+
+        // Only stop after the player's move if the material is still won after the reply.
+        let reply = engine::best_move(engine, &position, limits)?;
+        let mut after_reply = position.clone();
+        after_reply.play_unchecked(&reply);
+        if material_balance(&after_reply, player) - initial_balance >= MATERIAL_WIN {
+            return Ok(Some(moves));
+        }
+        position = after_reply;
+        moves.push(reply);
+    }
+    Ok(None)
+}
+
+/// Check if every move of the player in the line of `moves` gives check.
+fn only_checks(position: &Bughouse, moves: &[Move]) -> bool {
+    let mut position = position.clone();
+    for (index, mov) in moves.iter().enumerate() {
+        position.play_unchecked(mov);
+        if index % 2 == 0 && !position.is_check() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Check that the material won in a material win puzzle cannot be regained at once, in which case
+/// the puzzle would be unsound. The other puzzles are sound.
+pub fn is_sound(engine: &mut dyn Engine, puzzle: &Puzzle) -> io::Result<bool> {
+    match puzzle.goal {
+        Goal::WinMaterial(gain) => engine::keeps_won_material(engine, &puzzle.position, &puzzle.solution.main_line(), gain),
+        Goal::Defend(_) | Goal::Mate | Goal::PartnerMate(_) | Goal::Sit | Goal::Survive(_) => Ok(true),
+    }
+}
+
+/// Check that the player had the time to play the solution in the game. The puzzles without clock
+/// are accepted, but sitting never is when the partner is about to lose on time.
+pub fn has_time(puzzle: &Puzzle) -> bool {
+    if puzzle.goal == Goal::Sit && puzzle.partner_low_on_time() {
+        return false;
+    }
+    let clock =
+        match puzzle.clock {
+            Some(clock) => clock,
+            None => return true,
+        };
+    if puzzle.goal == Goal::Sit {
+        return clock.player >= SIT_SECONDS;
+    }
+    let player_moves = (puzzle.solution.len() + 1) / 2;
+    player_moves <= 1 || clock.player >= player_moves as f32 * SECONDS_PER_MOVE
+}
+
+/// Give a full set of pieces to the opponent of the side to move.
+pub fn fill_opponent_pocket(position: &Bughouse) -> Result<Bughouse, String> {
+    let opponent = !position.turn();
+    modify_pockets(position, |pockets| {
+        let opponent_pocket = pocket_mut(pockets, opponent);
+        opponent_pocket.pawns = 8;
+        opponent_pocket.knights = 2;
+        opponent_pocket.bishops = 2;
+        opponent_pocket.rooks = 2;
+        opponent_pocket.queens = 1;
+    })
+}
@@ -0,0 +1,11 @@
+/*
+ * FNV-1a hash which, unlike the hasher of the standard library, stays the same across runs, to
+ * identify what is saved to disk.
+ */
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0100_0000_01b3;
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(PRIME))
+}
@@ -0,0 +1,195 @@
+/*
+ * Export a position as an image to share it: the board seen from `orientation`, with the pocket
+ * of the opponent above it and the pocket of the player below, and the arrows and circles of the
+ * puzzle. The format is SVG for a ".svg" file and PNG otherwise.
+ *
+ * The pieces are drawn with the chess symbols of the fonts, so that no image is needed.
+ */
+
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use cairo::{Context, FontSlant, FontWeight, Format, ImageSurface, SvgSurface};
+use chessground::DrawBrush;
+use shakmaty::{
+    Color,
+    Piece,
+    position::Bughouse,
+    Role,
+    Setup,
+    Square,
+};
+
+use crate::annotation::Shape;
+use crate::material::POCKET_ROLES;
+
+const SQUARE_SIZE: f64 = 60.0;
+const WIDTH: f64 = 8.0 * SQUARE_SIZE;
+// The board, with a row for each pocket.
+const HEIGHT: f64 = 10.0 * SQUARE_SIZE;
+
+const LIGHT_SQUARE: (f64, f64, f64) = (0.94, 0.85, 0.71);
+const DARK_SQUARE: (f64, f64, f64) = (0.71, 0.53, 0.39);
+const POCKET_BACKGROUND: (f64, f64, f64) = (0.85, 0.85, 0.85);
+
+pub fn export(path: &Path, position: &Bughouse, shapes: &[Shape], orientation: Color) -> Result<(), String> {
+    let is_svg = path.extension().map(|extension| extension == "svg").unwrap_or(false);
+    if is_svg {
+        let surface = SvgSurface::new(WIDTH, HEIGHT, Some(path))
+            .map_err(|error| format!("Cannot create {}: {}", path.display(), error))?;
+        draw(&Context::new(&surface), position, shapes, orientation);
+        surface.finish();
+        Ok(())
+    }
+    else {
+        let png = png(position, shapes, orientation)?;
+        fs::write(path, png).map_err(|error| format!("Cannot write {}: {}", path.display(), error))
+    }
+}
+
+pub fn png(position: &Bughouse, shapes: &[Shape], orientation: Color) -> Result<Vec<u8>, String> {
+    let surface = ImageSurface::create(Format::ARgb32, WIDTH as i32, HEIGHT as i32)
+        .map_err(|error| format!("Cannot create the image: {}", error))?;
+    draw(&Context::new(&surface), position, shapes, orientation);
+    let mut png = vec![];
+    surface.write_to_png(&mut png).map_err(|error| format!("Cannot encode the image: {}", error))?;
+    Ok(png)
+}
+
+fn draw(context: &Context, position: &Bughouse, shapes: &[Shape], orientation: Color) {
+    context.select_font_face("DejaVu Sans", FontSlant::Normal, FontWeight::Normal);
+
+    for &(color, row) in &[(!orientation, 0.0), (orientation, 9.0)] {
+        set_color(context, POCKET_BACKGROUND);
+        context.rectangle(0.0, row * SQUARE_SIZE, WIDTH, SQUARE_SIZE);
+        context.fill();
+        if let Some(pockets) = position.pockets() {
+            for (column, &role) in POCKET_ROLES.iter().enumerate() {
+                let count = pockets.by_color(color).by_role(role);
+                if count > 0 {
+                    let x = column as f64 * SQUARE_SIZE;
+                    draw_piece(context, Piece { color, role }, x, row * SQUARE_SIZE);
+                    draw_count(context, count, x, row * SQUARE_SIZE);
+                }
+            }
+        }
+    }
+
+    for square in (0..64).map(Square::new) {
+        let (x, y) = square_origin(square, orientation);
+        let index = square as u32;
+        let light = (index % 8 + index / 8) % 2 == 1;
+        set_color(context, if light { LIGHT_SQUARE } else { DARK_SQUARE });
+        context.rectangle(x, y, SQUARE_SIZE, SQUARE_SIZE);
+        context.fill();
+        if let Some(piece) = position.board().piece_at(square) {
+            draw_piece(context, piece, x, y);
+        }
+    }
+
+    for shape in shapes {
+        draw_shape(context, shape, orientation);
+    }
+}
+
+/// Top left corner of `square`, below the pocket of the opponent.
+fn square_origin(square: Square, orientation: Color) -> (f64, f64) {
+    let index = square as u32;
+    let (file, rank) = (index % 8, index / 8);
+    let (column, row) =
+        match orientation {
+            Color::White => (file, 7 - rank),
+            Color::Black => (7 - file, rank),
+        };
+    (f64::from(column) * SQUARE_SIZE, f64::from(row + 1) * SQUARE_SIZE)
+}
+
+fn square_center(square: Square, orientation: Color) -> (f64, f64) {
+    let (x, y) = square_origin(square, orientation);
+    (x + SQUARE_SIZE / 2.0, y + SQUARE_SIZE / 2.0)
+}
+
+/// Solid chess symbol of the role, drawn in the color of the piece.
+pub fn symbol(role: Role) -> &'static str {
+    match role {
+        Role::King => "♚",
+        Role::Queen => "♛",
+        Role::Rook => "♜",
+        Role::Bishop => "♝",
+        Role::Knight => "♞",
+        Role::Pawn => "♟",
+    }
+}
+
+/// Draw the solid symbol of the piece, filled with its color and outlined in the other color.
+fn draw_piece(context: &Context, piece: Piece, x: f64, y: f64) {
+    let symbol = symbol(piece.role);
+    context.set_font_size(SQUARE_SIZE * 0.8);
+    let extents = context.text_extents(symbol);
+    context.move_to(
+        x + (SQUARE_SIZE - extents.width) / 2.0 - extents.x_bearing,
+        y + (SQUARE_SIZE - extents.height) / 2.0 - extents.y_bearing,
+    );
+    context.text_path(symbol);
+    let (fill, outline) =
+        match piece.color {
+            Color::White => (1.0, 0.0),
+            Color::Black => (0.0, 1.0),
+        };
+    context.set_source_rgb(fill, fill, fill);
+    context.fill_preserve();
+    context.set_source_rgb(outline, outline, outline);
+    context.set_line_width(1.0);
+    context.stroke();
+}
+
+/// Draw the number of pieces of a role in a pocket, in the bottom right corner.
+fn draw_count(context: &Context, count: u8, x: f64, y: f64) {
+    let text = count.to_string();
+    context.set_font_size(SQUARE_SIZE * 0.3);
+    let extents = context.text_extents(&text);
+    context.set_source_rgb(0.8, 0.0, 0.0);
+    context.move_to(x + SQUARE_SIZE - extents.width - extents.x_bearing - 2.0, y + SQUARE_SIZE - 4.0);
+    context.show_text(&text);
+}
+
+/// Draw an arrow, or a circle when the shape is on one square.
+fn draw_shape(context: &Context, shape: &Shape, orientation: Color) {
+    let (red, green, blue) =
+        match shape.brush {
+            DrawBrush::Green => (0.08, 0.47, 0.11),
+            DrawBrush::Red => (0.53, 0.13, 0.13),
+            DrawBrush::Blue => (0.0, 0.19, 0.53),
+            DrawBrush::Yellow => (0.9, 0.56, 0.0),
+        };
+    context.set_source_rgba(red, green, blue, 0.8);
+    let (orig_x, orig_y) = square_center(shape.orig, orientation);
+    if shape.orig == shape.dest {
+        context.set_line_width(SQUARE_SIZE / 15.0);
+        context.new_path();
+        context.arc(orig_x, orig_y, SQUARE_SIZE * 0.45, 0.0, 2.0 * PI);
+        context.stroke();
+        return;
+    }
+
+    let (dest_x, dest_y) = square_center(shape.dest, orientation);
+    let angle = (dest_y - orig_y).atan2(dest_x - orig_x);
+    let head_length = SQUARE_SIZE * 0.4;
+    // The line stops at the base of the head, which ends at the center of the square.
+    let (base_x, base_y) = (dest_x - head_length * angle.cos(), dest_y - head_length * angle.sin());
+    context.set_line_width(SQUARE_SIZE / 6.0);
+    context.move_to(orig_x, orig_y);
+    context.line_to(base_x, base_y);
+    context.stroke();
+    let half_width = SQUARE_SIZE * 0.25;
+    context.move_to(dest_x, dest_y);
+    context.line_to(base_x + half_width * angle.sin(), base_y - half_width * angle.cos());
+    context.line_to(base_x - half_width * angle.sin(), base_y + half_width * angle.cos());
+    context.close_path();
+    context.fill();
+}
+
+fn set_color(context: &Context, (red, green, blue): (f64, f64, f64)) {
+    context.set_source_rgb(red, green, blue);
+}
@@ -0,0 +1,880 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use pgn_reader::{
+    BufferedReader,
+    Nag,
+    RawComment,
+    RawHeader,
+    SanPlus,
+    Skip,
+    Visitor,
+};
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen,
+    Move,
+    Position,
+    position::Bughouse,
+    Role,
+    san::San,
+    Setup,
+};
+
+use crate::annotation;
+use crate::archive;
+use crate::bfen;
+use crate::bpgn::{self, Board, BpgnGame};
+use crate::charset;
+use crate::download::bughousedb;
+use crate::engine::{self, Engine};
+use crate::filter::{self, GameFilter};
+use crate::generator::{self, Options};
+use crate::material::material_balance;
+use crate::partner;
+use crate::puzzle::{self, Clock, Goal, PartnerMessage, PartnerMove, Players, Puzzle};
+use crate::solution::Solution;
+
+// Number of games read between two progress events.
+const PROGRESS_GAMES: usize = 100;
+
+/// Problem found in a game, which is skipped.
+pub struct ImportError {
+    /// Number of the game in the file, from 1.
+    pub game: usize,
+    pub message: String,
+}
+
+pub struct FENImporter {
+    // Board of the BPGN games the puzzles are played from.
+    board: Board,
+    current_position: Bughouse,
+    pub errors: Vec<ImportError>,
+    // Number of the current game.
+    game: usize,
+    goal: Option<Goal>,
+    // Whether an error was found in the current game.
+    has_error: bool,
+    // Headers of the current game.
+    headers: Vec<(String, String)>,
+    in_puzzle: bool,
+    // Moves of the current line, from the start of the puzzle.
+    line: Vec<Move>,
+    // Position before each move of the current line.
+    line_positions: Vec<Bughouse>,
+    // Players whose games are kept, all when empty.
+    players: Vec<String>,
+    pub puzzles: Vec<Puzzle>,
+    // Piece to ask the partner for, from the Request header.
+    request: Option<Role>,
+    pub skipped_games: usize,
+    // Whether the variant of the game can be played on a bughouse board.
+    supported_variant: bool,
+    // Lines and positions to go back to at the end of the variations.
+    variation_stack: Vec<(Vec<Move>, Vec<Bughouse>, Bughouse)>,
+}
+
+impl FENImporter {
+    pub fn new(players: Vec<String>, board: Board) -> Self {
+        Self {
+            board,
+            current_position: Bughouse::default(),
+            errors: vec![],
+            game: 0,
+            goal: None,
+            has_error: false,
+            headers: vec![],
+            in_puzzle: false,
+            line: vec![],
+            line_positions: vec![],
+            players,
+            puzzles: vec![],
+            request: None,
+            skipped_games: 0,
+            supported_variant: true,
+            variation_stack: vec![],
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.has_error = true;
+        self.errors.push(ImportError {
+            game: self.game,
+            message,
+        });
+    }
+
+    /// Read the puzzle of a BPGN game, if it starts from a position.
+    pub fn read_bpgn_game(&mut self, game: &BpgnGame) {
+        self.game += 1;
+        if !filter::has_player(&game.headers, &self.players) {
+            return;
+        }
+
+        match bpgn_puzzle(game, self.board) {
+            Ok(Some(puzzle)) => self.puzzles.push(puzzle),
+            Ok(None) => (),
+            Err(error) => {
+                self.error(error);
+                self.skipped_games += 1;
+            },
+        }
+    }
+}
+
+impl Visitor for FENImporter {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.game += 1;
+        self.goal = None;
+        self.has_error = false;
+        self.headers.clear();
+        self.in_puzzle = false;
+        self.line.clear();
+        self.line_positions.clear();
+        self.request = None;
+        self.supported_variant = true;
+        self.variation_stack.clear();
+    }
+
+    fn comment(&mut self, comment: RawComment) {
+        if !self.in_puzzle || self.has_error || !self.variation_stack.is_empty() {
+            return;
+        }
+
+        if let Some(puzzle) = self.puzzles.last_mut() {
+            annotation::add_comment(&mut puzzle.annotations, self.line.len(), &String::from_utf8_lossy(comment.as_bytes()));
+        }
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        if !self.in_puzzle {
+            if self.has_error {
+                self.skipped_games += 1;
+            }
+            return;
+        }
+
+        if !filter::has_player(&self.headers, &self.players) {
+            self.puzzles.pop();
+            return;
+        }
+
+        if !self.supported_variant {
+            self.error("Variant not supported".to_string());
+        }
+        if self.has_error {
+            self.puzzles.pop();
+            self.skipped_games += 1;
+            return;
+        }
+
+        if let Some(puzzle) = self.puzzles.last_mut() {
+            puzzle.goal =
+                match self.goal.take() {
+                    Some(goal) => goal,
+                    None => default_goal(puzzle, &self.current_position),
+                };
+            puzzle.request = self.request.take();
+            puzzle.chapter = self.headers.iter()
+                .find(|(key, _)| key == "Chapter")
+                .map(|(_, chapter)| chapter.clone());
+            puzzle.difficulty = self.headers.iter()
+                .find(|(key, _)| key == "Difficulty")
+                .and_then(|(_, difficulty)| difficulty.parse().ok());
+            puzzle.source = game_source(&self.headers);
+        }
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader) {
+        self.headers.push((String::from_utf8_lossy(key).into_owned(), String::from_utf8_lossy(value.as_bytes()).into_owned()));
+        if key == b"Goal" {
+            match String::from_utf8_lossy(value.as_bytes()).parse() {
+                Ok(goal) => self.goal = Some(goal),
+                Err(error) => self.error(error),
+            }
+        }
+        else if key == b"Request" {
+            match parse_request(&String::from_utf8_lossy(value.as_bytes())) {
+                Ok(request) => self.request = request,
+                Err(error) => self.error(error),
+            }
+        }
+        else if key == b"Variant" {
+            let variant = value.as_bytes().to_ascii_lowercase();
+            self.supported_variant = [&b"standard"[..], b"crazyhouse", b"bughouse", b"from position"].contains(&variant.as_slice());
+        }
+        else if key == b"FEN" || key == b"BFEN" {
+            match bfen::parse(&String::from_utf8_lossy(value.as_bytes())) {
+                Ok((setup, partner)) => {
+                    self.current_position = setup.clone();
+                    self.in_puzzle = true;
+                    self.puzzles.push(Puzzle::new(setup, partner, Goal::Mate, Solution::new()));
+                },
+                Err(error) => self.error(error),
+            }
+        }
+    }
+
+    fn nag(&mut self, nag: Nag) {
+        if !self.in_puzzle || self.has_error || !self.variation_stack.is_empty() || self.line.is_empty() {
+            return;
+        }
+
+        if let Some(puzzle) = self.puzzles.last_mut() {
+            annotation::add_nag(&mut puzzle.annotations, self.line.len(), nag.0);
+        }
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        if !self.in_puzzle || self.has_error {
+            return;
+        }
+
+        match san_plus.san.to_move(&self.current_position) {
+            Ok(mov) => {
+                self.line_positions.push(self.current_position.clone());
+                self.current_position.play_unchecked(&mov);
+                self.line.push(mov.clone());
+                if self.variation_stack.is_empty() {
+                    if let Some(puzzle) = self.puzzles.last_mut() {
+                        puzzle.solution.add_line(&self.line);
+                    }
+                }
+            },
+            Err(_) => self.error(format!("Illegal move {}", san_plus)),
+        }
+    }
+
+    /// A variation replaces the last move of the current line: it is another solution or another
+    /// defense of the opponent, which becomes a branch of the solution tree.
+    fn begin_variation(&mut self) -> Skip {
+        if !self.in_puzzle || self.has_error {
+            return Skip(true);
+        }
+
+        let mut line = self.line.clone();
+        let mut line_positions = self.line_positions.clone();
+        let position =
+            match (line.pop(), line_positions.pop()) {
+                (Some(_), Some(position)) => position,
+                _ => {
+                    self.error("Variation before the first move".to_string());
+                    return Skip(true);
+                },
+            };
+        let current_position = mem::replace(&mut self.current_position, position);
+        let main_line = mem::replace(&mut self.line, line);
+        let main_positions = mem::replace(&mut self.line_positions, line_positions);
+        self.variation_stack.push((main_line, main_positions, current_position));
+        Skip(false)
+    }
+
+    fn end_variation(&mut self) {
+        if let Some((line, line_positions, position)) = self.variation_stack.pop() {
+            let variation = mem::replace(&mut self.line, line);
+            self.line_positions = line_positions;
+            self.current_position = position;
+            if !self.has_error {
+                if let Some(puzzle) = self.puzzles.last_mut() {
+                    puzzle.solution.add_line(&variation);
+                }
+            }
+        }
+    }
+}
+
+/// Goal of a puzzle without Goal header: mate if its solution ends with a mate, otherwise win the
+/// material it gains, e.g. in the crazyhouse puzzles of Lichess.
+fn default_goal(puzzle: &Puzzle, final_position: &Bughouse) -> Goal {
+    let player = puzzle.position.turn();
+    let gain = material_balance(final_position, player) - material_balance(&puzzle.position, player);
+    if final_position.is_checkmate() || puzzle.solution.is_empty() || gain <= 0 {
+        Goal::Mate
+    }
+    else {
+        Goal::WinMaterial(gain)
+    }
+}
+
+/// Puzzle of a BPGN game starting from a FEN position: the moves of board A are the solution and
+/// those of board B are the partner's moves, played in between.
+fn bpgn_puzzle(game: &BpgnGame, board: Board) -> Result<Option<Puzzle>, String> {
+    let fen =
+        match game.header("FEN").or_else(|| game.header("BFEN")) {
+            Some(fen) => fen,
+            None => return Ok(None),
+        };
+    let (board_a, board_b) = bfen::parse(fen)?;
+    // Without the position of board B, the puzzle can only be on board A.
+    let main_board = if board_b.is_some() { board } else { Board::A };
+    let mut boards = [board_a, board_b.unwrap_or_default()];
+    let position = boards[main_board.index()].clone();
+    let partner = boards[main_board.other().index()].clone();
+    let mut annotations = vec![];
+    // Time left after the first move of the player and of the opponent.
+    let mut clocks = vec![];
+    let mut messages = vec![];
+    let mut moves = vec![];
+    // Time left after the first move of each side on the partner's board, white first.
+    let mut partner_clocks = [None, None];
+    let mut partner_moves = vec![];
+    for bpgn_move in &game.moves {
+        let board = bpgn_move.board.index();
+        let partner_board = 1 - board;
+        let mov = San::from_ascii(bpgn_move.san.as_bytes()).ok()
+            .and_then(|san| san.to_move(&boards[board]).ok())
+            .ok_or_else(|| format!("Illegal move {} on board {:?}", bpgn_move.san, bpgn_move.board))?;
+        let mover = boards[board].turn();
+        if let Some(partner_position) = partner::pass_capture(&boards[board], &mov, &boards[partner_board])? {
+            boards[partner_board] = partner_position;
+        }
+        boards[board].play_unchecked(&mov);
+        // The chat is shown after the move, whichever the board.
+        let ply = moves.len() + if bpgn_move.board == main_board { 1 } else { 0 };
+        messages.extend(bpgn_move.chat.iter().map(|text| PartnerMessage {
+            ply,
+            text: text.clone(),
+        }));
+        if bpgn_move.board == main_board {
+            moves.push(mov);
+            // The comments of BPGN are mostly the clocks.
+            if let Some(ref comment) = bpgn_move.comment {
+                match parse_clock(comment.as_bytes()) {
+                    Some(clock) if moves.len() == clocks.len() + 1 && clocks.len() < 2 => clocks.push(clock),
+                    Some(_) => (),
+                    None => annotation::add_comment(&mut annotations, moves.len(), comment),
+                }
+            }
+        }
+        else {
+            let clock = &mut partner_clocks[mover.fold(0, 1)];
+            if clock.is_none() {
+                *clock = bpgn_move.comment.as_ref().and_then(|comment| parse_clock(comment.as_bytes()));
+            }
+            partner_moves.push(PartnerMove {
+                mov,
+                ply: moves.len(),
+            });
+        }
+    }
+    // The partner plays the other color.
+    let player = position.turn().fold(0, 1);
+    let partner_clock =
+        match (partner_clocks[1 - player], partner_clocks[player]) {
+            (Some(player), Some(opponent)) => Some(Clock {
+                opponent,
+                player,
+            }),
+            _ => None,
+        };
+
+    let mut puzzle = Puzzle {
+        annotations,
+        clock:
+            match clocks[..] {
+                [player, opponent] => Some(Clock {
+                    opponent,
+                    player,
+                }),
+                _ => None,
+            },
+        messages,
+        partner_clock,
+        partner_moves,
+        players: bpgn_players(game, main_board),
+        source: game_source(&game.headers),
+        ..Puzzle::new(position, Some(partner), Goal::Mate, Solution::from_line(&moves))
+    };
+    puzzle.goal =
+        match game.header("Goal") {
+            Some(goal) => goal.parse()?,
+            None => default_goal(&puzzle, &boards[main_board.index()]),
+        };
+    puzzle.difficulty = game.header("Difficulty").and_then(|difficulty| difficulty.parse().ok());
+    puzzle.request = parse_request(game.header("Request").unwrap_or(""))?;
+    Ok(Some(puzzle))
+}
+
+/// Piece of a Request header, e.g. "N", none when empty.
+fn parse_request(text: &str) -> Result<Option<Role>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    text.chars().next()
+        .and_then(|letter| Role::from_char(letter.to_ascii_lowercase()))
+        .filter(|&role| role != Role::King)
+        .map(Some)
+        .ok_or_else(|| format!("Invalid requested piece {}", text))
+}
+
+/// Players of the paired headers, e.g. WhiteA and BlackA for board A.
+fn bpgn_players(game: &BpgnGame, main_board: Board) -> Option<Players> {
+    if !["WhiteA", "BlackA", "WhiteB", "BlackB"].iter().any(|key| game.header(key).is_some()) {
+        return None;
+    }
+
+    let names = |board: Board| {
+        let name = |color| game.header(&format!("{}{:?}", color, board)).unwrap_or("?").to_string();
+        [name("White"), name("Black")]
+    };
+    Some(Players {
+        board: names(main_board),
+        main_board,
+        partner: names(main_board.other()),
+    })
+}
+
+/// Check with the engine that an imported puzzle has no refutation.
+pub fn validate(engine: &mut dyn Engine, puzzle: &Puzzle, options: Options) -> io::Result<bool> {
+    let position =
+        if options.full_opponent_pocket {
+            generator::fill_opponent_pocket(&puzzle.position)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+        }
+        else {
+            puzzle.position.clone()
+        };
+    let valid =
+        match puzzle.goal {
+            Goal::Defend(_) =>
+                match puzzle.solution.first() {
+                    Some(mov) => engine::avoids_mate(engine, &position, mov, &options.limits)?,
+                    None => false,
+                },
+            Goal::Sit => engine::all_moves_lose(engine, &position, &options.limits)?,
+            Goal::WinMaterial(_) => generator::is_sound(engine, &Puzzle {
+                position: position.clone(),
+                ..puzzle.clone()
+            })?,
+            _ => engine::is_forced(engine, &position, &puzzle.solution.main_line(), &options.limits)?,
+        };
+    match puzzle.partner {
+        Some(ref partner) if valid => {
+            let fatal = partner::is_fatal_to_partner(engine, &position, &puzzle.solution.main_line(), partner, &options.limits)?;
+            Ok(!fatal)
+        },
+        _ => Ok(valid),
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(into = "GameRecord", try_from = "GameRecord")]
+pub struct Game {
+    /// Time left to the side playing each move, after it, from the clock comments.
+    pub clocks: Vec<Option<f32>>,
+    pub moves: Vec<Move>,
+    pub partner: Option<Bughouse>,
+    /// Time left on the partner's board at the start of the game, white first, when known.
+    pub partner_clocks: [Option<f32>; 2],
+    pub position: Bughouse,
+    /// URL of the game, when known.
+    pub source: Option<String>,
+}
+
+/// Serialized form of a game, with the positions in FEN and the moves in SAN.
+#[derive(Deserialize, Serialize)]
+struct GameRecord {
+    #[serde(default)]
+    clocks: Vec<Option<f32>>,
+    fen: String,
+    moves: Vec<String>,
+    #[serde(default)]
+    partner: Option<String>,
+    #[serde(default)]
+    partner_clocks: [Option<f32>; 2],
+    #[serde(default)]
+    source: Option<String>,
+}
+
+impl From<Game> for GameRecord {
+    fn from(game: Game) -> Self {
+        Self {
+            clocks: game.clocks,
+            fen: fen::fen(&game.position),
+            moves: puzzle::to_san(&game.position, &game.moves),
+            partner: game.partner.as_ref().map(|partner| fen::fen(partner)),
+            partner_clocks: game.partner_clocks,
+            source: game.source,
+        }
+    }
+}
+
+impl TryFrom<GameRecord> for Game {
+    type Error = String;
+
+    fn try_from(record: GameRecord) -> Result<Self, Self::Error> {
+        let position = puzzle::parse_position(&record.fen)?;
+        let partner =
+            match record.partner {
+                Some(ref partner) => Some(puzzle::parse_position(partner)?),
+                None => None,
+            };
+        Ok(Self {
+            clocks: record.clocks,
+            moves: puzzle::from_san(&position, &record.moves)?,
+            partner,
+            partner_clocks: record.partner_clocks,
+            position,
+            source: record.source,
+        })
+    }
+}
+
+impl Game {
+    /// Clocks of the position before the move at `index`, known when both sides have moved.
+    pub fn clock(&self, index: usize) -> Option<Clock> {
+        if index < 2 {
+            return None;
+        }
+        Some(Clock {
+            opponent: (*self.clocks.get(index - 1)?)?,
+            player: (*self.clocks.get(index - 2)?)?,
+        })
+    }
+
+    /// Clocks of the partner's board, the partner being the player, at the start of the game. The
+    /// partner plays the other color than the player of the move at `index`.
+    pub fn partner_clock(&self, index: usize) -> Option<Clock> {
+        let turn = self.position.turn();
+        let player = if index % 2 == 0 { turn } else { !turn };
+        Some(Clock {
+            opponent: self.partner_clocks[player.fold(0, 1)]?,
+            player: self.partner_clocks[(!player).fold(0, 1)]?,
+        })
+    }
+}
+
+/// Collect full games to generate puzzles from.
+pub struct GameImporter<'a> {
+    current_position: Bughouse,
+    filter: &'a GameFilter,
+    pub games: Vec<Game>,
+    // Headers of the current game.
+    headers: Vec<(String, String)>,
+}
+
+impl<'a> GameImporter<'a> {
+    pub fn new(filter: &'a GameFilter) -> Self {
+        Self {
+            current_position: Bughouse::default(),
+            filter,
+            games: vec![],
+            headers: vec![],
+        }
+    }
+}
+
+impl<'a> Visitor for GameImporter<'a> {
+    type Result = ();
+
+    fn begin_game(&mut self) {
+        self.current_position = Bughouse::default();
+        self.headers.clear();
+        self.games.push(Game {
+            clocks: vec![],
+            moves: vec![],
+            partner: None,
+            partner_clocks: [None, None],
+            position: Bughouse::default(),
+            source: None,
+        });
+    }
+
+    fn comment(&mut self, comment: RawComment) {
+        if let Some(game) = self.games.last_mut() {
+            // The clock follows the move it is for.
+            if let Some(clock @ None) = game.clocks.last_mut() {
+                *clock = parse_clock(comment.as_bytes());
+            }
+        }
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        if self.games.last().map(|game| game.moves.is_empty()).unwrap_or(false) || !self.filter.accepts(&self.headers) {
+            self.games.pop();
+        }
+        else if let Some(game) = self.games.last_mut() {
+            game.source = game_source(&self.headers);
+        }
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader) {
+        self.headers.push((String::from_utf8_lossy(key).into_owned(), String::from_utf8_lossy(value.as_bytes()).into_owned()));
+        if key == b"FEN" || key == b"BFEN" {
+            match bfen::parse(&String::from_utf8_lossy(value.as_bytes())) {
+                Ok((setup, partner)) => {
+                    self.current_position = setup.clone();
+                    if let Some(game) = self.games.last_mut() {
+                        game.partner = partner;
+                        game.position = setup;
+                    }
+                },
+                Err(error) => eprintln!("{}", error),
+            }
+        }
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        if let Some(game) = self.games.last_mut() {
+            match san_plus.san.to_move(&self.current_position) {
+                Ok(mov) => {
+                    self.current_position.play_unchecked(&mov);
+                    game.clocks.push(None);
+                    game.moves.push(mov);
+                },
+                Err(error) => eprintln!("Error playing move: {:?}", error),
+            }
+        }
+    }
+}
+
+/// Read the games of a file which pass the `filter`.
+pub fn read_games(filename: &Path, filter: &GameFilter) -> Result<Vec<Game>, String> {
+    let pgn = read_pgn(filename)?;
+    if bpgn::is_bpgn(&pgn) {
+        let mut games = vec![];
+        for (index, game) in bpgn::parse(&pgn).iter().enumerate() {
+            if !filter.accepts(&game.headers) {
+                continue;
+            }
+            match bpgn_games(game) {
+                Ok(board_games) => games.extend(board_games),
+                Err(error) => eprintln!("Skipping BPGN game {}: {}", index + 1, error),
+            }
+        }
+        return Ok(games);
+    }
+
+    let mut importer = GameImporter::new(filter);
+    let mut reader = BufferedReader::new_cursor(pgn.as_bytes());
+    reader.read_all(&mut importer).map_err(|_| "Cannot parse PGN file")?;
+    Ok(importer.games)
+}
+
+/// Replay both boards of a BPGN game, giving the games of each board to generate puzzles from.
+///
+/// A captured piece goes to the pocket of the partner of the capturing player. Since a `Game` only
+/// starts from one position, the game of a board is cut each time the player receives a piece and
+/// continued from the position with the piece in the pocket and the partner's board at that time.
+fn bpgn_games(game: &BpgnGame) -> Result<Vec<Game>, String> {
+    let mut boards =
+        match game.header("FEN").or_else(|| game.header("BFEN")) {
+            Some(fen) => {
+                let (board_a, board_b) = bfen::parse(fen)?;
+                [board_a, board_b.unwrap_or_default()]
+            },
+            None => [Bughouse::default(), Bughouse::default()],
+        };
+    // Last time left to each side of each board, white first.
+    let mut clocks = [[None, None], [None, None]];
+    let mut games = vec![];
+    let mut current_games: [Option<Game>; 2] = [None, None];
+    for bpgn_move in &game.moves {
+        let board = bpgn_move.board.index();
+        let partner_board = 1 - board;
+        let mov = San::from_ascii(bpgn_move.san.as_bytes()).ok()
+            .and_then(|san| san.to_move(&boards[board]).ok())
+            .ok_or_else(|| format!("Illegal move {} on board {:?}", bpgn_move.san, bpgn_move.board))?;
+
+        let mut game =
+            match current_games[board].take() {
+                Some(game) => game,
+                None => Game {
+                    clocks: vec![],
+                    moves: vec![],
+                    partner: Some(boards[partner_board].clone()),
+                    partner_clocks: clocks[partner_board],
+                    position: boards[board].clone(),
+                    source: game_source(&game.headers),
+                },
+            };
+        let clock = bpgn_move.comment.as_ref().and_then(|comment| parse_clock(comment.as_bytes()));
+        if clock.is_some() {
+            clocks[board][boards[board].turn().fold(0, 1)] = clock;
+        }
+        let received = partner::pass_capture(&boards[board], &mov, &boards[partner_board])?;
+        boards[board].play_unchecked(&mov);
+        game.clocks.push(clock);
+        game.moves.push(mov);
+        current_games[board] = Some(game);
+
+        if let Some(partner_position) = received {
+            boards[partner_board] = partner_position;
+            games.extend(current_games[partner_board].take());
+        }
+    }
+    games.extend(current_games.iter_mut().filter_map(Option::take));
+    Ok(games)
+}
+
+/// URL of a game, from its headers: the Link header of Lichess and chess.com, a Site which is a
+/// URL or the number of the game in BughouseDB.
+fn game_source(headers: &[(String, String)]) -> Option<String> {
+    let header = |name: &str| headers.iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.trim());
+    let is_url = |value: &&str| value.starts_with("http://") || value.starts_with("https://");
+    header("Link").filter(is_url)
+        .or_else(|| header("Site").filter(is_url))
+        .map(str::to_string)
+        .or_else(|| header("BughouseDBGameNo").filter(|number| !number.is_empty()).map(bughousedb::game_url))
+}
+
+pub enum PgnSource {
+    File(PathBuf),
+    Text(String),
+}
+
+impl PgnSource {
+    fn name(&self) -> String {
+        match *self {
+            PgnSource::File(ref filename) => filename.display().to_string(),
+            PgnSource::Text(_) => "Downloaded PGN".to_string(),
+        }
+    }
+}
+
+pub enum ImportEvent {
+    /// A source which cannot be read.
+    Error(String),
+    Finished,
+    /// Games read in the current source, with what was found since the previous event.
+    Progress {
+        /// Problems of the skipped games.
+        errors: Vec<String>,
+        games: usize,
+        puzzles: Vec<Puzzle>,
+        skipped_games: usize,
+        /// Estimated number of games of the current source.
+        total_games: usize,
+    },
+    /// The source of this name was read, including when the import was cancelled while reading it.
+    SourceRead(String),
+}
+
+/// Read the puzzles of the `sources` from games of the `players`, or of anyone when empty,
+/// sending them in batches while the games are parsed, until `cancelled` is set. This can take a
+/// while for big databases: it should not run in the GUI thread. The puzzles of BPGN games are
+/// played from `board`.
+pub fn stream_puzzles<F: Fn(ImportEvent)>(sources: Vec<PgnSource>, players: &[String], board: Board, cancelled: &AtomicBool,
+    send: F)
+{
+    for source in sources {
+        let name = source.name();
+        let pgn =
+            match source {
+                PgnSource::File(filename) =>
+                    match read_pgn(&filename) {
+                        Ok(pgn) => pgn,
+                        Err(error) => {
+                            send(ImportEvent::Error(format!("{}: {}", name, error)));
+                            continue;
+                        },
+                    },
+                PgnSource::Text(pgn) => pgn,
+            };
+        let total_games = pgn.lines().filter(|line| line.starts_with("[Event ")).count();
+        let progress = |importer: &mut FENImporter, games| ImportEvent::Progress {
+            errors: importer.errors.drain(..)
+                .map(|error| format!("{}, game {}: {}", name, error.game, error.message))
+                .collect(),
+            games,
+            puzzles: importer.puzzles.drain(..).collect(),
+            skipped_games: mem::replace(&mut importer.skipped_games, 0),
+            total_games,
+        };
+        let mut importer = FENImporter::new(players.to_vec(), board);
+        let mut games = 0;
+        if bpgn::is_bpgn(&pgn) {
+            for game in bpgn::parse(&pgn) {
+                if cancelled.load(Ordering::SeqCst) {
+                    break;
+                }
+                importer.read_bpgn_game(&game);
+                games += 1;
+                if games % PROGRESS_GAMES == 0 {
+                    send(progress(&mut importer, games));
+                }
+            }
+        }
+        else {
+            let mut reader = BufferedReader::new_cursor(pgn.as_bytes());
+            loop {
+                if cancelled.load(Ordering::SeqCst) {
+                    break;
+                }
+                match reader.read_game(&mut importer) {
+                    Ok(Some(())) => games += 1,
+                    Ok(None) => break,
+                    Err(error) => {
+                        send(ImportEvent::Error(format!("{}: cannot parse PGN file: {}", name, error)));
+                        break;
+                    },
+                }
+                if games % PROGRESS_GAMES == 0 {
+                    send(progress(&mut importer, games));
+                }
+            }
+        }
+        // The puzzles already read are kept when cancelled.
+        send(progress(&mut importer, games));
+        send(ImportEvent::SourceRead(name));
+        if cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    send(ImportEvent::Finished);
+}
+
+/// Find the PGN and BPGN files, possibly compressed, in `dir` and its subdirectories, sorted by path.
+pub fn find_pgn_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir).map_err(|error| format!("Cannot read {}: {}", dir.display(), error))?;
+        for entry in entries {
+            let path = entry.map_err(|error| error.to_string())?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            }
+            else if archive::is_pgn_file(&path.to_string_lossy()) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn read_pgn(filename: &Path) -> Result<String, String> {
+    let mut file = File::open(filename).map_err(|error| error.to_string())?;
+    let mut data = vec![];
+    file.read_to_end(&mut data).map_err(|error| error.to_string())?;
+    Ok(charset::decode(&archive::decompress(data)?))
+}
+
+/// Parse the time left in a move comment, either in seconds or minutes and seconds as in BPGN
+/// (e.g. "{118.9}" or "{C:1:58.9}") or as a "[%clk 0:01:58.9]" command.
+fn parse_clock(comment: &[u8]) -> Option<f32> {
+    let comment = String::from_utf8_lossy(comment);
+    let comment = comment.trim();
+    let time =
+        match comment.find("[%clk ") {
+            Some(start) => {
+                let start = start + "[%clk ".len();
+                let end = start + comment[start..].find(']')?;
+                &comment[start..end]
+            },
+            None => comment.trim_start_matches("C:"),
+        };
+    time.trim()
+        .split(':')
+        .try_fold(0.0, |seconds, part| part.parse::<f32>().ok().map(|part| seconds * 60.0 + part))
+}
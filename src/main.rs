@@ -18,8 +18,6 @@
  * Find puzzles where you can force capture piece (i.e. checked fork, pawn on b7 with unmovable
  * rook on a8, …).
  *
- * Add buttons to ask for the piece you need (request only before first move?).
- *
  * Add button for sit (the solution would be sit when any move will make your partner lose).
  *
  * Have puzzles where the solution is to survive the longest (when most leads to checkmate).
@@ -30,174 +28,1053 @@
  * as a solution.
  */
 
+extern crate bzip2;
+extern crate cairo;
 extern crate chessground;
 extern crate encoding_rs;
+extern crate flate2;
 extern crate gdk;
+extern crate glib;
 extern crate gtk;
+extern crate num_cpus;
 extern crate pgn_reader;
 extern crate relm;
 extern crate relm_derive;
+extern crate serde;
+extern crate serde_json;
 extern crate shakmaty;
+extern crate ureq;
+extern crate zip;
+
+mod annotation;
+mod archive;
+mod bfen;
+mod bpgn;
+mod charset;
+mod course;
+mod daily;
+mod data;
+mod difficulty;
+mod download;
+mod engine;
+mod export;
+mod filter;
+mod generator;
+mod hash;
+mod image;
+mod importer;
+mod material;
+mod pack;
+mod partner;
+mod patterns;
+mod puzzle;
+mod puzzle_set;
+mod quality;
+mod queue;
+mod recent;
+mod remote;
+mod review;
+mod rush;
+mod settings;
+mod solution;
+mod stats;
+mod timeline;
+mod worker;
+mod zobrist;
 
+use std::cell::RefCell;
 use std::cmp::min;
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::fs;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use chessground::{
     DrawBrush,
-    DrawShape,
     Ground,
-    GroundMsg::{SetOrientation, SetPockets, SetPos, UserDrop, UserMove},
+    GroundMsg::{SetOrientation, SetPockets, SetPos, SetShapes, UserDrop, UserMove},
     Pos,
 };
 use gtk::{
+    Adjustment,
+    Align,
+    BoxExt,
+    Button,
     ButtonExt,
     ButtonsType,
+    CheckButton,
+    Clipboard,
+    ComboBoxExt,
+    ComboBoxText,
+    ComboBoxTextExt,
+    ContainerExt,
+    Dialog,
     DialogExt,
     DialogFlags,
+    Entry,
+    EntryExt,
     FileChooserAction,
     FileChooserDialog,
     FileChooserExt,
+    FrameExt,
+    Grid,
+    GridExt,
+    GtkMenuItemExt,
     Inhibit,
+    Label,
     LabelExt,
+    Menu,
+    MenuItem,
+    MenuShellExt,
+    MenuToolButtonExt,
     MessageDialog,
     MessageType,
     OrientableExt,
-    Orientation::Vertical,
+    OverlayExt,
+    ProgressBarExt,
+    Orientation::{Horizontal, Vertical},
     ResponseType,
+    ScrolledWindow,
+    ShadowType,
+    SpinButton,
+    SpinButtonExt,
+    TextBufferExt,
+    TextView,
+    TextViewExt,
+    ToggleButtonExt,
+    ToggleToolButtonExt,
     ToolButtonExt,
     WidgetExt,
 };
-use pgn_reader::{
-    BufferedReader,
-    RawHeader,
-    SanPlus,
-    Visitor,
-};
-use relm::{Relm, Widget, timeout};
+use relm::{Channel, Relm, Sender, Widget, timeout};
 use relm_derive::{Msg, widget};
 use shakmaty::{
-    Board,
-    fen::Fen,
-    FromSetup,
+    Color,
     Material,
     Move,
     Piece,
     Position,
     position::Bughouse,
     Role,
+    san::San,
     Setup,
     Square,
 };
 
+use annotation::Shape;
+use bpgn::Board;
+use course::CourseProgress;
+use daily::{DailyPuzzle, DailyStats};
+use engine::{Cache, DiscoveredEngine, EngineConfig, EngineOptions, Score, SearchLimits, SharedCache, WINNING_SCORE};
+use filter::{self, GameFilter};
+use importer::{Game, ImportEvent, PgnSource};
+use material::POCKET_ROLES;
+use pack::{Installation, Manifest};
+use partner::Boards;
+use puzzle::{Goal, Puzzle};
+use queue::Queue;
+use recent::RecentFiles;
+use review::ReviewQueue;
+use rush::{BestScores, Rush};
+use settings::{Corner, PartnerBoardLayout, Settings};
+use solution::Solution;
+use stats::PuzzleStats;
+use timeline::Timeline;
+use worker::{Request, Response, Worker};
 use self::Msg::*;
 
+// Interval between the updates of the clock of the puzzle, in milliseconds.
+const CLOCK_TICK: u32 = 250;
+// Pieces offered for a promotion, in the order of the buttons of the dialog.
+const PROMOTION_ROLES: [Role; 4] = [Role::Queen, Role::Rook, Role::Bishop, Role::Knight];
+
 #[derive(Msg)]
 pub enum Msg {
+    AnalysisResponse(usize, Response),
+    /// Go to the next unsolved puzzle after solving the puzzle at this index.
+    AutoAdvance(usize),
+    BackToPuzzle,
+    CancelImport,
+    /// Update the clock of the puzzle, if it is still the clock of this id.
+    ClockTick(usize),
+    /// Play on the slower mate found by the user, against the engine.
+    ContinueLine,
+    CopyBfen,
+    /// Go to the puzzle of the day.
+    DailyPuzzle,
+    DownloadChessComArchives,
+    DownloadChessComGames,
+    DownloadGames,
+    Downloaded(Result<Download, String>),
+    EnginesDiscovered(Vec<DiscoveredEngine>),
+    /// Move of the user written in SAN.
+    EnterMove(String),
+    EvaluationResponse(usize, Response),
+    ExportAnki,
+    ExportImage,
+    ExportPack,
+    GeneratePuzzles,
+    /// Response of the generator of this id.
+    GeneratorResponse(usize, usize, Response),
+    Hint,
+    ImportFiles(Vec<PathBuf>),
+    ImportFolder,
+    ImportLichessStudy,
     ImportPGN,
+    ImportPgnFromUrl,
+    ImportRecent(usize),
+    Imported(ImportEvent),
+    InstallPack,
+    JobResponse(usize, Response),
+    KeepPlaying,
+    KeyPress(gdk::EventKey),
     MovePlayed(Square, Square, Option<Role>),
     NextPuzzle,
+    /// Show the chapters of the training course, to start one of them.
+    OpenCourse,
+    OpenSettings,
+    OpenSource,
+    PartnerMovePlayed(Square, Square, Option<Role>),
+    PartnerPieceDrop(Piece, Square),
+    PasteBfen,
     PieceDrop(Piece, Square),
-    PlayOpponentMove,
+    PlayOpponentMove(usize),
+    PlayPartnerDefense,
+    PlayPartnerMate,
     PreviousPuzzle,
+    ProveMeWrong,
     Quit,
+    RemoteWorkerConnected(TcpStream),
+    RequestPiece(Role),
+    Retry,
+    /// Serve the next puzzle of the puzzle rush.
+    RushNext,
+    /// Update the time left in the puzzle rush, every second.
+    RushTick,
+    SavePuzzles,
+    SetAppendImports(bool),
+    SetBlindfold(bool),
+    /// Ask the chapter of the training course of the current puzzle.
+    SetChapter,
+    SetDropsOnly(bool),
+    SetEngineReplies(bool),
+    SetFullOpponentPocket(bool),
+    SetPatternsOnly(bool),
+    SetPictureInPicture(bool),
+    SetReviewing(bool),
+    ShowRefutation,
+    ShowSolution,
+    /// Play the next move of the solution being shown.
+    ShowSolutionMove,
+    Sit,
+    SortByDifficulty,
+    StartRush,
+    StartStreak,
+    SwitchBoard,
+    ToggleAnalysis(bool),
+    ToggleEvaluation(bool),
+    ToggleGenerationPause,
+    TogglePartnerBoard(bool),
+    Undo,
+}
+
+/// Puzzle state saved while the user plays against the engine, either to see why their wrong move
+/// fails or to keep playing after solving the puzzle.
+struct Demonstration {
+    played: Vec<Move>,
+    position: Bughouse,
+    // Whether the user keeps playing after solving the puzzle.
+    sparring: bool,
+}
+
+/// Puzzle state saved while the user moves the pieces of both sides freely to analyze the
+/// position, restored at the end of the analysis.
+struct FreeAnalysis {
+    boards: Boards,
+    // Time left on the clock of the puzzle, which is paused during the analysis.
+    clock_left: Option<Duration>,
+    // Moves of the analysis, which can all be taken back.
+    moves: Vec<Move>,
+    played: Vec<Move>,
+    // Whether the opponent's reply was about to be played.
+    reply_pending: bool,
+}
+
+/// Move entered before the opponent's reply, played as soon as the reply is on the board.
+#[derive(Clone, Copy)]
+enum Premove {
+    Drop(Role, Square),
+    Move(Square, Square, Option<Role>),
+}
+
+/// Downloaded games to generate puzzles from, or puzzles to import.
+pub enum Download {
+    Games(Vec<PathBuf>),
+    Puzzles(Vec<PathBuf>),
+    /// PGN of puzzles downloaded in memory.
+    PuzzlesPgn(String),
+}
+
+/// Puzzles being read from PGN files on another thread.
+struct Import {
+    _channel: Channel<ImportEvent>,
+    // Whether the puzzles are appended to the current ones.
+    append: bool,
+    cancelled: Arc<AtomicBool>,
+    errors: Vec<String>,
+    puzzles: Vec<Puzzle>,
+    skipped_games: usize,
+    // Puzzles found in the source being read.
+    source_puzzles: usize,
+    // Puzzles found in each source read, e.g. "games.pgn: 12 puzzles".
+    sources: Vec<String>,
 }
 
-#[derive(Clone)]
-struct TrainingPosition {
-    annotations: Vec<Shape>,
-    position: Board,
+/// Engine analyzing the games of the queue, on this machine or on a remote worker.
+struct Generator {
+    // Number of the game being analyzed.
+    game: Option<usize>,
+    id: usize,
+    remote: bool,
+    worker: Worker,
 }
 
 pub struct Model {
+    _discovery: Channel<Vec<DiscoveredEngine>>,
+    _downloads: Channel<Result<Download, String>>,
+    // Engine analysis bound to the current puzzle.
+    analysis: Worker,
+    // Whether imported puzzles are added to the current ones instead of replacing them.
+    append_imports: bool,
+    // Best scores of the puzzle rushes.
+    best_scores: BestScores,
+    // Whether the board of the user is hidden, the moves being written in SAN.
+    blindfold: bool,
+    // Pieces of the position of the puzzle and last move, shown when the board is hidden.
+    blindfold_status: String,
+    // The board of the puzzle and the partner's board, whose pockets feed each other.
+    boards: Boards,
+    cache: SharedCache,
     can_play: bool,
-    current_move: usize,
-    current_position: Bughouse,
+    // Whether the user can play the partner's mate enabled by the solution of the puzzle.
+    can_play_partner_mate: bool,
+    // Whether the user can still ask the partner for a piece, before the first move.
+    can_request: bool,
+    // When the clock of the current puzzle runs out, while it runs.
+    clock_deadline: Option<Instant>,
+    // Identifies the clock of the current puzzle, to ignore the ticks of the previous ones.
+    clock_id: usize,
+    // Time left to solve the puzzle.
+    clock_status: String,
+    // Chapter of the training course whose puzzles are served, if any.
+    course_chapter: Option<String>,
+    // Puzzles of the course solved, in this run or the previous ones.
+    course_progress: CourseProgress,
+    // Whether the success of the current attempt is counted, to count it once when the last moves
+    // are taken back and played again.
+    credited: bool,
     current_puzzle: usize,
+    // Days the puzzle of the day was solved in a row.
+    daily_stats: DailyStats,
+    // Explanation of the puzzle of the day, with the daily streak.
+    daily_tooltip: String,
+    // Set while the user plays against the engine from a wrong move, to come back to the puzzle.
+    demonstration: Option<Demonstration>,
+    // Gives the downloaded game files to the GUI thread.
+    download_sender: Sender<Result<Download, String>>,
+    // Whether only the puzzles whose key move is a drop are served.
+    drops_only: bool,
+    // Whether the engine chooses the opponent's replies instead of following the solution.
+    engine_replies: bool,
+    // Engines found on this machine, offered in the settings.
+    engines: Vec<DiscoveredEngine>,
+    evaluation: Worker,
+    // Share of the evaluation bar for the player, between 0 and 1.
+    evaluation_fraction: f64,
+    evaluation_text: String,
+    // Whether the current puzzle failed, after which the board is locked until it is retried.
+    failed: bool,
+    // Set while the user moves the pieces of both sides freely, to come back to the puzzle.
+    free_analysis: Option<FreeAnalysis>,
+    generation_paused: bool,
+    generation_status: String,
+    generator_options: generator::Options,
+    // Engines analyzing games in parallel.
+    generators: Vec<Generator>,
+    // Remaining moves of the goal, e.g. "Mate in 3".
+    goal_status: String,
+    // Hints shown for the next move: 1 for the piece to move, 2 for the whole move.
+    hint_level: u8,
+    import: Option<Import>,
+    // Share of the games of the current file read by the import.
+    import_fraction: f64,
+    import_status: String,
+    // Puzzle validation.
+    jobs: Worker,
+    // Last move played on the board of the user, in SAN.
+    last_move: String,
+    // Accepts the connections of remote workers.
+    listener: Option<Channel<TcpStream>>,
+    // Moves played in the current puzzle with their annotations.
+    move_list: String,
+    next_generator_id: usize,
+    // Whether the user left the scripted solution, in which case the engine plays the opponent.
+    off_script: bool,
+    // Clocks of the partner's board, when known.
+    partner_clock: String,
+    // Whether the moves are played on the partner's board instead of the user's board.
+    partner_focused: bool,
+    // Number of moves of the partner's mate played while the user plays it against the scripted
+    // defense, after solving the puzzle.
+    partner_mate_played: Option<usize>,
+    // Messages of the partner so far in the puzzle.
+    partner_messages: String,
+    // Whether the partner's board is over a corner of the board of the user.
+    partner_overlaid: bool,
+    // Number of the partner's moves of the puzzle played on their board.
+    partner_played: usize,
+    // Moves played in the current puzzle, by both sides.
+    played: Vec<Move>,
+    // Pieces in the pocket of the user, shown in large while training the drops.
+    pocket_status: String,
+    premove: Option<Premove>,
+    // Ids of the puzzles, computed when first needed since hashing a puzzle replays its solution.
+    // Cleared when the puzzles move.
+    puzzle_ids: RefCell<Vec<Option<String>>>,
+    puzzle_info: String,
+    // Statistics of the puzzles, in this run or the previous ones.
+    puzzle_stats: PuzzleStats,
     puzzles: Vec<Puzzle>,
+    queue: Queue,
+    recent_files: RecentFiles,
+    refutation: String,
     relm: Relm<Win>,
+    // Identifies the opponent's reply scheduled last, to ignore the replies scheduled before the
+    // puzzle was reset.
+    reply_id: usize,
+    // Whether the opponent's reply is about to be played, during which the user can premove.
+    reply_pending: bool,
+    // Piece asked to the partner in the current puzzle.
+    requested: Option<Role>,
+    // Failed puzzles to solve twice in a row.
+    review_queue: ReviewQueue,
+    // Explanation of the review of the mistakes, with the number of puzzles to review.
+    review_tooltip: String,
+    // Whether only the puzzles of the review queue are served.
+    reviewing: bool,
+    // Puzzle rush in progress.
+    rush: Option<Rush>,
+    // Score, mistakes and time left of the puzzle rush.
+    rush_status: String,
+    // Moves of the player found before the first mistake, and moves of the player in the solution,
+    // once the attempt is over or has a mistake.
+    score: Option<(usize, usize)>,
+    settings: Settings,
+    show_evaluation: bool,
+    // Whether the moves of the solution are being played to show it to the user.
+    showing_solution: bool,
+    // Move of the user forcing a slower mate than the solution, which they can play on.
+    slower_mate: Option<Move>,
+    solved: bool,
+    // URL of the game of the current puzzle.
+    source_url: Option<String>,
+    // Explanation of the streak mode, with the longest streak.
+    streak_tooltip: String,
     text: &'static str,
-}
-
-#[derive(Clone)]
-struct Shape {
-    orig: Square,
-    dest: Square,
-    brush: DrawBrush,
-}
-
-impl PartialEq<DrawShape> for Shape {
-    fn eq(&self, rhs: &DrawShape) -> bool {
-        self.orig == rhs.orig() && self.dest == rhs.dest() && self.brush == rhs.brush()
-    }
+    // Pieces arriving in the pockets during the current puzzle, shared with the drawing of the
+    // timeline.
+    timeline: Rc<RefCell<Timeline>>,
+    // Where the last captured piece went, from one board to the other.
+    transfer_status: String,
+    // Piece and file typed so far of a drop entered with the keyboard, e.g. "P", "e" and "6".
+    typed_drop: Option<(Role, Option<char>)>,
+    // Whether the puzzles being validated are appended to the current ones.
+    validation_appends: bool,
+    wrong_move: Option<Move>,
 }
 
 #[widget]
 impl Widget for Win {
-    fn model(relm: &Relm<Self>, _: ()) -> Model {
+    /// The `files` given on the command line are imported on startup.
+    fn model(relm: &Relm<Self>, files: Vec<PathBuf>) -> Model {
+        let settings = Settings::load();
+        let cache = Arc::new(Mutex::new(Cache::load(&settings.engine_fingerprint(), settings.cache_size())));
+        let stream = relm.stream().clone();
+        let analysis = Worker::new(cache.clone(), move |generation, response| stream.emit(AnalysisResponse(generation, response)));
+        let stream = relm.stream().clone();
+        let jobs = Worker::new(cache.clone(), move |generation, response| stream.emit(JobResponse(generation, response)));
+        let stream = relm.stream().clone();
+        let evaluation = Worker::new(cache.clone(),
+            move |generation, response| stream.emit(EvaluationResponse(generation, response)));
+        for worker in &[&analysis, &evaluation, &jobs] {
+            worker.configure(&settings);
+        }
+        let generators: Vec<_> = (0..settings.generator_count())
+            .map(|id| local_generator(relm, &cache, id, &settings))
+            .collect();
+        let listener = settings.remote_port.and_then(|port| remote_listener(relm, port));
+        let stream = relm.stream().clone();
+        let (discovery, sender) = Channel::new(move |engines| stream.emit(EnginesDiscovered(engines)));
+        thread::spawn(move || {
+            let _ = sender.send(engine::discover());
+        });
+        let stream = relm.stream().clone();
+        let (downloads, download_sender) = Channel::new(move |result| stream.emit(Downloaded(result)));
+        let next_generator_id = generators.len();
+        let mut generator_options = generator::Options::default();
+        generator_options.limits = settings.generation;
+        generator_options.min_quality = settings.min_quality;
+        // A generation left unfinished stays paused until the user resumes it.
+        let queue = Queue::load();
+        let generation_paused = !queue.is_empty();
+        if !files.is_empty() {
+            relm.stream().emit(ImportFiles(files));
+        }
         Model {
+            _discovery: discovery,
+            _downloads: downloads,
+            analysis,
+            append_imports: false,
+            best_scores: BestScores::load(),
+            blindfold: false,
+            blindfold_status: String::new(),
+            boards: Boards::default(),
+            cache,
             can_play: true,
-            current_move: 0,
-            current_position: Bughouse::default(),
+            can_play_partner_mate: false,
+            can_request: true,
+            clock_deadline: None,
+            clock_id: 0,
+            clock_status: String::new(),
+            course_chapter: None,
+            course_progress: CourseProgress::load(),
+            credited: false,
             current_puzzle: 0,
-            puzzles: vec![],
+            daily_stats: DailyStats::load(),
+            daily_tooltip: String::new(),
+            demonstration: None,
+            download_sender,
+            drops_only: false,
+            engine_replies: false,
+            engines: vec![],
+            evaluation,
+            evaluation_fraction: 0.5,
+            evaluation_text: String::new(),
+            failed: false,
+            free_analysis: None,
+            generation_paused,
+            generation_status: generation_status(&queue, generation_paused),
+            generator_options,
+            generators,
+            goal_status: String::new(),
+            hint_level: 0,
+            import: None,
+            import_fraction: 0.0,
+            import_status: String::new(),
+            jobs,
+            last_move: String::new(),
+            listener,
+            move_list: String::new(),
+            next_generator_id,
+            off_script: false,
+            partner_clock: String::new(),
+            partner_focused: false,
+            partner_mate_played: None,
+            partner_messages: String::new(),
+            partner_overlaid: false,
+            partner_played: 0,
+            played: vec![],
+            pocket_status: String::new(),
+            premove: None,
+            puzzle_ids: RefCell::new(vec![]),
+            puzzle_info: String::new(),
+            puzzle_stats: PuzzleStats::load(),
+            puzzles: queue.puzzles.clone(),
+            queue,
+            recent_files: RecentFiles::load(),
+            refutation: String::new(),
             relm: relm.clone(),
+            reply_id: 0,
+            reply_pending: false,
+            requested: None,
+            review_queue: ReviewQueue::load(),
+            review_tooltip: String::new(),
+            reviewing: false,
+            rush: None,
+            rush_status: String::new(),
+            score: None,
+            settings,
+            show_evaluation: false,
+            showing_solution: false,
+            slower_mate: None,
+            solved: false,
+            source_url: None,
+            streak_tooltip: String::new(),
             text: "",
+            timeline: Rc::new(RefCell::new(Timeline::default())),
+            transfer_status: String::new(),
+            typed_drop: None,
+            validation_appends: false,
+            wrong_move: None,
+        }
+    }
+
+    fn init_view(&mut self) {
+        self.partner_ground.widget().set_valign(Align::Start);
+        self.apply_partner_layout();
+        let timeline = self.model.timeline.clone();
+        self.timeline.connect_draw(move |area, context| {
+            let width = f64::from(area.get_allocated_width());
+            let height = f64::from(area.get_allocated_height());
+            timeline.borrow().draw(context, width, height);
+            Inhibit(false)
+        });
+        self.update_recent_menu();
+        self.update_streak_tooltip();
+        self.update_daily_tooltip();
+        self.update_review_tooltip();
+        self.restore_puzzle_stats(0);
+        // Puzzles found by a previous unfinished generation, starting with the puzzle of the day.
+        if let Some(index) = self.daily_puzzle() {
+            self.model.current_puzzle = index;
+            self.show_position();
         }
     }
 
     fn update(&mut self, event: Msg) {
         match event {
+            AnalysisResponse(generation, response) => {
+                if !self.model.analysis.is_current(generation) {
+                    return;
+                }
+
+                match response {
+                    Response::BestMove(mov, score) => {
+                        if self.model.demonstration.is_some() {
+                            self.play_demonstration_reply(&mov, score.map(Score::negate));
+                        }
+                        else {
+                            self.play_opponent_move(&mov);
+                        }
+                    },
+                    Response::Error(error) => {
+                        eprintln!("Engine error: {}", error);
+                        self.model.can_play = true;
+                        self.model.text = "";
+                    },
+                    Response::MoveChecked { correct, fatal_to_partner, mov, slower_mate } => {
+                        self.model.can_play = true;
+                        // A slower mate still wins: it is not a mistake.
+                        if slower_mate {
+                            self.model.slower_mate = Some(mov);
+                            self.model.text = "You found a mate, but not the fastest: look for a faster one or continue your line";
+                        }
+                        else if !correct {
+                            self.model.wrong_move = Some(mov);
+                            self.wrong_answer("Wrong answer");
+                        }
+                        else if fatal_to_partner {
+                            self.model.wrong_move = Some(mov);
+                            self.wrong_answer("Wrong answer: this gives your partner's opponent a mating piece");
+                        }
+                        else {
+                            self.model.text = "";
+                            self.model.off_script = true;
+                            self.play_move(&mov);
+                        }
+                    },
+                    Response::Evaluation(_) | Response::Generated(_) | Response::Puzzles(_) => (),
+                    Response::Refutation { pv, score } => {
+                        self.model.refutation =
+                            match score {
+                                Some(score) => format!("{} ({})", pv.join(" "), score),
+                                None => pv.join(" "),
+                            };
+                    },
+                }
+            },
+            ContinueLine => {
+                if !self.model.can_play || self.model.rush.is_some() {
+                    return;
+                }
+
+                if let Some(mov) = self.model.slower_mate.clone() {
+                    self.model.slower_mate = None;
+                    self.model.text = "";
+                    self.model.off_script = true;
+                    self.play_move(&mov);
+                }
+            },
+            CopyBfen => {
+                let bfen = bfen::format(&self.model.boards.position, self.model.boards.partner.as_ref());
+                Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&bfen);
+            },
+            AutoAdvance(index) => {
+                let stays = index != self.model.current_puzzle || !self.model.solved ||
+                    self.model.demonstration.is_some() || self.model.partner_mate_played.is_some();
+                if !stays {
+                    if self.model.reviewing {
+                        self.next_review_puzzle();
+                    }
+                    else {
+                        self.next_unsolved_puzzle();
+                    }
+                }
+            },
+            BackToPuzzle => {
+                if let Some(demonstration) = self.model.demonstration.take() {
+                    self.model.analysis.cancel();
+                    self.model.boards.position = demonstration.position;
+                    self.model.played = demonstration.played;
+                    self.model.can_play = !self.model.solved && !self.model.failed;
+                    self.model.refutation.clear();
+                    self.model.text = if self.model.solved { "Success" } else { "" };
+                    self.update_ground();
+                    self.update_goal_status();
+                    self.evaluate();
+                }
+            },
+            DownloadChessComArchives => {
+                if let Some(username) = self.ask_text("Download games from chess.com", "Username") {
+                    self.download("Downloading games…", move || download::chesscom::download_player(&username).map(Download::Games));
+                }
+            },
+            DownloadChessComGames => {
+                let ids =
+                    match self.ask_text("Download games from chess.com", "Game id, or range of ids like 1234-1250") {
+                        Some(text) => text,
+                        None => return,
+                    };
+                match download::chesscom::parse_ids(&ids) {
+                    Ok(ids) => self.download("Downloading games…", move || download::chesscom::download(ids).map(|path| Download::Games(vec![path]))),
+                    Err(error) => self.show_error(&error),
+                }
+            },
+            DownloadGames => {
+                if let Some(query) = self.ask_download_query() {
+                    self.download("Downloading games…", move || download::bughousedb::download(&query).map(|path| Download::Games(vec![path])));
+                }
+            },
+            Downloaded(result) => {
+                self.model.text = "";
+                match result {
+                    Ok(Download::Games(filenames)) => self.queue_games(filenames),
+                    Ok(Download::Puzzles(filenames)) =>
+                        self.import(filenames.into_iter().map(PgnSource::File).collect(), self.model.append_imports),
+                    Ok(Download::PuzzlesPgn(pgn)) => self.import(vec![PgnSource::Text(pgn)], self.model.append_imports),
+                    Err(error) => self.show_error(&error),
+                }
+            },
+            EnginesDiscovered(engines) => self.model.engines = engines,
+            EnterMove(text) => {
+                self.move_entry.set_text("");
+                if !self.model.can_play && !self.model.partner_focused {
+                    return;
+                }
+
+                let position =
+                    if self.model.partner_focused {
+                        self.model.boards.partner.clone()
+                    }
+                    else {
+                        Some(self.model.boards.position.clone())
+                    };
+                let mov =
+                    match position.and_then(|position| puzzle::parse_san(&position, text.trim()).ok()) {
+                        Some(mov) => mov,
+                        None => {
+                            self.model.text = "Invalid move: write a legal move in SAN, e.g. Nxf7+ or N@f3";
+                            return;
+                        },
+                    };
+                if self.model.partner_focused {
+                    self.try_partner_move(Some(mov));
+                }
+                else {
+                    self.model.text = "";
+                    self.try_move(Some(&mov));
+                }
+            },
+            EvaluationResponse(generation, response) => {
+                if !self.model.evaluation.is_current(generation) {
+                    return;
+                }
+
+                if let Response::Evaluation(Some(score)) = response {
+                    let player = self.current_player();
+                    let score =
+                        if self.model.boards.position.turn() == player {
+                            score
+                        }
+                        else {
+                            score.negate()
+                        };
+                    self.set_evaluation(score);
+                }
+            },
+            GeneratePuzzles => {
+                let filenames = self.choose_files("Select games to generate puzzles from");
+                self.queue_games(filenames);
+            },
+            GeneratorResponse(id, generation, response) => {
+                let index =
+                    match self.model.generators.iter().position(|generator| generator.id == id) {
+                        Some(index) if self.model.generators[index].worker.is_current(generation) => index,
+                        _ => return,
+                    };
+
+                let game =
+                    match self.model.generators[index].game.take() {
+                        Some(game) => game,
+                        None => return,
+                    };
+                match response {
+                    Response::Error(error) => {
+                        self.model.queue.fail(game);
+                        // A remote worker is disconnected on error: its game goes to the other
+                        // generators.
+                        if self.model.generators[index].remote {
+                            eprintln!("{}", error);
+                            self.model.generators.remove(index);
+                            self.generate_next();
+                        }
+                        // Only report the first error when all the generators fail.
+                        else if self.model.generation_paused {
+                            eprintln!("Generation error: {}", error);
+                        }
+                        else {
+                            self.model.generation_paused = true;
+                            self.update_generation_status();
+                            self.show_error(&error);
+                        }
+                    },
+                    Response::Generated(puzzles) => {
+                        let puzzles = self.model.queue.complete(game, puzzles);
+                        self.save_queue();
+                        let was_empty = self.model.puzzles.is_empty();
+                        let start = self.model.puzzles.len();
+                        self.model.puzzles.extend(puzzles);
+                        self.restore_puzzle_stats(start);
+                        if was_empty {
+                            self.show_position();
+                        }
+                        else {
+                            self.update_puzzle_info();
+                        }
+                        self.generate_next();
+                    },
+                    Response::BestMove(..) | Response::Evaluation(_) | Response::MoveChecked { .. } | Response::Puzzles(_) |
+                        Response::Refutation { .. } => (),
+                }
+            },
+            CancelImport => {
+                if let Some(ref import) = self.model.import {
+                    import.cancelled.store(true, Ordering::SeqCst);
+                }
+            },
+            ImportFiles(paths) => {
+                let mut filenames = vec![];
+                for path in paths {
+                    if path.is_dir() {
+                        match importer::find_pgn_files(&path) {
+                            Ok(files) => filenames.extend(files),
+                            Err(error) => self.show_error(&error),
+                        }
+                    }
+                    else {
+                        filenames.push(path);
+                    }
+                }
+                self.import_files(filenames, self.model.append_imports);
+            },
+            ImportFolder => {
+                if let Some(dir) = self.choose_folder("Select a folder of PGN files to import") {
+                    if let Err(error) = self.import_folder(&dir) {
+                        self.show_error(&error);
+                    }
+                }
+            },
+            ImportLichessStudy => {
+                let study =
+                    match self.ask_text("Import a Lichess study", "Crazyhouse study id or URL") {
+                        Some(study) => study,
+                        None => return,
+                    };
+                match download::lichess::parse_study_id(&study) {
+                    Ok(id) => self.download("Downloading puzzles…",
+                        move || download::lichess::download_study(&id).map(|path| Download::Puzzles(vec![path]))),
+                    Err(error) => self.show_error(&error),
+                }
+            },
             ImportPGN => {
-                let dialog = FileChooserDialog::with_buttons(
-                    Some("Select a PGN file to import"),
-                    Some(&self.window),
-                    FileChooserAction::Open,
-                    &[("Import", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
-                );
-                let dir = env::current_dir().expect("current dir").join("tests");
-                dialog.set_current_folder(dir);
-                if dialog.run() == ResponseType::Ok {
-                    for filename in dialog.get_filenames() {
-                        if let Err(error) = self.import_file(&filename) {
-                            let message_dialog = MessageDialog::new(Some(&self.window), DialogFlags::empty(), MessageType::Error, ButtonsType::Ok, &error);
-                            message_dialog.run();
-                            message_dialog.destroy();
+                let filenames = self.choose_files("Select a PGN file to import");
+                self.import_files(filenames, self.model.append_imports);
+            },
+            Imported(event) => {
+                let import =
+                    match self.model.import {
+                        Some(ref mut import) => import,
+                        None => return,
+                    };
+                match event {
+                    ImportEvent::Error(error) => import.errors.push(error),
+                    ImportEvent::Finished => {
+                        if let Some(import) = self.model.import.take() {
+                            self.show_import_summary(&import);
+                            self.validate_puzzles(import.puzzles, import.append);
                         }
+                    },
+                    ImportEvent::Progress { errors, games, puzzles, skipped_games, total_games } => {
+                        import.errors.extend(errors);
+                        import.source_puzzles += puzzles.len();
+                        import.puzzles.extend(puzzles);
+                        import.skipped_games += skipped_games;
+                        self.model.import_fraction =
+                            if total_games > 0 {
+                                (games as f64 / total_games as f64).min(1.0)
+                            }
+                            else {
+                                0.0
+                            };
+                        self.model.import_status = format!("{} games read, {} puzzles found", games, import.puzzles.len());
+                    },
+                    ImportEvent::SourceRead(name) => {
+                        import.sources.push(format!("{}: {} puzzles", name, import.source_puzzles));
+                        import.source_puzzles = 0;
+                    },
+                }
+            },
+            ImportPgnFromUrl => {
+                let url =
+                    match self.ask_text("Import PGN from URL", "URL of a PGN or BPGN file") {
+                        Some(url) => url,
+                        None => return,
+                    };
+                match download::parse_url(&url) {
+                    Ok(url) => self.download("Downloading puzzles…", move || {
+                        let data = archive::decompress(download::fetch_bytes(&url)?)?;
+                        Ok(Download::PuzzlesPgn(charset::decode(&data)))
+                    }),
+                    Err(error) => self.show_error(&error),
+                }
+            },
+            ImportRecent(index) => {
+                if let Some(file) = self.model.recent_files.files.get(index).cloned() {
+                    self.import_files(vec![file.path], file.append);
+                }
+            },
+            JobResponse(generation, response) => {
+                if !self.model.jobs.is_current(generation) {
+                    return;
+                }
+
+                match response {
+                    Response::Error(error) => {
+                        self.model.text = "";
+                        self.show_error(&error);
+                    },
+                    Response::Puzzles(puzzles) => self.add_puzzles(puzzles, self.model.validation_appends),
+                    Response::BestMove(..) | Response::Evaluation(_) | Response::Generated(_) | Response::MoveChecked { .. } |
+                        Response::Refutation { .. } => (),
+                }
+            },
+            KeepPlaying => {
+                if self.model.solved && self.model.demonstration.is_none() {
+                    self.keep_playing();
+                }
+            },
+            KeyPress(key) => {
+                if key.get_keyval() == gdk::enums::key::F6 {
+                    self.switch_board();
+                }
+                else if key.get_keyval() == gdk::enums::key::Escape && self.model.typed_drop.is_some() {
+                    self.model.typed_drop = None;
+                    self.model.text = "";
+                }
+                else if !self.move_entry.has_focus() && !key.get_state().contains(gdk::ModifierType::CONTROL_MASK) {
+                    if let Some(letter) = gdk::keyval_to_unicode(key.get_keyval()) {
+                        self.type_drop_key(letter);
                     }
                 }
-                dialog.destroy();
             },
             MovePlayed(orig, dest, promotion) => {
-                if !self.model.can_play {
+                if self.model.reply_pending && !self.model.partner_focused {
+                    self.set_premove(Premove::Move(orig, dest, promotion));
+                    return;
+                }
+                if !self.model.can_play || self.model.partner_focused {
                     return;
                 }
 
                 self.model.text = "";
-                let legals = self.model.current_position.legals();
-                let mov = legals.iter().find(|mov| {
-                    mov.from() == Some(orig) && mov.to() == dest &&
-                    mov.promotion() == promotion
-                });
-
-                self.try_move(mov);
+                let position = self.model.boards.position.clone();
+                match self.choose_move(&position, orig, dest, promotion) {
+                    Some(mov) => self.try_move(Some(&mov)),
+                    // The promotion was cancelled.
+                    None => self.update_ground(),
+                }
             },
             NextPuzzle => {
-                self.model.current_move = 0;
+                // Skipping a puzzle of the rush would not count as a mistake.
+                if self.model.rush.is_some() {
+                    return;
+                }
+
+                self.model.played.clear();
                 self.model.can_play = true;
                 self.model.text = "";
-                self.model.current_puzzle = min(self.model.current_puzzle + 1, self.model.puzzles.len() - 1);
+                self.model.current_puzzle = (self.model.current_puzzle + 1..self.model.puzzles.len())
+                    .find(|&index| self.is_served(index))
+                    .unwrap_or(self.model.current_puzzle);
                 self.show_position();
             },
+            OpenCourse => {
+                if self.model.rush.is_none() {
+                    self.open_course();
+                }
+            },
+            OpenSettings => {
+                if let Some(settings) = self.edit_settings() {
+                    self.model.generator_options.limits = settings.generation;
+                    self.model.generator_options.min_quality = settings.min_quality;
+                    if let Ok(mut cache) = self.model.cache.lock() {
+                        cache.invalidate(&settings.engine_fingerprint());
+                        cache.set_capacity(settings.cache_size());
+                    }
+                    for worker in &[&self.model.analysis, &self.model.evaluation, &self.model.jobs] {
+                        worker.configure(&settings);
+                    }
+                    for generator in &self.model.generators {
+                        generator.worker.configure(&settings);
+                    }
+                    self.model.settings = settings.clone();
+                    self.apply_partner_layout();
+                    self.resize_generators(settings.generator_count());
+                    if self.model.listener.is_none() {
+                        self.model.listener = settings.remote_port.and_then(|port| remote_listener(&self.model.relm, port));
+                    }
+                    if let Err(error) = settings.save() {
+                        self.show_error(&format!("Cannot save settings: {}", error));
+                    }
+                }
+            },
             PieceDrop(piece, to) => {
-                if !self.model.can_play {
+                if self.model.reply_pending && !self.model.partner_focused {
+                    self.set_premove(Premove::Drop(piece.role, to));
+                    return;
+                }
+                if !self.model.can_play || self.model.partner_focused {
                     return;
                 }
 
                 self.model.text = "";
-                let legals = self.model.current_position.legals();
+                let legals = self.model.boards.position.legals();
                 let mov = Move::Put {
                     role: piece.role,
                     to,
@@ -206,84 +1083,2645 @@ impl Widget for Win {
                     self.try_move(Some(&mov));
                 }
             },
-            PlayOpponentMove => {
-                if let Some(puzzle) = self.model.puzzles.get(self.model.current_puzzle) {
-                    if let Some(current_move) = puzzle.moves.get(self.model.current_move) {
-                        self.model.can_play = true;
-                        self.model.current_move += 1;
-                        self.model.current_position.play_unchecked(current_move);
-                        self.ground.emit(SetPos(Pos::new(&self.model.current_position)));
+            PlayOpponentMove(id) => {
+                if !self.model.reply_pending || id != self.model.reply_id || self.model.showing_solution ||
+                    self.model.free_analysis.is_some()
+                {
+                    return;
+                }
+
+                if self.model.off_script {
+                    self.model.analysis.send(Request::BestMove(self.model.boards.position.clone()));
+                }
+                else {
+                    let mov = self.model.puzzles.get(self.model.current_puzzle)
+                        .and_then(|puzzle| puzzle.opponent_reply(&self.model.played).cloned());
+                    if let Some(mov) = mov {
+                        self.play_opponent_move(&mov);
                     }
                 }
             },
+            PlayPartnerDefense => self.play_partner_defense(),
+            PlayPartnerMate => {
+                if self.model.can_play_partner_mate {
+                    self.start_partner_mate();
+                }
+            },
+            OpenSource => {
+                if let Some(ref url) = self.model.source_url {
+                    if let Err(error) = gtk::show_uri_on_window(Some(&self.window), url, gtk::get_current_event_time()) {
+                        self.show_error(&format!("Cannot open {}: {}", url, error));
+                    }
+                }
+            },
+            PartnerMovePlayed(orig, dest, promotion) => {
+                if !self.model.partner_focused {
+                    self.update_partner_ground();
+                    return;
+                }
+
+                let mov = self.model.boards.partner.clone()
+                    .and_then(|partner| self.choose_move(&partner, orig, dest, promotion));
+                self.try_partner_move(mov);
+            },
+            PartnerPieceDrop(piece, to) => {
+                if !self.model.partner_focused {
+                    self.update_partner_ground();
+                    return;
+                }
+
+                let mov = Move::Put {
+                    role: piece.role,
+                    to,
+                };
+                let legal = self.model.boards.partner.as_ref().map(|partner| partner.is_legal(&mov)).unwrap_or(false);
+                self.try_partner_move(if legal { Some(mov) } else { None });
+            },
+            PasteBfen => {
+                let text = Clipboard::get(&gdk::SELECTION_CLIPBOARD).wait_for_text();
+                match text.map(|text| bfen::parse(text.trim())) {
+                    Some(Ok((position, partner))) => self.analyze_position(position, partner),
+                    Some(Err(error)) => self.show_error(&error),
+                    None => self.show_error("The clipboard has no text"),
+                }
+            },
+            RequestPiece(role) => {
+                let expected =
+                    match self.model.puzzles.get(self.model.current_puzzle) {
+                        Some(puzzle) => puzzle.request,
+                        None => return,
+                    };
+                self.model.requested = Some(role);
+                self.model.can_request = false;
+                self.model.text =
+                    match expected {
+                        Some(expected) if expected == role => "Right: your partner will try to get it for you",
+                        Some(_) => {
+                            self.model.can_request = true;
+                            "Wrong piece"
+                        },
+                        None => "No piece is needed: find the best move",
+                    };
+            },
             PreviousPuzzle => {
-                self.model.current_move = 0;
+                if self.model.rush.is_some() {
+                    return;
+                }
+
+                self.model.played.clear();
                 self.model.can_play = true;
                 self.model.text = "";
-                if self.model.current_puzzle > 0 {
-                    self.model.current_puzzle -= 1;
+                if let Some(index) = (0..self.model.current_puzzle).rev().find(|&index| self.is_served(index)) {
+                    self.model.current_puzzle = index;
                 }
                 self.show_position();
             },
-            Quit => gtk::main_quit(),
-        }
-    }
+            ClockTick(id) => {
+                let deadline =
+                    match self.model.clock_deadline {
+                        Some(deadline) if id == self.model.clock_id => deadline,
+                        _ => return,
+                    };
+                if Instant::now() >= deadline {
+                    self.flag();
+                }
+                else {
+                    self.update_clock_status();
+                    timeout(self.model.relm.stream(), CLOCK_TICK, move || ClockTick(id));
+                }
+            },
+            RushNext => self.rush_next(),
+            RushTick => {
+                let over =
+                    match self.model.rush {
+                        Some(ref rush) => rush.is_over(),
+                        None => return,
+                    };
+                if over {
+                    self.end_rush();
+                }
+                else {
+                    self.update_rush_status();
+                    timeout(self.model.relm.stream(), 1000, || RushTick);
+                }
+            },
+            StartRush => {
+                if self.model.rush.is_some() {
+                    return;
+                }
 
-    fn import_file(&mut self, filename: &PathBuf) -> Result<(), String> {
-        let mut file = File::open(filename).map_err(|error| error.to_string())?;
-        let mut data = vec![];
-        file.read_to_end(&mut data).map_err(|error| error.to_string())?;
-        let (result, _, _) = encoding_rs::WINDOWS_1252.decode(&data);
+                if self.model.puzzles.is_empty() {
+                    self.show_error("Import or generate puzzles first");
+                    return;
+                }
+                if let Some(minutes) = self.ask_rush_minutes() {
+                    let ids = (0..self.model.puzzles.len()).map(|index| self.puzzle_id(index)).collect();
+                    self.model.rush = Some(Rush::new(Some(minutes), &self.model.puzzles, ids));
+                    self.update_rush_status();
+                    timeout(self.model.relm.stream(), 1000, || RushTick);
+                    self.rush_next();
+                }
+            },
+            DailyPuzzle => {
+                if self.model.rush.is_some() {
+                    return;
+                }
 
-        let mut importer = FENImporter::new();
-        let mut reader = BufferedReader::new_cursor(result.as_bytes());
-        reader.read_all(&mut importer).map_err(|_| "Cannot parse PGN file")?;
-        self.model.puzzles = importer.puzzles;
-        self.model.current_puzzle = 0;
-        self.model.current_move = 0;
-        self.model.can_play = true;
-        self.model.text = "";
-        self.show_position();
-        Ok(())
-    }
+                match self.daily_puzzle() {
+                    Some(index) => {
+                        self.model.played.clear();
+                        self.model.can_play = true;
+                        self.model.text = "";
+                        self.model.current_puzzle = index;
+                        self.show_position();
+                    },
+                    None => self.show_error("Import or generate puzzles first"),
+                }
+            },
+            StartStreak => {
+                if self.model.rush.is_some() {
+                    return;
+                }
 
-    fn show_position(&mut self) {
-        if let Some(puzzle) = self.model.puzzles.get(self.model.current_puzzle) {
-            self.model.current_position = puzzle.position.clone();
-            let pos = Pos::new(&puzzle.position);
-            let turn = puzzle.position.turn();
-            self.ground.emit(SetPos(pos));
-            self.ground.emit(SetOrientation(turn));
-            self.ground.emit(SetPockets(puzzle.position.pockets().cloned().unwrap_or(Material::new()), turn));
-        }
-    }
+                if self.model.puzzles.is_empty() {
+                    self.show_error("Import or generate puzzles first");
+                    return;
+                }
+                let ids = (0..self.model.puzzles.len()).map(|index| self.puzzle_id(index)).collect();
+                self.model.rush = Some(Rush::new(None, &self.model.puzzles, ids));
+                self.update_rush_status();
+                self.rush_next();
+            },
+            Retry => {
+                if self.model.puzzles.is_empty() || self.model.rush.is_some() {
+                    return;
+                }
 
-    fn try_move(&mut self, mov: Option<&Move>) {
-        if let Some(puzzle) = self.model.puzzles.get(self.model.current_puzzle) {
-            if let Some(current_move) = puzzle.moves.get(self.model.current_move) {
-                if let Some(mov) = mov {
-                    if mov == current_move {
-                        self.model.current_move += 1;
-                        let turn = self.model.current_position.turn();
-                        self.model.current_position.play_unchecked(mov);
-                        self.ground.emit(SetPos(Pos::new(&self.model.current_position)));
-                        self.ground.emit(SetPockets(self.model.current_position.pockets().cloned().unwrap_or(Material::new()), turn));
-                        self.model.can_play = false;
-
-                        if self.model.current_move == puzzle.moves.len() {
-                            self.model.text = "Success";
-                        }
-                        else {
-                            timeout(self.model.relm.stream(), 500, || PlayOpponentMove);
-                        }
+                self.model.played.clear();
+                self.model.can_play = true;
+                self.model.text = "";
+                self.show_position();
+            },
+            Undo => {
+                if self.model.free_analysis.is_some() {
+                    self.take_back_analysis_move();
+                }
+                else if (self.model.can_play || self.model.solved) && self.model.demonstration.is_none() && !self.model.failed &&
+                    self.model.partner_mate_played.is_none() && !self.model.showing_solution && self.model.rush.is_none()
+                {
+                    self.undo();
+                }
+            },
+            ProveMeWrong => {
+                if let Some(mov) = self.model.wrong_move.take() {
+                    self.model.analysis.cancel();
+                    self.model.demonstration = Some(Demonstration {
+                        played: self.model.played.clone(),
+                        position: self.model.boards.position.clone(),
+                        sparring: false,
+                    });
+                    self.model.text = "";
+                    self.play_demonstration_move(&mov);
+                }
+            },
+            Quit => {
+                self.save_cache();
+                gtk::main_quit();
+            },
+            RemoteWorkerConnected(stream) => {
+                let id = self.model.next_generator_id;
+                let relm_stream = self.model.relm.stream().clone();
+                let worker = Worker::remote(stream,
+                    move |generation, response| relm_stream.emit(GeneratorResponse(id, generation, response)));
+                match worker {
+                    Ok(worker) => {
+                        self.model.next_generator_id += 1;
+                        self.model.generators.push(Generator {
+                            game: None,
+                            id,
+                            remote: true,
+                            worker,
+                        });
+                        self.generate_next();
+                    },
+                    Err(error) => eprintln!("Cannot use remote worker: {}", error),
+                }
+            },
+            ExportAnki => {
+                if self.model.puzzles.is_empty() {
+                    self.show_error("There are no puzzles to export");
+                    return;
+                }
+
+                if let Some(dir) = self.choose_folder("Select the folder of the Anki deck") {
+                    match export::to_anki(&self.model.puzzles, &dir) {
+                        Ok(()) => self.show_info(&format!("Import {} in Anki after copying the images to the collection.media folder of your profile",
+                            dir.join(export::ANKI_DECK).display())),
+                        Err(error) => self.show_error(&error),
                     }
-                    else {
-                        self.model.text = "Wrong answer";
+                }
+            },
+            ExportImage => {
+                if self.model.puzzles.is_empty() {
+                    self.show_error("There is no position to export");
+                    return;
+                }
+
+                if let Some(filename) = self.choose_save_file("Export the position as an image", "position.png") {
+                    let result = image::export(&filename, &self.model.boards.position, &self.current_shapes(), self.current_player());
+                    if let Err(error) = result {
+                        self.show_error(&error);
                     }
                 }
-            }
-        }
-    }
+            },
+            ExportPack => {
+                if self.model.puzzles.is_empty() {
+                    self.show_error("There are no puzzles to export");
+                    return;
+                }
+
+                if let Some(manifest) = self.ask_pack_manifest() {
+                    let name = format!("{}.{}", manifest.name, pack::EXTENSION);
+                    if let Some(filename) = self.choose_save_file("Export the puzzle pack", &name) {
+                        if let Err(error) = pack::export(&filename, &manifest, &self.model.puzzles) {
+                            self.show_error(&error);
+                        }
+                    }
+                }
+            },
+            InstallPack => {
+                for filename in self.choose_files("Install puzzle packs") {
+                    self.install_pack(&filename);
+                }
+            },
+            SavePuzzles => {
+                if self.model.puzzles.is_empty() {
+                    self.show_error("There are no puzzles to save");
+                    return;
+                }
+
+                let name = format!("puzzles.{}", puzzle_set::EXTENSION);
+                if let Some(filename) = self.choose_save_file("Save the puzzle set", &name) {
+                    // Everything is kept in the native format, only the puzzles themselves in BPGN.
+                    let result =
+                        if puzzle_set::is_puzzle_set(&filename) {
+                            puzzle_set::save(&filename, &self.model.puzzles)
+                        }
+                        else {
+                            let extension = filename.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+                            let content =
+                                match extension {
+                                    "epd" => export::to_epd(&self.model.puzzles),
+                                    "tex" => export::to_latex(&self.model.puzzles),
+                                    _ => export::to_bpgn(&self.model.puzzles),
+                                };
+                            fs::write(&filename, content)
+                                .map_err(|error| format!("Cannot save {}: {}", filename.display(), error))
+                        };
+                    if let Err(error) = result {
+                        self.show_error(&error);
+                    }
+                }
+            },
+            SetAppendImports(append) => self.model.append_imports = append,
+            SetEngineReplies(engine_replies) => {
+                self.model.engine_replies = engine_replies;
+                self.model.played.clear();
+                self.model.can_play = true;
+                self.model.text = "";
+                self.show_position();
+            },
+            SetFullOpponentPocket(full) => self.model.generator_options.full_opponent_pocket = full,
+            SetBlindfold(blindfold) => {
+                self.model.blindfold = blindfold;
+                self.update_ground();
+            },
+            SetChapter => {
+                if self.model.puzzles.is_empty() {
+                    return;
+                }
+
+                if let Some(chapter) = self.ask_text("Chapter", "Chapter of the puzzle in the training course") {
+                    self.model.puzzles[self.model.current_puzzle].chapter = Some(chapter);
+                    self.update_puzzle_info();
+                }
+            },
+            SetDropsOnly(drops_only) => self.set_drops_only(drops_only),
+            SetPatternsOnly(patterns_only) => self.model.generator_options.patterns_only = patterns_only,
+            SetReviewing(reviewing) => {
+                if reviewing != self.model.reviewing {
+                    self.set_reviewing(reviewing);
+                }
+            },
+            SetPictureInPicture(picture_in_picture) => {
+                self.model.settings.partner_board.picture_in_picture = picture_in_picture;
+                self.apply_partner_layout();
+                if let Err(error) = self.model.settings.save() {
+                    self.show_error(&format!("Cannot save settings: {}", error));
+                }
+            },
+            ShowRefutation => {
+                if let Some(mov) = self.model.wrong_move.clone() {
+                    self.model.refutation = "Analyzing…".to_string();
+                    self.model.analysis.send(Request::Refute(self.model.boards.position.clone(), mov));
+                }
+            },
+            SwitchBoard => self.switch_board(),
+            TogglePartnerBoard(show) => {
+                self.partner_column.set_visible(show);
+                if !show && self.model.partner_focused {
+                    self.switch_board();
+                }
+            },
+            ToggleAnalysis(active) => {
+                if active && self.model.free_analysis.is_none() {
+                    self.start_free_analysis();
+                }
+                else if !active && self.model.free_analysis.is_some() {
+                    self.end_free_analysis();
+                }
+            },
+            ToggleEvaluation(show) => {
+                self.model.show_evaluation = show;
+                if show {
+                    self.evaluate();
+                }
+                else {
+                    self.model.evaluation.cancel();
+                }
+            },
+            ToggleGenerationPause => {
+                // The game being analyzed is finished before pausing.
+                self.model.generation_paused = !self.model.generation_paused;
+                self.generate_next();
+            },
+            SortByDifficulty => {
+                // Puzzles without difficulty go last.
+                self.model.puzzles.sort_by_key(|puzzle| puzzle.difficulty.unwrap_or(u32::max_value()));
+                self.model.puzzle_ids.borrow_mut().clear();
+                self.model.played.clear();
+                self.model.can_play = true;
+                self.model.text = "";
+                self.model.current_puzzle = 0;
+                self.show_position();
+            },
+            Hint => {
+                if self.model.can_play && self.model.demonstration.is_none() && self.model.rush.is_none() {
+                    self.show_hint();
+                }
+            },
+            ShowSolution => {
+                if !self.model.showing_solution && !self.model.solved && self.model.rush.is_none() {
+                    self.show_solution();
+                }
+            },
+            ShowSolutionMove => self.show_solution_move(),
+            Sit => {
+                if !self.model.can_play {
+                    return;
+                }
+
+                if let Some(puzzle) = self.model.puzzles.get(self.model.current_puzzle) {
+                    if puzzle.partner_low_on_time() {
+                        self.wrong_answer("Wrong answer: your partner would lose on time, play on");
+                    }
+                    else if puzzle.goal == Goal::Sit {
+                        self.succeed();
+                    }
+                    else {
+                        self.wrong_answer("Wrong answer");
+                    }
+                }
+            },
+        }
+    }
+
+    /// Ask which games of bughousedb.com to download.
+    fn ask_download_query(&self) -> Option<download::bughousedb::Query> {
+        let dialog = Dialog::new_with_buttons(
+            Some("Download games from bughousedb.com"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("Download", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let grid = Grid::new();
+        grid.set_border_width(12);
+        grid.set_column_spacing(12);
+        grid.set_row_spacing(6);
+        let from = add_entry(&grid, 0, "From (YYYY-MM-DD)");
+        let to = add_entry(&grid, 1, "To (YYYY-MM-DD)");
+        let players = add_entry(&grid, 2, "Players, separated by commas");
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+        let mut query = None;
+        while query.is_none() && dialog.run() == ResponseType::Ok {
+            let field = |entry: &Entry| entry.get_text().map(|text| text.to_string()).unwrap_or_default();
+            match download::bughousedb::Query::parse(&field(&from), &field(&to), &field(&players)) {
+                Ok(parsed_query) => query = Some(parsed_query),
+                Err(error) => self.show_error(&error),
+            }
+        }
+        dialog.destroy();
+        query
+    }
+
+    /// Ask for the description of a puzzle pack to export.
+    fn ask_pack_manifest(&self) -> Option<Manifest> {
+        let dialog = Dialog::new_with_buttons(
+            Some("Export a puzzle pack"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("Export", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let grid = Grid::new();
+        grid.set_border_width(12);
+        grid.set_column_spacing(12);
+        grid.set_row_spacing(6);
+        let name = add_entry(&grid, 0, "Name");
+        let author = add_entry(&grid, 1, "Author");
+        let license = add_entry(&grid, 2, "License");
+        license.set_text("CC-BY-4.0");
+        let version = add_entry(&grid, 3, "Version");
+        version.set_text("1");
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+        let mut manifest = None;
+        while manifest.is_none() && dialog.run() == ResponseType::Ok {
+            let field = |entry: &Entry| entry.get_text().map(|text| text.trim().to_string()).unwrap_or_default();
+            match (field(&name), field(&version).parse()) {
+                (ref name, _) if name.is_empty() => self.show_error("The pack needs a name"),
+                (_, Err(_)) => self.show_error("The version must be a number, increased at each release"),
+                (name, Ok(version)) => manifest = Some(Manifest {
+                    author: field(&author),
+                    license: field(&license),
+                    name,
+                    version,
+                }),
+            }
+        }
+        dialog.destroy();
+        manifest
+    }
+
+    /// Legal move of the user from `orig` to `dest` in `position`. The piece of a promotion is
+    /// asked, since the board always sends the same one.
+    fn choose_move(&self, position: &Bughouse, orig: Square, dest: Square, promotion: Option<Role>) -> Option<Move> {
+        let candidates: Vec<Move> = position.legals().into_iter()
+            .filter(|mov| mov.from() == Some(orig) && mov.to() == dest)
+            .collect();
+        let promotion =
+            if candidates.iter().any(|mov| mov.promotion().is_some()) {
+                Some(self.ask_promotion()?)
+            }
+            else {
+                promotion
+            };
+        candidates.into_iter().find(|mov| mov.promotion() == promotion)
+    }
+
+    /// Ask the piece to promote to, the underpromotions being the solution of some puzzles.
+    fn ask_promotion(&self) -> Option<Role> {
+        let dialog = Dialog::new_with_buttons(
+            Some("Promote to"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[
+                ("Queen", ResponseType::Other(0)),
+                ("Rook", ResponseType::Other(1)),
+                ("Bishop", ResponseType::Other(2)),
+                ("Knight", ResponseType::Other(3)),
+            ],
+        );
+        dialog.set_default_response(ResponseType::Other(0));
+        let response = dialog.run();
+        dialog.destroy();
+        match response {
+            ResponseType::Other(index) => PROMOTION_ROLES.get(usize::from(index)).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Ask the duration of the puzzle rush, in minutes, showing the best scores.
+    fn ask_rush_minutes(&self) -> Option<u64> {
+        let dialog = Dialog::new_with_buttons(
+            Some("Puzzle rush"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("Start", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let grid = Grid::new();
+        grid.set_border_width(12);
+        grid.set_column_spacing(12);
+        grid.set_row_spacing(6);
+        let label = Label::new(Some("Duration"));
+        label.set_xalign(0.0);
+        let duration = ComboBoxText::new();
+        for &minutes in &rush::DURATIONS {
+            duration.append(Some(&minutes.to_string()), &format!("{} minutes", minutes));
+        }
+        duration.set_active(Some(0));
+        grid.attach(&label, 0, 0, 1, 1);
+        grid.attach(&duration, 1, 0, 1, 1);
+        for (row, &minutes) in rush::DURATIONS.iter().enumerate() {
+            let scores: Vec<String> = self.model.best_scores.of_duration(minutes)
+                .map(|score| score.score.to_string())
+                .collect();
+            let scores = if scores.is_empty() { "none yet".to_string() } else { scores.join(", ") };
+            let label = Label::new(Some(format!("Best scores in {} minutes: {}", minutes, scores).as_str()));
+            label.set_xalign(0.0);
+            grid.attach(&label, 0, row as i32 + 1, 2, 1);
+        }
+        grid.attach(&Label::new(Some("Three wrong answers end the run.")), 0, rush::DURATIONS.len() as i32 + 1, 2, 1);
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+        let minutes =
+            if dialog.run() == ResponseType::Ok {
+                duration.get_active_id().and_then(|id| id.parse().ok())
+            }
+            else {
+                None
+            };
+        dialog.destroy();
+        minutes
+    }
+
+    /// Ask for a single text value, giving None when cancelled or empty.
+    fn ask_text(&self, title: &str, label: &str) -> Option<String> {
+        let dialog = Dialog::new_with_buttons(
+            Some(title),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("Ok", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let grid = Grid::new();
+        grid.set_border_width(12);
+        grid.set_column_spacing(12);
+        let entry = add_entry(&grid, 0, label);
+        entry.set_activates_default(true);
+        dialog.set_default_response(ResponseType::Ok);
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+        let text =
+            if dialog.run() == ResponseType::Ok {
+                entry.get_text()
+                    .map(|text| text.trim().to_string())
+                    .filter(|text| !text.is_empty())
+            }
+            else {
+                None
+            };
+        dialog.destroy();
+        text
+    }
+
+    /// Show the progression in the chapters of the course, to start one of the unlocked chapters
+    /// or to leave the course.
+    fn open_course(&mut self) {
+        let unlock_rate = self.model.settings.chapter_unlock_rate;
+        let chapters = self.chapters();
+        if chapters.is_empty() {
+            self.show_info("No puzzle belongs to a chapter: set the chapter of the puzzles or import a course");
+            return;
+        }
+
+        let dialog = Dialog::new_with_buttons(
+            Some("Course"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("All puzzles", ResponseType::Reject), ("Close", ResponseType::Cancel)],
+        );
+        let grid = Grid::new();
+        grid.set_border_width(12);
+        grid.set_column_spacing(12);
+        grid.set_row_spacing(6);
+        for (row, chapter) in chapters.iter().enumerate() {
+            let name = Label::new(Some(chapter.name.as_str()));
+            name.set_xalign(0.0);
+            let progress = Label::new(Some(format!("{}/{} solved ({}%)", chapter.solved, chapter.puzzles.len(),
+                chapter.success_rate()).as_str()));
+            progress.set_xalign(0.0);
+            grid.attach(&name, 0, row as i32, 1, 1);
+            grid.attach(&progress, 1, row as i32, 1, 1);
+            if chapter.unlocked {
+                let start = Button::new_with_label("Start");
+                let dialog = dialog.clone();
+                let response = ResponseType::Other(row as u16);
+                start.connect_clicked(move |_| dialog.response(response));
+                grid.attach(&start, 2, row as i32, 1, 1);
+            }
+            else {
+                grid.attach(&Label::new(Some("Locked")), 2, row as i32, 1, 1);
+            }
+        }
+        let explanation = format!("A chapter is unlocked by solving {}% of the puzzles of the previous one.", unlock_rate);
+        grid.attach(&Label::new(Some(explanation.as_str())), 0, chapters.len() as i32, 3, 1);
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+        let response = dialog.run();
+        dialog.destroy();
+        match response {
+            ResponseType::Other(index) => self.start_chapter(chapters.get(usize::from(index))),
+            ResponseType::Reject => self.start_chapter(None),
+            _ => (),
+        }
+    }
+
+    fn choose_folder(&self, title: &str) -> Option<PathBuf> {
+        let dialog = FileChooserDialog::with_buttons(
+            Some(title),
+            Some(&self.window),
+            FileChooserAction::SelectFolder,
+            &[("Import", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let folder =
+            if dialog.run() == ResponseType::Ok {
+                dialog.get_filename()
+            }
+            else {
+                None
+            };
+        dialog.destroy();
+        folder
+    }
+
+    fn choose_save_file(&self, title: &str, name: &str) -> Option<PathBuf> {
+        let dialog = FileChooserDialog::with_buttons(
+            Some(title),
+            Some(&self.window),
+            FileChooserAction::Save,
+            &[("Save", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        dialog.set_current_name(name);
+        dialog.set_do_overwrite_confirmation(true);
+        let filename =
+            if dialog.run() == ResponseType::Ok {
+                dialog.get_filename()
+            }
+            else {
+                None
+            };
+        dialog.destroy();
+        filename
+    }
+
+    /// Install a puzzle pack and add its puzzles to the current ones.
+    fn install_pack(&mut self, filename: &Path) {
+        let (manifest, installation, puzzles) =
+            match pack::install(filename) {
+                Ok(result) => result,
+                Err(error) => {
+                    self.show_error(&error);
+                    return;
+                },
+            };
+        let message =
+            match installation {
+                Installation::AlreadyInstalled(version) => {
+                    self.show_info(&format!("Version {} of the pack {} is already installed", version, manifest.name));
+                    return;
+                },
+                Installation::New => format!("Installed the pack {}, version {}, by {} ({})", manifest.name,
+                    manifest.version, manifest.author, manifest.license),
+                Installation::Updated(version) => format!("Updated the pack {} from version {} to {}", manifest.name,
+                    version, manifest.version),
+            };
+        self.add_puzzles(puzzles, true);
+        self.show_info(&message);
+    }
+
+    fn choose_files(&self, title: &str) -> Vec<PathBuf> {
+        let dialog = FileChooserDialog::with_buttons(
+            Some(title),
+            Some(&self.window),
+            FileChooserAction::Open,
+            &[("Import", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let dir = env::current_dir().expect("current dir").join("tests");
+        dialog.set_current_folder(dir);
+        let filenames =
+            if dialog.run() == ResponseType::Ok {
+                dialog.get_filenames()
+            }
+            else {
+                vec![]
+            };
+        dialog.destroy();
+        filenames
+    }
+
+    fn edit_settings(&self) -> Option<Settings> {
+        let dialog = Dialog::new_with_buttons(
+            Some("Settings"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("Save", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let grid = Grid::new();
+        grid.set_border_width(12);
+        grid.set_column_spacing(12);
+        grid.set_row_spacing(6);
+        let settings = &self.model.settings;
+        let limits = settings.generation;
+        let depth = add_setting(&grid, 0, "Search depth", limits.depth.map(u64::from), 99.0, 1.0);
+        let movetime = add_setting(&grid, 1, "Time per position (ms)", limits.movetime.map(u64::from), 600_000.0, 100.0);
+        let nodes = add_setting(&grid, 2, "Nodes per position", limits.nodes, 1e10, 10_000.0);
+        let hash = add_setting(&grid, 3, "Hash size (MB)", settings.engine.hash.map(u64::from), 65_536.0, 16.0);
+        let threads = add_setting(&grid, 4, "Threads", settings.engine.threads.map(u64::from), 512.0, 1.0);
+        let generators = add_setting(&grid, 5, "Parallel engines", settings.generators.map(|count| count as u64), 256.0, 1.0);
+        let cache_size = add_setting(&grid, 6, "Cached searches", settings.cache_size.map(|size| size as u64), 1e8, 1000.0);
+        let min_quality = add_setting(&grid, 7, "Minimum puzzle quality (0-100)", Some(u64::from(settings.min_quality)), 100.0, 5.0);
+        let remote_port = add_setting(&grid, 8, "Port for remote workers", settings.remote_port.map(u64::from), 65_535.0, 1.0);
+        let (engine_choice, engine_configs) = add_engine_setting(&grid, 9, &self.model.engines, settings.engine_config.as_ref());
+        let filter = &settings.game_filter;
+        let min_rating = add_setting(&grid, 10, "Minimum rating of the players", filter.min_rating.map(u64::from), 4000.0, 100.0);
+        let min_time = add_setting(&grid, 11, "Minimum base time (s)", filter.min_time.map(u64::from), 86_400.0, 60.0);
+        let max_time = add_setting(&grid, 12, "Maximum base time (s)", filter.max_time.map(u64::from), 86_400.0, 60.0);
+        let decisive_only = CheckButton::new_with_label("Only generate from decisive games");
+        decisive_only.set_active(filter.decisive_only);
+        grid.attach(&decisive_only, 0, 13, 2, 1);
+        let players = add_entry(&grid, 14, "Only import and generate from the games of");
+        players.set_text(&filter.players.join(", "));
+        players.set_placeholder_text(Some("Player names, separated by commas"));
+        let bpgn_board = add_board_setting(&grid, 15, settings.bpgn_board);
+        let partner_board = settings.partner_board;
+        let partner_board_size = add_setting(&grid, 16, "Size of the partner's board", Some(u64::from(partner_board.size)), 2000.0, 20.0);
+        let partner_board_corner = add_corner_setting(&grid, 17, partner_board.corner);
+        let solution_delay = add_setting(&grid, 18, "Delay between the moves of the solution (ms)", settings.solution_delay.map(u64::from), 60_000.0, 100.0);
+        let auto_advance_delay = add_setting(&grid, 19, "Delay before the next unsolved puzzle after a success (ms)",
+            settings.auto_advance_delay.map(u64::from), 60_000.0, 100.0);
+        let puzzle_clock = add_setting(&grid, 20, "Time to solve each puzzle (s)", settings.puzzle_clock.map(u64::from), 3600.0, 5.0);
+        let chapter_unlock_rate = add_setting(&grid, 21, "Share of a chapter to solve to unlock the next one (%)",
+            Some(u64::from(settings.chapter_unlock_rate)), 100.0, 5.0);
+        let one_try = CheckButton::new_with_label("A wrong move fails the puzzle and locks the board until retried");
+        one_try.set_active(settings.one_try);
+        grid.attach(&one_try, 0, 22, 2, 1);
+        grid.attach(&Label::new(Some("Generation stops each search at the first limit reached. 0 means no limit, the default, or staying on the solved puzzle.")),
+            0, 23, 2, 1);
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+        let settings =
+            if dialog.run() == ResponseType::Ok {
+                Some(Settings {
+                    auto_advance_delay: setting_value(&auto_advance_delay).map(|delay| delay as u32),
+                    bpgn_board:
+                        match bpgn_board.get_active_id().as_ref().map(|id| id.as_str()) {
+                            Some("B") => Board::B,
+                            _ => Board::A,
+                        },
+                    cache_size: setting_value(&cache_size).map(|size| size as usize),
+                    chapter_unlock_rate: setting_value(&chapter_unlock_rate).unwrap_or(0) as u32,
+                    engine: EngineOptions {
+                        hash: setting_value(&hash).map(|hash| hash as u32),
+                        threads: setting_value(&threads).map(|threads| threads as u32),
+                    },
+                    engine_config: engine_choice.get_active_id()
+                        .and_then(|id| id.parse::<usize>().ok())
+                        .and_then(|index| engine_configs.get(index).cloned()),
+                    game_filter: GameFilter {
+                        decisive_only: decisive_only.get_active(),
+                        max_time: setting_value(&max_time).map(|time| time as u32),
+                        min_rating: setting_value(&min_rating).map(|rating| rating as u32),
+                        min_time: setting_value(&min_time).map(|time| time as u32),
+                        players: filter::parse_players(&players.get_text().map(|text| text.to_string()).unwrap_or_default()),
+                    },
+                    generation: SearchLimits {
+                        depth: setting_value(&depth).map(|depth| depth as u32),
+                        movetime: setting_value(&movetime).map(|movetime| movetime as u32),
+                        nodes: setting_value(&nodes),
+                    },
+                    generators: setting_value(&generators).map(|count| count as usize),
+                    min_quality: setting_value(&min_quality).unwrap_or(0) as u32,
+                    one_try: one_try.get_active(),
+                    partner_board: PartnerBoardLayout {
+                        corner:
+                            match partner_board_corner.get_active_id().as_ref().map(|id| id.as_str()) {
+                                Some("BottomLeft") => Corner::BottomLeft,
+                                Some("BottomRight") => Corner::BottomRight,
+                                Some("TopLeft") => Corner::TopLeft,
+                                _ => Corner::TopRight,
+                            },
+                        picture_in_picture: partner_board.picture_in_picture,
+                        size: setting_value(&partner_board_size).map(|size| size as u32).unwrap_or(settings::DEFAULT_PARTNER_BOARD_SIZE),
+                    },
+                    puzzle_clock: setting_value(&puzzle_clock).map(|seconds| seconds as u32),
+                    remote_port: setting_value(&remote_port).map(|port| port as u16),
+                    solution_delay: setting_value(&solution_delay).map(|delay| delay as u32),
+                })
+            }
+            else {
+                None
+            };
+        dialog.destroy();
+        settings
+    }
+
+    /// Send the next games of the queue to the idle generators.
+    fn generate_next(&mut self) {
+        if !self.model.generation_paused {
+            for index in 0..self.model.generators.len() {
+                if self.model.generators[index].game.is_some() {
+                    continue;
+                }
+
+                match self.next_game() {
+                    Some((number, game)) => {
+                        let generator = &mut self.model.generators[index];
+                        generator.worker.send(Request::Generate(game, self.model.generator_options));
+                        generator.game = Some(number);
+                    },
+                    None => break,
+                }
+            }
+        }
+
+        let running = self.model.generators.iter().any(|generator| generator.game.is_some());
+        if self.model.queue.is_empty() && !running {
+            if self.model.queue.analyzed_games > 0 {
+                self.model.generation_status = format!("Generation finished: {} puzzles found in {} games",
+                    self.model.queue.puzzles.len(), self.model.queue.analyzed_games);
+            }
+            self.model.generation_paused = false;
+            self.model.queue = Queue::default();
+            self.save_queue();
+            self.save_cache();
+        }
+        else {
+            self.update_generation_status();
+        }
+    }
+
+    fn next_game(&mut self) -> Option<(usize, Game)> {
+        loop {
+            match self.model.queue.next_game() {
+                Ok(game) => return game,
+                Err(error) => {
+                    self.save_queue();
+                    self.show_error(&error);
+                },
+            }
+        }
+    }
+
+    /// Import the files chosen by the user, remembering them in the recent files.
+    fn import_files(&mut self, filenames: Vec<PathBuf>, append: bool) {
+        if filenames.is_empty() {
+            return;
+        }
+
+        for filename in filenames.iter().rev() {
+            let filename = filename.canonicalize().unwrap_or_else(|_| filename.clone());
+            self.model.recent_files.add(filename, append);
+        }
+        if let Err(error) = self.model.recent_files.save() {
+            eprintln!("Cannot save recent files: {}", error);
+        }
+        self.update_recent_menu();
+
+        let (packs, filenames): (Vec<_>, Vec<_>) = filenames.into_iter().partition(|filename| pack::is_pack(filename));
+        for filename in packs {
+            self.install_pack(&filename);
+        }
+
+        // The puzzle sets are already validated.
+        let (sets, filenames): (Vec<_>, Vec<_>) = filenames.into_iter().partition(|filename| puzzle_set::is_puzzle_set(filename));
+        let mut append = append;
+        for filename in sets {
+            match puzzle_set::load(&filename) {
+                Ok(puzzles) => {
+                    self.add_puzzles(puzzles, append);
+                    append = true;
+                },
+                Err(error) => self.show_error(&error),
+            }
+        }
+        if !filenames.is_empty() {
+            self.import(filenames.into_iter().map(PgnSource::File).collect(), append);
+        }
+    }
+
+    /// Read the puzzles of the `sources` on another thread, then validate them.
+    fn import(&mut self, sources: Vec<PgnSource>, append: bool) {
+        if self.model.import.is_some() {
+            self.show_error("Wait for the current import to finish");
+            return;
+        }
+
+        let stream = self.model.relm.stream().clone();
+        let (channel, sender) = Channel::new(move |event| stream.emit(Imported(event)));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let cancelled = cancelled.clone();
+            let players = self.model.settings.game_filter.players.clone();
+            let board = self.model.settings.bpgn_board;
+            thread::spawn(move || {
+                importer::stream_puzzles(sources, &players, board, &cancelled, |event| {
+                    let _ = sender.send(event);
+                });
+            });
+        }
+        self.model.import = Some(Import {
+            _channel: channel,
+            append,
+            cancelled,
+            errors: vec![],
+            puzzles: vec![],
+            skipped_games: 0,
+            source_puzzles: 0,
+            sources: vec![],
+        });
+        self.model.import_fraction = 0.0;
+        self.model.import_status = "Reading games…".to_string();
+    }
+
+    /// Import the PGN files of `dir` and its subdirectories, showing the puzzles found in each.
+    fn import_folder(&mut self, dir: &Path) -> Result<(), String> {
+        let filenames = importer::find_pgn_files(dir)?;
+        if filenames.is_empty() {
+            return Err(format!("No PGN file in {}", dir.display()));
+        }
+
+        self.import(filenames.into_iter().map(PgnSource::File).collect(), self.model.append_imports);
+        Ok(())
+    }
+
+    /// Validate the imported `puzzles`, which then replace the current ones or are appended to
+    /// them.
+    fn validate_puzzles(&mut self, puzzles: Vec<Puzzle>, append: bool) {
+        self.model.validation_appends = append;
+        self.model.jobs.send(Request::Validate(puzzles, self.model.generator_options));
+        self.model.text = "Validating puzzles…";
+    }
+
+    fn update_recent_menu(&self) {
+        let menu = Menu::new();
+        for (index, file) in self.model.recent_files.files.iter().enumerate() {
+            let label =
+                if file.append {
+                    format!("{} (appended)", file.path.display())
+                }
+                else {
+                    file.path.display().to_string()
+                };
+            let item = MenuItem::new_with_label(&label);
+            let stream = self.model.relm.stream().clone();
+            item.connect_activate(move |_| stream.emit(ImportRecent(index)));
+            menu.append(&item);
+        }
+        menu.show_all();
+        self.import_button.set_menu(&menu);
+    }
+
+    /// Run `download` on another thread, showing `status` until it is imported.
+    fn download<F>(&mut self, status: &'static str, download: F)
+    where F: FnOnce() -> Result<Download, String> + Send + 'static
+    {
+        self.model.text = status;
+        let sender = self.model.download_sender.clone();
+        thread::spawn(move || {
+            let _ = sender.send(download());
+        });
+    }
+
+    /// Add the game files to the generation queue.
+    fn queue_games(&mut self, filenames: Vec<PathBuf>) {
+        if filenames.is_empty() {
+            return;
+        }
+
+        if self.model.queue.is_empty() {
+            self.set_puzzles(vec![]);
+        }
+        for filename in filenames {
+            self.model.queue.add(filename, self.model.settings.game_filter.clone());
+        }
+        self.save_queue();
+        self.generate_next();
+    }
+
+    /// Change the number of local engines analyzing games in parallel. The games being analyzed by
+    /// the removed generators will be given to the others.
+    fn resize_generators(&mut self, count: usize) {
+        let mut local_count = self.model.generators.iter().filter(|generator| !generator.remote).count();
+        while local_count > count {
+            if let Some(index) = self.model.generators.iter().rposition(|generator| !generator.remote) {
+                if let Some(game) = self.model.generators.remove(index).game {
+                    self.model.queue.fail(game);
+                }
+            }
+            local_count -= 1;
+        }
+        while local_count < count {
+            let id = self.model.next_generator_id;
+            self.model.next_generator_id += 1;
+            self.model.generators.push(local_generator(&self.model.relm, &self.model.cache, id, &self.model.settings));
+            local_count += 1;
+        }
+        self.generate_next();
+    }
+
+    fn save_cache(&self) {
+        if let Ok(cache) = self.model.cache.lock() {
+            if let Err(error) = cache.save() {
+                eprintln!("Cannot save the engine cache: {}", error);
+            }
+        }
+    }
+
+    fn save_queue(&self) {
+        if let Err(error) = self.model.queue.save() {
+            eprintln!("Cannot save the generation queue: {}", error);
+        }
+    }
+
+    /// Append the imported `puzzles` to the current ones or replace them, without duplicates.
+    fn add_puzzles(&mut self, mut puzzles: Vec<Puzzle>, append: bool) {
+        if append {
+            self.append_puzzles(puzzles);
+        }
+        else {
+            let duplicates = puzzle::remove_duplicates(&[], &mut puzzles);
+            self.set_puzzles(puzzles);
+            if duplicates > 0 {
+                self.show_info(&format!("Skipped {} duplicate puzzles", duplicates));
+            }
+        }
+    }
+
+    /// Add the puzzles which are not already in the set.
+    fn append_puzzles(&mut self, mut puzzles: Vec<Puzzle>) {
+        let was_empty = self.model.puzzles.is_empty();
+        let duplicates = puzzle::remove_duplicates(&self.model.puzzles, &mut puzzles);
+        let start = self.model.puzzles.len();
+        self.model.puzzles.extend(puzzles);
+        self.restore_puzzle_stats(start);
+        self.model.text = "";
+        if was_empty {
+            self.show_position();
+        }
+        else {
+            self.update_puzzle_info();
+        }
+        if duplicates > 0 {
+            self.show_info(&format!("Skipped {} puzzles already in the set", duplicates));
+        }
+    }
+
+    fn set_puzzles(&mut self, puzzles: Vec<Puzzle>) {
+        self.model.puzzles = puzzles;
+        self.model.puzzle_ids.borrow_mut().clear();
+        self.restore_puzzle_stats(0);
+        self.model.course_chapter = None;
+        self.model.reviewing = false;
+        self.model.current_puzzle = 0;
+        self.model.played.clear();
+        self.model.can_play = true;
+        self.model.text = "";
+        self.show_position();
+    }
+
+    fn show_error(&self, error: &str) {
+        let message_dialog = MessageDialog::new(Some(&self.window), DialogFlags::empty(), MessageType::Error, ButtonsType::Ok, error);
+        message_dialog.run();
+        message_dialog.destroy();
+    }
+
+    fn show_import_summary(&self, import: &Import) {
+        let summary = format!("{} puzzles loaded, {} games skipped", import.puzzles.len(), import.skipped_games);
+        // The puzzles found in each file are listed when importing several files, e.g. a folder.
+        let details: Vec<&String> =
+            if import.sources.len() > 1 {
+                import.sources.iter().chain(&import.errors).collect()
+            }
+            else {
+                import.errors.iter().collect()
+            };
+        if details.is_empty() {
+            self.show_info(&summary);
+            return;
+        }
+
+        let dialog = Dialog::new_with_buttons(
+            Some("Import summary"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("Ok", ResponseType::Ok)],
+        );
+        let content = gtk::Box::new(Vertical, 6);
+        content.set_border_width(12);
+        content.add(&Label::new(Some(&summary)));
+        let text_view = TextView::new();
+        text_view.set_editable(false);
+        text_view.set_monospace(true);
+        if let Some(buffer) = text_view.get_buffer() {
+            buffer.set_text(&details.iter().map(|line| line.as_str()).collect::<Vec<_>>().join("\n"));
+        }
+        let scrolled_window = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+        scrolled_window.set_size_request(600, 300);
+        scrolled_window.add(&text_view);
+        content.pack_start(&scrolled_window, true, true, 0);
+        dialog.get_content_area().pack_start(&content, true, true, 0);
+        dialog.show_all();
+        dialog.run();
+        dialog.destroy();
+    }
+
+    fn show_info(&self, message: &str) {
+        let message_dialog = MessageDialog::new(Some(&self.window), DialogFlags::empty(), MessageType::Info, ButtonsType::Ok, message);
+        message_dialog.run();
+        message_dialog.destroy();
+    }
+
+    fn show_position(&mut self) {
+        self.model.analysis.cancel();
+        self.model.demonstration = None;
+        self.model.free_analysis = None;
+        self.model.solved = false;
+        self.model.failed = false;
+        self.model.last_move.clear();
+        self.model.score = None;
+        self.model.credited = false;
+        self.model.off_script = self.model.engine_replies;
+        self.model.refutation.clear();
+        self.model.wrong_move = None;
+        self.model.slower_mate = None;
+        self.model.hint_level = 0;
+        self.model.showing_solution = false;
+        self.start_puzzle_clock();
+        self.model.premove = None;
+        self.model.reply_pending = false;
+        if let Some(puzzle) = self.model.puzzles.get(self.model.current_puzzle) {
+            self.model.boards = Boards::new(puzzle.position.clone(), puzzle.partner.clone());
+            let turn = puzzle.position.turn();
+            self.ground.emit(SetOrientation(turn));
+
+            // The partner plays the other color.
+            self.partner_ground.emit(SetOrientation(!turn));
+            *self.model.timeline.borrow_mut() = Timeline::new(puzzle);
+            self.model.partner_clock =
+                match puzzle.partner_clock {
+                    Some(clock) if clock.is_low() => format!("Partner's clock: {} — low on time!", clock),
+                    Some(clock) => format!("Partner's clock: {}", clock),
+                    None => String::new(),
+                };
+            self.model.partner_played = 0;
+        }
+        self.model.transfer_status.clear();
+        self.model.can_play_partner_mate = false;
+        self.model.partner_mate_played = None;
+        if self.model.partner_focused {
+            self.switch_board();
+        }
+        self.model.can_request = true;
+        self.model.requested = None;
+        self.play_partner_moves();
+        self.update_ground();
+        self.update_puzzle_info();
+        self.update_goal_status();
+        self.evaluate();
+    }
+
+    fn update_generation_status(&mut self) {
+        self.model.generation_status = generation_status(&self.model.queue, self.model.generation_paused);
+    }
+
+    fn update_goal_status(&mut self) {
+        let (goal, mate_in) =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) if self.model.demonstration.is_none() && self.model.free_analysis.is_none() =>
+                    (Some(puzzle.goal.clone()), puzzle.mate_in(&self.model.played)),
+                _ => (None, None),
+            };
+        let goal_status =
+            match (goal, mate_in) {
+                _ if self.model.failed => "Failed: retry the puzzle or go to the next one".to_string(),
+                (_, Some(_)) if self.model.boards.position.is_checkmate() => String::new(),
+                // A mate slower than the solution can be accepted when the engine plays the replies.
+                (_, Some(moves)) => format!("Mate in {}", moves.max(1)),
+                (Some(Goal::Defend(plies)), None) if plies > self.model.played.len() =>
+                    format!("Hold for {} more plies", plies - self.model.played.len()),
+                _ => String::new(),
+            };
+        self.model.goal_status =
+            match self.model.score {
+                Some((found, total)) if goal_status.is_empty() => score_text(found, total),
+                Some((found, total)) => format!("{} — {}", goal_status, score_text(found, total)),
+                None => goal_status,
+            };
+    }
+
+    fn update_puzzle_info(&mut self) {
+        self.model.puzzle_info =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) => {
+                    let difficulty = puzzle.difficulty
+                        .map(|difficulty| difficulty.to_string())
+                        .unwrap_or_else(|| "?".to_string());
+                    let mut info = format!("Puzzle {}/{} ({}) — difficulty {}", self.model.current_puzzle + 1,
+                        self.model.puzzles.len(), self.puzzle_id(self.model.current_puzzle), difficulty);
+                    if self.is_daily_puzzle() {
+                        info.push_str(" — puzzle of the day");
+                    }
+                    if let Some(ref chapter) = puzzle.chapter {
+                        info.push_str(&format!(" — chapter {}", chapter));
+                    }
+                    if let Some(clock) = puzzle.clock {
+                        info.push_str(&format!(" — clock {}", clock));
+                    }
+                    if puzzle.hints > 0 {
+                        info.push_str(&format!(" — {} hints used", puzzle.hints));
+                    }
+                    if puzzle.successes > 0 {
+                        info.push_str(&format!(" — solved {} times", puzzle.successes));
+                    }
+                    if let Some(best_score) = puzzle.best_score {
+                        info.push_str(&format!(" — best score {}%", best_score));
+                    }
+                    if puzzle.failures > 0 {
+                        info.push_str(&format!(" — failed {} times", puzzle.failures));
+                    }
+                    if let Some(ref players) = puzzle.players {
+                        info.push_str(&format!("\nBoard {:?}: {} – {}, partners {} – {}", players.main_board,
+                            players.board[0], players.board[1], players.partner[0], players.partner[1]));
+                    }
+                    if let Some(ref source) = puzzle.source {
+                        info.push_str(&format!("\nFrom {}", source));
+                    }
+                    info
+                },
+                None => String::new(),
+            };
+        self.model.source_url = self.model.puzzles.get(self.model.current_puzzle)
+            .and_then(|puzzle| puzzle.source.clone())
+            .filter(|source| source.starts_with("http://") || source.starts_with("https://"));
+    }
+
+    fn try_move(&mut self, mov: Option<&Move>) {
+        let mov =
+            match mov {
+                Some(mov) => mov.clone(),
+                None => return,
+            };
+        if self.model.demonstration.is_some() {
+            self.play_demonstration_move(&mov);
+            return;
+        }
+        if self.model.free_analysis.is_some() {
+            self.play_analysis_move(&mov);
+            return;
+        }
+
+        let (goal, expected, partner, request) =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) => (
+                    puzzle.goal.clone(),
+                    match puzzle.goal {
+                        // Success is on the partner's board: the capture must allow the scripted mate.
+                        Goal::PartnerMate(_) if self.model.played.is_empty() =>
+                            puzzle.enables_partner_mate(&self.model.boards, &mov),
+                        _ => puzzle.is_correct(&mov, &self.model.played) || {
+                            // The correct moves can be played in another order.
+                            let mut played = self.model.played.clone();
+                            played.push(mov.clone());
+                            puzzle.transposed_line(&played).is_some()
+                        },
+                    },
+                    puzzle.partner.clone(),
+                    puzzle.request,
+                ),
+                None => return,
+            };
+        // The solution starts by asking the partner for a piece.
+        if request.is_some() && self.model.requested != request {
+            self.wrong_answer("Wrong answer: ask your partner for a piece first");
+            return;
+        }
+        self.model.wrong_move = None;
+        self.model.slower_mate = None;
+        self.model.refutation.clear();
+        let mut position = self.model.boards.position.clone();
+        position.play_unchecked(&mov);
+        match goal {
+            Goal::Sit => {
+                self.model.wrong_move = Some(mov);
+                self.wrong_answer("Wrong answer");
+            },
+            _ if expected => self.play_move(&mov),
+            Goal::PartnerMate(_) => {
+                self.model.wrong_move = Some(mov);
+                self.wrong_answer("Wrong answer: your partner cannot mate with this");
+            },
+            // Any mating move is accepted, not only the one of the solution.
+            Goal::Mate if position.is_checkmate() => self.play_move(&mov),
+            // The engine tells if the move mates as fast, or slower.
+            Goal::Mate => self.check_move(goal, mov, partner),
+            // Any defense surviving the longest is correct: ask the engine.
+            Goal::Survive(_) => self.check_move(goal, mov, partner),
+            // When the engine plays the replies, any move keeping the win, or avoiding the mate, is correct.
+            Goal::Defend(_) | Goal::WinMaterial(_) if self.model.off_script => self.check_move(goal, mov, partner),
+            _ => {
+                self.model.wrong_move = Some(mov);
+                self.wrong_answer("Wrong answer");
+            },
+        }
+    }
+
+    /// Play against the engine from the current position, outside of the puzzle.
+    fn keep_playing(&mut self) {
+        self.model.demonstration = Some(Demonstration {
+            played: self.model.played.clone(),
+            position: self.model.boards.position.clone(),
+            sparring: true,
+        });
+        self.model.text = "";
+        self.update_goal_status();
+        if self.model.boards.position.turn() == self.current_player() {
+            self.model.can_play = !self.model.boards.position.is_game_over();
+        }
+        else {
+            self.ask_engine_reply();
+        }
+    }
+
+    /// Add a puzzle without solution after the current one, to play the `position` out against
+    /// the engine.
+    fn analyze_position(&mut self, position: Bughouse, partner: Option<Bughouse>) {
+        let puzzle = Puzzle::new(position, partner, Goal::Mate, Solution::new());
+        let index =
+            if self.model.puzzles.is_empty() {
+                0
+            }
+            else {
+                self.model.current_puzzle + 1
+            };
+        self.model.puzzles.insert(index, puzzle);
+        self.model.puzzle_ids.borrow_mut().clear();
+        self.model.current_puzzle = index;
+        self.model.played.clear();
+        self.model.can_play = true;
+        self.model.text = "";
+        self.show_position();
+        self.keep_playing();
+    }
+
+    fn check_move(&mut self, goal: Goal, mov: Move, partner: Option<Bughouse>) {
+        self.model.can_play = false;
+        self.model.text = "Checking move…";
+        self.model.analysis.send(Request::CheckMove {
+            goal,
+            mov,
+            partner,
+            position: self.model.boards.position.clone(),
+        });
+    }
+
+    fn play_move(&mut self, mov: &Move) {
+        self.play_puzzle_move(mov);
+        self.model.can_play = false;
+        self.update_goal_status();
+        self.evaluate();
+
+        let solved =
+            self.model.puzzles.get(self.model.current_puzzle)
+                .map(|puzzle| puzzle.is_solved(&self.model.boards, &self.model.played))
+                .unwrap_or(false);
+        if solved {
+            self.succeed();
+            self.model.can_play_partner_mate = self.model.puzzles.get(self.model.current_puzzle)
+                .map(|puzzle| !puzzle.partner_mate().is_empty())
+                .unwrap_or(false);
+        }
+        else {
+            self.schedule_opponent_move();
+        }
+    }
+
+    /// Play the user's move against the engine, outside of the puzzle.
+    fn play_demonstration_move(&mut self, mov: &Move) {
+        self.model.boards.position.play_unchecked(mov);
+        self.update_ground();
+        self.update_goal_status();
+        self.model.can_play = false;
+        self.evaluate();
+        self.ask_engine_reply();
+    }
+
+    fn ask_engine_reply(&mut self) {
+        if self.model.boards.position.is_game_over() {
+            self.model.refutation = self.demonstration_status(None);
+        }
+        else {
+            self.model.refutation = "Engine thinking…".to_string();
+            self.model.analysis.send(Request::BestMove(self.model.boards.position.clone()));
+        }
+    }
+
+    /// Detach the board from the solution, so that the user moves the pieces of both sides freely.
+    fn start_free_analysis(&mut self) {
+        if self.model.puzzles.is_empty() || self.model.demonstration.is_some() || self.model.showing_solution ||
+            self.model.rush.is_some()
+        {
+            // Release the toggle button.
+            self.model.free_analysis = None;
+            return;
+        }
+
+        // The move being checked and the opponent's reply are asked again at the end of the analysis.
+        self.model.analysis.cancel();
+        let clock_left = self.model.clock_deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        self.stop_puzzle_clock();
+        self.model.free_analysis = Some(FreeAnalysis {
+            boards: self.model.boards.clone(),
+            clock_left,
+            moves: vec![],
+            played: self.model.played.clone(),
+            reply_pending: self.model.reply_pending,
+        });
+        self.model.premove = None;
+        self.model.reply_pending = false;
+        self.model.can_play = true;
+        self.model.text = "Analysis: play the moves of both sides";
+        self.update_ground();
+        self.update_goal_status();
+    }
+
+    /// Come back to the state of the puzzle before the analysis.
+    fn end_free_analysis(&mut self) {
+        let free_analysis =
+            match self.model.free_analysis.take() {
+                Some(free_analysis) => free_analysis,
+                None => return,
+            };
+        self.model.boards = free_analysis.boards;
+        self.model.played = free_analysis.played;
+        self.model.can_play = !self.model.solved && !self.model.failed && !free_analysis.reply_pending;
+        self.model.text = if self.model.solved { "Success" } else { "" };
+        if let Some(left) = free_analysis.clock_left {
+            self.resume_puzzle_clock(left);
+        }
+        if free_analysis.reply_pending {
+            self.schedule_opponent_move();
+        }
+        self.update_ground();
+        self.update_partner_ground();
+        self.update_goal_status();
+        self.evaluate();
+    }
+
+    /// Play a move of either side during the analysis, giving its capture to the partner.
+    fn play_analysis_move(&mut self, mov: &Move) {
+        if let Err(error) = self.model.boards.play(mov) {
+            eprintln!("Cannot give the capture to the partner: {}", error);
+        }
+        if let Some(ref mut free_analysis) = self.model.free_analysis {
+            free_analysis.moves.push(mov.clone());
+        }
+        self.update_ground();
+        self.update_partner_ground();
+        self.evaluate();
+    }
+
+    /// Take back the last move of the analysis, by replaying the others from the puzzle state.
+    fn take_back_analysis_move(&mut self) {
+        let boards =
+            match self.model.free_analysis {
+                Some(ref mut free_analysis) if free_analysis.moves.pop().is_some() => {
+                    let mut boards = free_analysis.boards.clone();
+                    for mov in &free_analysis.moves {
+                        if let Err(error) = boards.play(mov) {
+                            eprintln!("Cannot give the capture to the partner: {}", error);
+                        }
+                    }
+                    boards
+                },
+                _ => return,
+            };
+        self.model.boards = boards;
+        self.update_ground();
+        self.update_partner_ground();
+        self.evaluate();
+    }
+
+    /// Play the engine's reply to the user's move, with `score` from the point of view of the user.
+    fn play_demonstration_reply(&mut self, mov: &Move, score: Option<Score>) {
+        self.model.boards.position.play_unchecked(mov);
+        self.update_ground();
+        self.model.can_play = !self.model.boards.position.is_game_over();
+        self.evaluate();
+        self.model.refutation = self.demonstration_status(score);
+    }
+
+    /// Tell whether the position reached against the engine shows that the wrong move fails, or
+    /// how the game goes when sparring.
+    fn demonstration_status(&self, score: Option<Score>) -> String {
+        let sparring = self.model.demonstration.as_ref()
+            .map(|demonstration| demonstration.sparring)
+            .unwrap_or(false);
+        let position = &self.model.boards.position;
+        if position.is_checkmate() {
+            let message =
+                match (position.turn() == self.current_player(), sparring) {
+                    (true, true) => "You are checkmated",
+                    (true, false) => "Refuted: you are checkmated",
+                    (false, true) => "Checkmate: you converted the position",
+                    (false, false) => "You checkmated the engine",
+                };
+            return message.to_string();
+        }
+
+        let score =
+            match score {
+                Some(score) => score,
+                None => return String::new(),
+            };
+        if sparring {
+            return format!("Evaluation: {}", score);
+        }
+
+        let refuted =
+            match (self.current_goal(), score) {
+                (Some(Goal::Mate), Score::Mate(moves)) | (Some(Goal::WinMaterial(_)), Score::Mate(moves)) => moves <= 0,
+                (Some(Goal::Mate), Score::Centipawns(_)) => true,
+                (Some(Goal::WinMaterial(_)), Score::Centipawns(centipawns)) => centipawns < WINNING_SCORE,
+                (Some(Goal::Defend(_)), Score::Mate(moves)) | (Some(Goal::Sit), Score::Mate(moves)) |
+                    (Some(Goal::Survive(_)), Score::Mate(moves)) => moves <= 0,
+                _ => false,
+            };
+        if refuted {
+            format!("Refuted ({}): go back to the puzzle or keep playing", score)
+        }
+        else {
+            format!("Evaluation: {}, keep playing to see the refutation", score)
+        }
+    }
+
+    /// Play a move of the puzzle, giving its capture to the partner of the player, followed by the
+    /// partner's moves played before the next one in the game.
+    fn play_puzzle_move(&mut self, mov: &Move) {
+        self.model.played.push(mov.clone());
+        self.model.hint_level = 0;
+        // After a transposition, the puzzle goes on following the line of the solution.
+        let transposed_line = self.model.puzzles.get(self.model.current_puzzle)
+            .filter(|_| self.model.demonstration.is_none() && !self.model.off_script)
+            .and_then(|puzzle| puzzle.transposed_line(&self.model.played));
+        if let Some(line) = transposed_line {
+            self.model.played = line;
+        }
+        self.model.can_request = false;
+        self.model.last_move = San::from_move(&self.model.boards.position, mov).to_string();
+        if let Some(message) = transfer_message(&self.model.boards.position, mov, self.current_player(), false) {
+            self.model.transfer_status = message;
+        }
+        if let Err(error) = self.model.boards.play(mov) {
+            eprintln!("Cannot give the capture to the partner: {}", error);
+        }
+        self.play_partner_moves();
+        self.update_ground();
+    }
+
+    /// Play the partner's moves of the game up to the next move of the puzzle, as long as the moves
+    /// played follow the main line. Their captures go to the pocket of the user. When the goal is
+    /// the partner's mate, any accepted capture is followed by the partner's mate.
+    fn play_partner_moves(&mut self) {
+        let puzzle =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) => puzzle,
+                None => return,
+            };
+        let on_script =
+            match puzzle.goal {
+                Goal::PartnerMate(_) => true,
+                Goal::Defend(_) | Goal::Mate | Goal::Sit | Goal::Survive(_) | Goal::WinMaterial(_) =>
+                    puzzle.solution.on_main_line(&self.model.played),
+            };
+        if !on_script || self.model.boards.partner.is_none() {
+            return;
+        }
+        let ply = self.model.played.len();
+        let player = puzzle.position.turn();
+        while let Some(partner_move) = puzzle.partner_moves.get(self.model.partner_played).filter(|partner_move| partner_move.ply <= ply) {
+            if let Some(ref partner_position) = self.model.boards.partner {
+                if let Some(message) = transfer_message(partner_position, &partner_move.mov, player, true) {
+                    self.model.transfer_status = message;
+                }
+            }
+            if let Err(error) = self.model.boards.play_partner(&partner_move.mov) {
+                eprintln!("Cannot play the partner's move: {}", error);
+                break;
+            }
+            self.model.partner_played += 1;
+        }
+        self.update_partner_ground();
+    }
+
+    /// Show the partner's board, from the point of view of the partner who plays the other color.
+    fn update_partner_ground(&self) {
+        let partner_position = self.model.boards.partner.clone().unwrap_or_default();
+        self.partner_ground.emit(SetPos(Pos::new(&partner_position)));
+        self.partner_ground.emit(SetPockets(partner_position.pockets().cloned().unwrap_or(Material::new()), !self.current_player()));
+    }
+
+    /// Show the current position on the board of the user.
+    fn update_ground(&mut self) {
+        let position = &self.model.boards.position;
+        self.ground.emit(SetPos(Pos::new(position)));
+        self.ground.emit(SetPockets(position.pockets().cloned().unwrap_or(Material::new()), self.current_player()));
+        self.model.pocket_status =
+            if self.model.drops_only || self.model.blindfold {
+                pocket_markup(position, self.current_player())
+            }
+            else {
+                String::new()
+            };
+        self.model.blindfold_status =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) if self.model.blindfold => {
+                    let last_move = if self.model.last_move.is_empty() { "none" } else { &self.model.last_move };
+                    format!("Position of the puzzle: {}\nLast move: {}", piece_list(&puzzle.position), last_move)
+                },
+                _ => String::new(),
+            };
+        self.model.timeline.borrow_mut().set_ply(self.model.played.len());
+        self.timeline.queue_draw();
+        self.update_annotations();
+    }
+
+    /// Show the moves played with their annotations and the partner's messages, and the arrows
+    /// and circles of the puzzle as long as the moves follow the main line.
+    fn update_annotations(&mut self) {
+        let puzzle =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) => puzzle,
+                None => {
+                    self.model.move_list.clear();
+                    self.model.partner_messages.clear();
+                    return;
+                },
+            };
+        self.model.move_list = puzzle.move_list(&self.model.played);
+        self.model.partner_messages = puzzle.partner_messages(&self.model.played)
+            .map(|message| format!("Partner: {}", message.text))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let shapes = self.current_shapes().iter()
+            .chain(&self.hint_shape())
+            .chain(&self.premove_shape())
+            .map(|shape| shape.to_draw_shape())
+            .collect();
+        self.ground.emit(SetShapes(shapes));
+    }
+
+    /// Show the next move of the solution in two steps: first the piece to move, then the move.
+    fn show_hint(&mut self) {
+        let (goal, next_move) =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) => (puzzle.goal.clone(), puzzle.next_move(&self.model.played).cloned()),
+                None => return,
+            };
+        let mov =
+            match next_move {
+                Some(mov) if !self.model.off_script => mov,
+                _ => {
+                    self.model.text =
+                        if goal == Goal::Sit {
+                            "Hint: every move loses"
+                        }
+                        else {
+                            "No hint: the engine plays the replies"
+                        };
+                    return;
+                },
+            };
+        if self.model.hint_level < 2 {
+            self.model.hint_level += 1;
+            if let Some(puzzle) = self.model.puzzles.get_mut(self.model.current_puzzle) {
+                puzzle.hints += 1;
+            }
+            self.save_puzzle_stats();
+            self.update_puzzle_info();
+        }
+        // The pockets are not on the board: the piece to drop is named.
+        self.model.text =
+            match mov {
+                Move::Put { role, .. } => drop_hint(role),
+                _ if self.model.hint_level == 1 => "Hint: move the circled piece",
+                _ => "Hint: play the move of the arrow",
+            };
+        self.update_annotations();
+    }
+
+    /// Count the puzzle as solved and, when enabled, go to the next unsolved puzzle after a delay.
+    fn succeed(&mut self) {
+        self.stop_puzzle_clock();
+        self.model.can_play = false;
+        self.model.solved = true;
+        self.model.text = "Success";
+        // Solving the same attempt again after taking back the last moves counts nothing more.
+        if self.model.credited {
+            return;
+        }
+
+        self.model.credited = true;
+        let unlocked_chapters = self.unlocked_chapters();
+        if let Some(puzzle) = self.model.puzzles.get_mut(self.model.current_puzzle) {
+            puzzle.successes += 1;
+        }
+        self.record_course_progress();
+        if self.model.course_chapter.is_some() && self.unlocked_chapters() > unlocked_chapters {
+            self.model.text = "Success: the next chapter is unlocked";
+        }
+        self.record_score();
+        self.save_puzzle_stats();
+        self.review_success();
+        if self.model.rush.is_none() && self.is_daily_puzzle() {
+            self.model.daily_stats.solve(daily::today());
+            if let Err(error) = self.model.daily_stats.save() {
+                eprintln!("Cannot save the daily statistics: {}", error);
+            }
+            self.update_daily_tooltip();
+        }
+        self.update_puzzle_info();
+        if let Some(ref mut rush) = self.model.rush {
+            rush.score += 1;
+            self.update_rush_status();
+            timeout(self.model.relm.stream(), 500, || RushNext);
+        }
+        else if let Some(delay) = self.model.settings.auto_advance_delay {
+            let index = self.model.current_puzzle;
+            timeout(self.model.relm.stream(), delay, move || AutoAdvance(index));
+        }
+    }
+
+    /// Start the clock of the puzzle when enabled in the settings. The clocks of the previous
+    /// puzzles stop.
+    fn start_puzzle_clock(&mut self) {
+        self.model.clock_id += 1;
+        self.model.clock_deadline = self.model.settings.puzzle_clock
+            .filter(|_| !self.model.puzzles.is_empty())
+            .map(|seconds| Instant::now() + Duration::from_secs(u64::from(seconds)));
+        self.update_clock_status();
+        if self.model.clock_deadline.is_some() {
+            let id = self.model.clock_id;
+            timeout(self.model.relm.stream(), CLOCK_TICK, move || ClockTick(id));
+        }
+    }
+
+    /// Stop the clock between the puzzles, once the puzzle is solved or failed.
+    /// Run the clock of the puzzle again with the time `left` when it was paused.
+    fn resume_puzzle_clock(&mut self, left: Duration) {
+        self.model.clock_id += 1;
+        self.model.clock_deadline = Some(Instant::now() + left);
+        self.update_clock_status();
+        let id = self.model.clock_id;
+        timeout(self.model.relm.stream(), CLOCK_TICK, move || ClockTick(id));
+    }
+
+    fn stop_puzzle_clock(&mut self) {
+        self.model.clock_deadline = None;
+        self.update_clock_status();
+    }
+
+    fn update_clock_status(&mut self) {
+        self.model.clock_status =
+            match self.model.clock_deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    let seconds = if deadline > now { (deadline - now).as_secs() } else { 0 };
+                    format!("Time left: {}:{:02}", seconds / 60, seconds % 60)
+                },
+                None => String::new(),
+            };
+    }
+
+    /// Count the puzzle as failed when its clock runs out.
+    fn flag(&mut self) {
+        self.model.wrong_move = None;
+        self.wrong_answer("Time is up");
+        self.fail();
+    }
+
+    /// Count a failure of the current puzzle and lock the board until it is retried.
+    fn fail(&mut self) {
+        if self.model.failed {
+            return;
+        }
+
+        self.model.failed = true;
+        self.model.can_play = false;
+        self.stop_puzzle_clock();
+        if let Some(puzzle) = self.model.puzzles.get_mut(self.model.current_puzzle) {
+            puzzle.failures += 1;
+        }
+        self.save_puzzle_stats();
+        self.update_puzzle_info();
+        self.update_goal_status();
+    }
+
+    /// Show why the move is wrong. In a puzzle rush, it counts as a mistake and the next puzzle is
+    /// served. With a single try, the puzzle fails.
+    fn wrong_answer(&mut self, text: &'static str) {
+        self.model.text = text;
+        self.record_score();
+        self.review_mistake();
+        if let Some(ref mut rush) = self.model.rush {
+            rush.mistakes += 1;
+            self.model.can_play = false;
+            self.update_rush_status();
+            timeout(self.model.relm.stream(), 1000, || RushNext);
+        }
+        else if self.model.settings.one_try {
+            self.fail();
+        }
+    }
+
+    /// Give credit for the share of the moves of the solution found before the first mistake, the
+    /// whole credit after a success without mistakes, and keep the best score of the puzzle.
+    fn record_score(&mut self) {
+        if self.model.score.is_some() || self.model.demonstration.is_some() {
+            return;
+        }
+
+        let puzzle =
+            match self.model.puzzles.get_mut(self.model.current_puzzle) {
+                Some(puzzle) => puzzle,
+                None => return,
+            };
+        // The player plays the odd plies of the line, and there is nothing to find when sitting.
+        let total = (puzzle.solution.len() + 1) / 2;
+        if total == 0 {
+            return;
+        }
+        let found =
+            if self.model.solved {
+                total
+            }
+            else {
+                ((self.model.played.len() + 1) / 2).min(total)
+            };
+        let percent = (found * 100 / total) as u32;
+        puzzle.best_score = Some(puzzle.best_score.map_or(percent, |best| best.max(percent)));
+        self.model.score = Some((found, total));
+        self.save_puzzle_stats();
+        self.update_puzzle_info();
+        self.update_goal_status();
+    }
+
+    /// Serve the next puzzle of the puzzle rush, or end it when the time is over, after the last
+    /// mistake or when no puzzle is left. The puzzles removed from the set since the start of the
+    /// run are skipped.
+    fn rush_next(&mut self) {
+        loop {
+            let next =
+                match self.model.rush {
+                    Some(ref mut rush) if !rush.is_over() => rush.next_puzzle(),
+                    Some(_) => None,
+                    None => return,
+                };
+            let id =
+                match next {
+                    Some(id) => id,
+                    None => {
+                        self.end_rush();
+                        return;
+                    },
+                };
+            if let Some(index) = (0..self.model.puzzles.len()).find(|&index| self.puzzle_id(index) == id) {
+                self.model.played.clear();
+                self.model.can_play = true;
+                self.model.text = "";
+                self.model.current_puzzle = index;
+                self.show_position();
+                return;
+            }
+        }
+    }
+
+    /// Record the score of the puzzle rush or of the streak among the best ones.
+    fn end_rush(&mut self) {
+        let (minutes, score) =
+            match self.model.rush {
+                Some(ref rush) => {
+                    self.model.best_scores.add(rush);
+                    (rush.minutes, rush.score)
+                },
+                None => return,
+            };
+        self.model.rush = None;
+        self.update_rush_status();
+        self.model.can_play = false;
+        if let Err(error) = self.model.best_scores.save() {
+            eprintln!("Cannot save the best scores: {}", error);
+        }
+        let message =
+            match minutes {
+                Some(minutes) => {
+                    let best = self.model.best_scores.of_duration(minutes)
+                        .next()
+                        .map(|best| best.score)
+                        .unwrap_or(score);
+                    format!("Puzzle rush over: {} puzzles solved in {} minutes (best score: {})", score, minutes, best)
+                },
+                None => format!("Streak over: {} puzzles solved in a row (longest streak: {})", score,
+                    self.model.best_scores.longest_streak),
+            };
+        self.update_streak_tooltip();
+        self.show_info(&message);
+    }
+
+    /// Index of the puzzle of the day, picked among the puzzles on the first request of the day.
+    /// It is added back to the puzzles when the ones loaded since then don't contain it.
+    fn daily_puzzle(&mut self) -> Option<usize> {
+        let day = daily::today();
+        let id =
+            match self.model.daily_stats.puzzle(day) {
+                Some(daily) => daily.id.clone(),
+                None => {
+                    let index = daily::puzzle_index(day, (0..self.model.puzzles.len()).map(|index| self.puzzle_id(index)))?;
+                    self.model.daily_stats.puzzle = Some(DailyPuzzle {
+                        day,
+                        id: self.puzzle_id(index),
+                        puzzle: self.model.puzzles[index].clone(),
+                    });
+                    if let Err(error) = self.model.daily_stats.save() {
+                        eprintln!("Cannot save the daily statistics: {}", error);
+                    }
+                    return Some(index);
+                },
+            };
+        if let Some(index) = (0..self.model.puzzles.len()).find(|&index| self.puzzle_id(index) == id) {
+            return Some(index);
+        }
+
+        let puzzle = self.model.daily_stats.puzzle(day)?.puzzle.clone();
+        self.model.puzzles.push(puzzle);
+        let index = self.model.puzzles.len() - 1;
+        self.restore_puzzle_stats(index);
+        Some(index)
+    }
+
+    fn is_daily_puzzle(&self) -> bool {
+        match self.model.daily_stats.puzzle(daily::today()) {
+            Some(daily) if self.model.current_puzzle < self.model.puzzles.len() =>
+                self.puzzle_id(self.model.current_puzzle) == daily.id,
+            _ => false,
+        }
+    }
+
+    fn update_daily_tooltip(&mut self) {
+        let day = daily::today();
+        let solved = if self.model.daily_stats.last_solved == Some(day) { "solved" } else { "not solved yet" };
+        self.model.daily_tooltip = format!("Go to the puzzle of the day, {} (daily streak: {}, longest: {})", solved,
+            self.model.daily_stats.streak(day), self.model.daily_stats.longest_streak);
+    }
+
+    fn update_streak_tooltip(&mut self) {
+        self.model.streak_tooltip = format!("Solve puzzles from the easiest until the first wrong answer (longest streak: {})",
+            self.model.best_scores.longest_streak);
+    }
+
+    fn update_rush_status(&mut self) {
+        self.model.rush_status = self.model.rush.as_ref()
+            .map(Rush::status)
+            .unwrap_or_default();
+    }
+
+    /// Check if the puzzle at `index` is served by the navigation, which skips the puzzles without
+    /// a drop as key move while training the drops, and the puzzles of the other chapters while
+    /// following the course.
+    fn is_served(&self, index: usize) -> bool {
+        let puzzle = &self.model.puzzles[index];
+        (!self.model.drops_only || puzzle.starts_with_drop())
+            && (self.model.course_chapter.is_none() || puzzle.chapter == self.model.course_chapter)
+            && (!self.model.reviewing || self.model.review_queue.contains(&self.puzzle_id(index)))
+    }
+
+    /// Id of the puzzle at `index`, from the cache.
+    fn puzzle_id(&self, index: usize) -> String {
+        let mut ids = self.model.puzzle_ids.borrow_mut();
+        ids.resize(self.model.puzzles.len(), None);
+        ids[index].get_or_insert_with(|| self.model.puzzles[index].id()).clone()
+    }
+
+    /// Serve only the failed puzzles until each is solved twice in a row.
+    fn set_reviewing(&mut self, reviewing: bool) {
+        if reviewing {
+            let queued = (0..self.model.puzzles.len())
+                .any(|index| self.model.review_queue.contains(&self.puzzle_id(index)));
+            if self.model.rush.is_some() || !queued {
+                // Release the toggle button.
+                self.model.reviewing = false;
+                if self.model.review_queue.is_empty() {
+                    self.show_info("No mistake to review");
+                }
+                else if !queued {
+                    self.show_info("None of these puzzles is to review");
+                }
+                return;
+            }
+
+            self.model.reviewing = true;
+            self.next_review_puzzle();
+        }
+        else {
+            self.model.reviewing = false;
+        }
+    }
+
+    /// Go to the next puzzle to review, coming back to the first ones after the last one.
+    fn next_review_puzzle(&mut self) {
+        let count = self.model.puzzles.len();
+        let next = (1..=count)
+            .map(|offset| (self.model.current_puzzle + offset) % count)
+            .find(|&index| self.is_served(index));
+        match next {
+            Some(index) => {
+                self.model.played.clear();
+                self.model.can_play = true;
+                self.model.text = "";
+                self.model.current_puzzle = index;
+                self.show_position();
+            },
+            None => {
+                self.model.reviewing = false;
+                self.model.text = "Success: no mistake left to review";
+            },
+        }
+    }
+
+    /// Queue the current puzzle for the review of the mistakes, or start its review over.
+    fn review_mistake(&mut self) {
+        if self.model.current_puzzle < self.model.puzzles.len() {
+            let id = self.puzzle_id(self.model.current_puzzle);
+            self.model.review_queue.mistake(id);
+            if let Err(error) = self.model.review_queue.save() {
+                eprintln!("Cannot save the review queue: {}", error);
+            }
+        }
+        self.update_review_tooltip();
+    }
+
+    /// Count a success without mistake of the current puzzle for its review, ending the review
+    /// when no puzzle is left to review.
+    fn review_success(&mut self) {
+        let without_mistake = self.model.score.map_or(false, |(found, total)| found == total);
+        if !without_mistake || self.model.current_puzzle >= self.model.puzzles.len() {
+            return;
+        }
+
+        let id = self.puzzle_id(self.model.current_puzzle);
+        if !self.model.review_queue.solve(&id) {
+            return;
+        }
+
+        if let Err(error) = self.model.review_queue.save() {
+            eprintln!("Cannot save the review queue: {}", error);
+        }
+        self.update_review_tooltip();
+        if self.model.reviewing && !(0..self.model.puzzles.len()).any(|index| self.is_served(index)) {
+            self.model.reviewing = false;
+            self.model.text = "Success: no mistake left to review";
+        }
+    }
+
+    fn update_review_tooltip(&mut self) {
+        self.model.review_tooltip = format!("Drill the puzzles you failed until each is solved twice in a row ({} to review)",
+            self.model.review_queue.len());
+    }
+
+    /// Chapters of the course, with the progress of the user.
+    fn chapters(&self) -> Vec<course::Chapter> {
+        course::chapters(&self.model.puzzles, self.model.settings.chapter_unlock_rate, |index| self.is_course_solved(index))
+    }
+
+    /// Check if the puzzle at `index` was solved, in this run or in a previous one.
+    fn is_course_solved(&self, index: usize) -> bool {
+        self.model.puzzles[index].successes > 0 || self.model.course_progress.is_solved(&self.puzzle_id(index))
+    }
+
+    /// Save that the current puzzle is solved when it is part of the course.
+    fn record_course_progress(&mut self) {
+        let index = self.model.current_puzzle;
+        if self.model.puzzles.get(index).map_or(true, |puzzle| puzzle.chapter.is_none()) {
+            return;
+        }
+
+        let id = self.puzzle_id(index);
+        self.model.course_progress.solve(id);
+        if let Err(error) = self.model.course_progress.save() {
+            eprintln!("Cannot save the course progress: {}", error);
+        }
+    }
+
+    /// Give back their saved statistics to the puzzles from `start`, once loaded.
+    fn restore_puzzle_stats(&mut self, start: usize) {
+        for index in start..self.model.puzzles.len() {
+            let id = self.puzzle_id(index);
+            self.model.puzzle_stats.restore(&id, &mut self.model.puzzles[index]);
+        }
+    }
+
+    /// Save the statistics of the current puzzle, after they change.
+    fn save_puzzle_stats(&mut self) {
+        let index = self.model.current_puzzle;
+        if index >= self.model.puzzles.len() {
+            return;
+        }
+
+        let id = self.puzzle_id(index);
+        self.model.puzzle_stats.record(id, &self.model.puzzles[index]);
+        if let Err(error) = self.model.puzzle_stats.save() {
+            eprintln!("Cannot save the puzzle statistics: {}", error);
+        }
+    }
+
+    /// Number of chapters of the course unlocked by the success rate of the previous ones.
+    fn unlocked_chapters(&self) -> usize {
+        self.chapters().iter()
+            .filter(|chapter| chapter.unlocked)
+            .count()
+    }
+
+    /// Serve only the puzzles of the chapter, starting from its first unsolved puzzle, or serve all
+    /// the puzzles again when no chapter is given.
+    fn start_chapter(&mut self, chapter: Option<&course::Chapter>) {
+        self.model.course_chapter = chapter.map(|chapter| chapter.name.clone());
+        if let Some(chapter) = chapter {
+            let first = chapter.puzzles.iter()
+                .find(|&&index| !self.is_course_solved(index))
+                .or_else(|| chapter.puzzles.first());
+            if let Some(&index) = first {
+                self.model.played.clear();
+                self.model.can_play = true;
+                self.model.text = "";
+                self.model.current_puzzle = index;
+                self.show_position();
+            }
+        }
+    }
+
+    /// Serve only the puzzles whose key move is a drop, going to the next one if the current
+    /// puzzle starts otherwise.
+    fn set_drops_only(&mut self, drops_only: bool) {
+        self.model.drops_only = drops_only;
+        let count = self.model.puzzles.len();
+        if drops_only && count > 0 && !self.is_served(self.model.current_puzzle) {
+            let next = (1..count)
+                .map(|offset| (self.model.current_puzzle + offset) % count)
+                .find(|&index| self.is_served(index));
+            match next {
+                Some(index) => {
+                    self.model.played.clear();
+                    self.model.can_play = true;
+                    self.model.text = "";
+                    self.model.current_puzzle = index;
+                    self.show_position();
+                },
+                None => self.model.text = "No puzzle starts with a drop",
+            }
+        }
+        self.update_ground();
+    }
+
+    /// Go to the first puzzle after the current one which was never solved.
+    fn next_unsolved_puzzle(&mut self) {
+        let next = (self.model.current_puzzle + 1..self.model.puzzles.len())
+            .find(|&index| self.model.puzzles[index].successes == 0 && self.is_served(index));
+        match next {
+            Some(index) => {
+                self.model.played.clear();
+                self.model.can_play = true;
+                self.model.text = "";
+                self.model.current_puzzle = index;
+                self.show_position();
+            },
+            None => self.model.text = "Success: no unsolved puzzle left",
+        }
+    }
+
+    /// Enter a drop with the keyboard: the letter of the piece, then the file and the rank of the
+    /// square, e.g. "P", "e" and "6". Any other key cancels the drop.
+    fn type_drop_key(&mut self, letter: char) {
+        match self.model.typed_drop.take() {
+            None => {
+                match Role::from_char(letter.to_ascii_lowercase()) {
+                    Some(Role::King) | None => (),
+                    Some(role) => {
+                        self.model.typed_drop = Some((role, None));
+                        self.model.text = "Drop: type the file, then the rank of the square";
+                    },
+                }
+            },
+            Some((role, None)) if ('a'..='h').contains(&letter) => {
+                self.model.typed_drop = Some((role, Some(letter)));
+                self.model.text = "Drop: type the rank of the square";
+            },
+            Some((role, Some(file))) => {
+                self.model.text = "";
+                if let Ok(to) = format!("{}{}", file, letter).parse::<Square>() {
+                    let stream = self.model.relm.stream();
+                    if self.model.partner_focused {
+                        if let Some(ref partner) = self.model.boards.partner {
+                            stream.emit(PartnerPieceDrop(Piece { color: partner.turn(), role }, to));
+                        }
+                    }
+                    else {
+                        stream.emit(PieceDrop(Piece { color: self.model.boards.position.turn(), role }, to));
+                    }
+                }
+            },
+            Some(_) => self.model.text = "",
+        }
+    }
+
+    /// Take back the last move of the user with the reply of the opponent, by replaying the puzzle
+    /// up to the move before.
+    fn undo(&mut self) {
+        let mut played = self.model.played.clone();
+        if played.is_empty() {
+            return;
+        }
+
+        if self.model.boards.position.turn() == self.current_player() {
+            played.pop();
+        }
+        played.pop();
+        let on_solution = self.model.puzzles.get(self.model.current_puzzle)
+            .map(|puzzle| puzzle.solution.after(&played).is_some())
+            .unwrap_or(false);
+        let requested = self.model.requested;
+        // Taking back a move doesn't give more time, nor credit for the moves after a mistake.
+        let clock_deadline = self.model.clock_deadline;
+        let score = self.model.score;
+        let credited = self.model.credited;
+        self.model.played.clear();
+        self.model.can_play = true;
+        self.model.text = "";
+        self.show_position();
+        self.model.requested = requested;
+        self.model.clock_deadline = clock_deadline;
+        self.model.score = score;
+        self.model.credited = credited;
+        self.model.can_request = played.is_empty() && requested.is_none();
+        for mov in &played {
+            self.play_puzzle_move(mov);
+        }
+        // The engine plays the replies again if the moves left the solution.
+        self.model.off_script = self.model.engine_replies || !on_solution;
+        self.update_goal_status();
+        self.evaluate();
+    }
+
+    /// Play the rest of the solution on the board, from the start if the moves played left it,
+    /// counting the puzzle as failed.
+    fn show_solution(&mut self) {
+        self.record_score();
+        self.review_mistake();
+        let score = self.model.score;
+        let failed = self.model.failed;
+        let on_solution =
+            match self.model.puzzles.get_mut(self.model.current_puzzle) {
+                Some(puzzle) => {
+                    // The failure is already counted when the attempt failed.
+                    if !failed {
+                        puzzle.failures += 1;
+                    }
+                    puzzle.solution.after(&self.model.played).is_some()
+                },
+                None => return,
+            };
+        self.save_puzzle_stats();
+        if self.model.demonstration.is_some() || self.model.off_script || !on_solution {
+            self.model.played.clear();
+            self.show_position();
+            self.model.score = score;
+        }
+        self.stop_puzzle_clock();
+        self.model.premove = None;
+        self.model.reply_pending = false;
+        // The scripted replies are played, whether the engine plays them otherwise.
+        self.model.off_script = false;
+        self.model.can_play = false;
+        self.model.showing_solution = true;
+        self.model.text = "Showing the solution…";
+        self.update_puzzle_info();
+        timeout(self.model.relm.stream(), self.model.settings.solution_delay(), || ShowSolutionMove);
+    }
+
+    fn show_solution_move(&mut self) {
+        if !self.model.showing_solution {
+            return;
+        }
+        let mov = self.model.puzzles.get(self.model.current_puzzle)
+            .and_then(|puzzle| puzzle.next_move(&self.model.played).cloned());
+        match mov {
+            Some(mov) => {
+                self.play_puzzle_move(&mov);
+                self.update_goal_status();
+                timeout(self.model.relm.stream(), self.model.settings.solution_delay(), || ShowSolutionMove);
+            },
+            None => {
+                self.model.showing_solution = false;
+                self.model.text =
+                    match self.current_goal() {
+                        Some(Goal::Sit) => "The solution is to sit: every move loses",
+                        _ => "End of the solution",
+                    };
+            },
+        }
+    }
+
+    /// Circle on the piece to move, or arrow of the move, of the hints shown for the next move.
+    fn hint_shape(&self) -> Option<Shape> {
+        if self.model.hint_level == 0 || self.model.off_script || self.model.demonstration.is_some() ||
+            self.model.free_analysis.is_some()
+        {
+            return None;
+        }
+        let puzzle = self.model.puzzles.get(self.model.current_puzzle)?;
+        let mov = puzzle.next_move(&self.model.played)?;
+        let (orig, dest) =
+            match *mov {
+                Move::Put { to, .. } if self.model.hint_level > 1 => (to, to),
+                Move::Put { .. } => return None,
+                _ if self.model.hint_level > 1 => (mov.from()?, mov.to()),
+                _ => (mov.from()?, mov.from()?),
+            };
+        Some(Shape {
+            orig,
+            dest,
+            brush: DrawBrush::Blue,
+        })
+    }
+
+    /// Arrows and circles of the puzzle for the moves played, if they follow the main line.
+    fn current_shapes(&self) -> Vec<Shape> {
+        match self.model.puzzles.get(self.model.current_puzzle) {
+            Some(puzzle) if self.model.demonstration.is_none() && self.model.free_analysis.is_none() &&
+                puzzle.solution.on_main_line(&self.model.played) =>
+                puzzle.annotation(self.model.played.len())
+                    .map(|annotation| annotation.shapes.clone())
+                    .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// Play the opponent's reply after a delay, unless the puzzle is reset in between.
+    fn schedule_opponent_move(&mut self) {
+        self.model.reply_id += 1;
+        self.model.reply_pending = true;
+        let id = self.model.reply_id;
+        timeout(self.model.relm.stream(), 500, move || PlayOpponentMove(id));
+    }
+
+    fn play_opponent_move(&mut self, mov: &Move) {
+        self.model.can_play = true;
+        self.model.reply_pending = false;
+        self.play_puzzle_move(mov);
+        self.update_goal_status();
+        self.evaluate();
+
+        if let Some(Goal::Survive(_)) = self.current_goal() {
+            if self.model.boards.position.is_checkmate() {
+                self.succeed();
+            }
+        }
+
+        if let Some(premove) = self.model.premove.take() {
+            if self.model.can_play {
+                self.play_premove(premove);
+            }
+            else {
+                self.update_annotations();
+            }
+        }
+    }
+
+    /// Remember the move entered while the opponent's reply is pending.
+    fn set_premove(&mut self, premove: Premove) {
+        self.model.premove = Some(premove);
+        self.model.text = "Premove: played right after the reply";
+        // The piece goes back to its square until the premove is played.
+        self.update_ground();
+    }
+
+    fn play_premove(&mut self, premove: Premove) {
+        let legals = self.model.boards.position.legals();
+        let mov =
+            match premove {
+                Premove::Drop(role, to) => legals.iter().find(|mov| **mov == Move::Put { role, to }),
+                Premove::Move(orig, dest, promotion) => legals.iter().find(|mov| {
+                    mov.from() == Some(orig) && mov.to() == dest && mov.promotion() == promotion
+                }),
+            };
+        match mov {
+            Some(mov) => {
+                self.model.text = "";
+                self.try_move(Some(mov));
+            },
+            None => {
+                self.model.text = "Premove cancelled: it is illegal after the reply";
+                self.update_annotations();
+            },
+        }
+    }
+
+    /// Arrow of the premove, or circle where the piece is dropped.
+    fn premove_shape(&self) -> Option<Shape> {
+        let (orig, dest) =
+            match self.model.premove? {
+                Premove::Drop(_, to) => (to, to),
+                Premove::Move(orig, dest, _) => (orig, dest),
+            };
+        Some(Shape {
+            orig,
+            dest,
+            brush: DrawBrush::Yellow,
+        })
+    }
+
+    /// Go back on the partner's board to the capture of the solution, for the user to play the
+    /// partner's mate against the scripted defense.
+    fn start_partner_mate(&mut self) {
+        let puzzle =
+            match self.model.puzzles.get(self.model.current_puzzle) {
+                Some(puzzle) => puzzle,
+                None => return,
+            };
+        let mut boards = Boards::new(puzzle.position.clone(), puzzle.partner.clone());
+        let result = puzzle.partner_moves.iter()
+            .filter(|partner_move| partner_move.ply == 0)
+            .try_for_each(|partner_move| boards.play_partner(&partner_move.mov))
+            .and_then(|()| self.model.played.iter().try_for_each(|mov| boards.play(mov)));
+        if let Err(error) = result {
+            eprintln!("Cannot replay the capture: {}", error);
+            return;
+        }
+        self.model.boards = boards;
+        self.model.partner_mate_played = Some(0);
+        self.model.can_play_partner_mate = false;
+        if !self.model.partner_focused {
+            self.switch_board();
+        }
+        self.model.text = "Play your partner's mate";
+        self.update_ground();
+        self.update_partner_ground();
+        // The script can start with a move of the partner's opponent.
+        let partner_turn = self.model.boards.partner.as_ref().map(|partner| partner.turn());
+        if partner_turn == Some(self.current_player()) {
+            timeout(self.model.relm.stream(), 500, || PlayPartnerDefense);
+        }
+    }
+
+    /// Play the user's move on the partner's board, if it follows the partner's mate or mates.
+    fn try_partner_move(&mut self, mov: Option<Move>) {
+        let played =
+            match self.model.partner_mate_played {
+                Some(played) => played,
+                None => return,
+            };
+        // The user plays the partner, who has the other color.
+        let partner_turn = self.model.boards.partner.as_ref().map(|partner| partner.turn());
+        let (mov, script) =
+            match (mov, self.model.puzzles.get(self.model.current_puzzle)) {
+                (Some(mov), Some(puzzle)) if partner_turn == Some(!puzzle.position.turn()) => (mov, puzzle.partner_mate()),
+                _ => {
+                    self.update_partner_ground();
+                    return;
+                },
+            };
+        let mut boards = self.model.boards.clone();
+        let mates = boards.play_partner(&mov).is_ok() &&
+            boards.partner.as_ref().map(|partner| partner.is_checkmate()).unwrap_or(false);
+        if script.get(played) != Some(&mov) && !mates {
+            self.model.text = "Wrong move: this is not the mate";
+            self.update_partner_ground();
+            return;
+        }
+
+        self.play_partner_mate_move(&mov, played);
+        if mates {
+            self.model.partner_mate_played = None;
+            self.model.text = "Checkmate: your partner wins";
+        }
+        else {
+            self.model.text = "";
+            timeout(self.model.relm.stream(), 500, || PlayPartnerDefense);
+        }
+    }
+
+    /// Play the reply of the partner's opponent from the partner's mate.
+    fn play_partner_defense(&mut self) {
+        let played =
+            match self.model.partner_mate_played {
+                Some(played) => played,
+                None => return,
+            };
+        let mov = self.model.puzzles.get(self.model.current_puzzle)
+            .and_then(|puzzle| puzzle.partner_mate().get(played).cloned());
+        if let Some(mov) = mov {
+            self.play_partner_mate_move(&mov, played);
+        }
+    }
+
+    fn play_partner_mate_move(&mut self, mov: &Move, played: usize) {
+        if let Some(ref partner_position) = self.model.boards.partner {
+            if let Some(message) = transfer_message(partner_position, mov, self.current_player(), true) {
+                self.model.transfer_status = message;
+            }
+        }
+        if let Err(error) = self.model.boards.play_partner(mov) {
+            eprintln!("Cannot play the partner's move: {}", error);
+        }
+        self.model.partner_mate_played = Some(played + 1);
+        self.update_ground();
+        self.update_partner_ground();
+    }
+
+    /// Show the partner's board beside the board of the user or over one of its corners, with the
+    /// size of the settings.
+    fn apply_partner_layout(&mut self) {
+        let layout = self.model.settings.partner_board;
+        let size = layout.size as i32;
+        self.partner_ground.widget().set_size_request(size, size);
+        self.timeline.set_size_request(size, timeline::HEIGHT);
+        if layout.picture_in_picture != self.model.partner_overlaid {
+            if layout.picture_in_picture {
+                self.boards_box.remove(&self.partner_column);
+                self.board_overlay.add_overlay(&self.partner_column);
+            }
+            else {
+                self.board_overlay.remove(&self.partner_column);
+                self.boards_box.add(&self.partner_column);
+            }
+            self.model.partner_overlaid = layout.picture_in_picture;
+        }
+        let (halign, valign) =
+            match (layout.picture_in_picture, layout.corner) {
+                (false, _) => (Align::Fill, Align::Fill),
+                (true, Corner::BottomLeft) => (Align::Start, Align::End),
+                (true, Corner::BottomRight) => (Align::End, Align::End),
+                (true, Corner::TopLeft) => (Align::Start, Align::Start),
+                (true, Corner::TopRight) => (Align::End, Align::Start),
+            };
+        self.partner_column.set_halign(halign);
+        self.partner_column.set_valign(valign);
+    }
+
+    /// Give the move input to the other board, as long as the partner's board is shown.
+    fn switch_board(&mut self) {
+        let partner_focused = !self.model.partner_focused && self.partner_column.get_visible();
+        self.model.partner_focused = partner_focused;
+        if partner_focused {
+            self.partner_ground.widget().grab_focus();
+        }
+        else {
+            self.ground.widget().grab_focus();
+        }
+    }
+
+    fn current_player(&self) -> Color {
+        self.model.puzzles.get(self.model.current_puzzle)
+            .map(|puzzle| puzzle.position.turn())
+            .unwrap_or(Color::White)
+    }
+
+    /// Start analyzing the current position for the evaluation bar.
+    fn evaluate(&mut self) {
+        if !self.model.show_evaluation {
+            return;
+        }
+
+        self.model.evaluation.cancel();
+        let position = &self.model.boards.position;
+        if position.is_checkmate() {
+            let score =
+                if position.turn() == self.current_player() {
+                    Score::Mate(0)
+                }
+                else {
+                    Score::Mate(1)
+                };
+            self.set_evaluation(score);
+        }
+        else {
+            self.model.evaluation.send(Request::Evaluate(position.clone()));
+        }
+    }
+
+    /// Show `score`, from the point of view of the player, in the evaluation bar.
+    fn set_evaluation(&mut self, score: Score) {
+        self.model.evaluation_fraction =
+            match score {
+                Score::Centipawns(centipawns) => 1.0 / (1.0 + 10_f64.powf(-f64::from(centipawns) / 400.0)),
+                Score::Mate(moves) if moves > 0 => 1.0,
+                Score::Mate(_) => 0.0,
+            };
+        self.model.evaluation_text = score.to_string();
+    }
+
+    fn current_goal(&self) -> Option<Goal> {
+        self.model.puzzles.get(self.model.current_puzzle)
+            .map(|puzzle| puzzle.goal.clone())
+    }
 
     view! {
         #[name="window"]
@@ -291,117 +3729,699 @@ impl Widget for Win {
             gtk::Box {
                 orientation: Vertical,
                 gtk::Toolbar {
-                    gtk::ToolButton {
+                    #[name="import_button"]
+                    gtk::MenuToolButton {
                         icon_name: Some("document-open"),
                         label: Some("Import PGN files"),
+                        tooltip_text: Some("Import the puzzles of PGN files, or of a recently imported file from the menu"),
                         clicked => ImportPGN,
                     },
+                    gtk::ToolButton {
+                        icon_name: Some("document-open-remote"),
+                        label: Some("From URL…"),
+                        tooltip_text: Some("Import the puzzles of a PGN file downloaded from a URL"),
+                        clicked => ImportPgnFromUrl,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("folder-open"),
+                        label: Some("Import folder"),
+                        tooltip_text: Some("Import every PGN and BPGN file of a folder and its subfolders, compressed or not"),
+                        clicked => ImportFolder,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("document-open-remote"),
+                        label: Some("Import a Lichess study"),
+                        tooltip_text: Some("Import the crazyhouse positions of a Lichess study as puzzles"),
+                        clicked => ImportLichessStudy,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("document-save"),
+                        label: Some("Save puzzle set"),
+                        tooltip_text: Some("Save the puzzles in a .buzzle file, in a .bpgn file to share them with other bughouse tools, in a .epd file for chess tools, or in a .tex file to print them"),
+                        clicked => SavePuzzles,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("package-x-generic"),
+                        label: Some("Install pack"),
+                        tooltip_text: Some("Install a .buzzlepack puzzle pack and add its puzzles, or update it to a newer version"),
+                        clicked => InstallPack,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("package-x-generic"),
+                        label: Some("Export pack"),
+                        tooltip_text: Some("Share the puzzles as a .buzzlepack with their author, license and version"),
+                        clicked => ExportPack,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("x-office-presentation"),
+                        label: Some("Export Anki deck"),
+                        tooltip_text: Some("Write the puzzles as Anki flashcards, with the position on the front and the solution on the back"),
+                        clicked => ExportAnki,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("image-x-generic"),
+                        label: Some("Export image"),
+                        tooltip_text: Some("Save the position with the pockets and arrows as a PNG image, or as SVG with the .svg extension"),
+                        clicked => ExportImage,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("edit-copy"),
+                        label: Some("Copy position as BFEN"),
+                        tooltip_text: Some("Copy the position of both boards to the clipboard"),
+                        clicked => CopyBfen,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("edit-paste"),
+                        label: Some("Paste BFEN"),
+                        tooltip_text: Some("Play the position of the BFEN or FEN of the clipboard against the engine"),
+                        clicked => PasteBfen,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("system-run"),
+                        label: Some("Generate puzzles from games"),
+                        clicked => GeneratePuzzles,
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("list-add"),
+                        label: Some("Append imports"),
+                        tooltip_text: Some("Add the imported puzzles to the current ones instead of replacing them, skipping the duplicates"),
+                        toggled(button) => SetAppendImports(button.get_active()),
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("network-workgroup"),
+                        label: Some("Download games"),
+                        tooltip_text: Some("Download games from bughousedb.com to generate puzzles from"),
+                        clicked => DownloadGames,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("network-workgroup"),
+                        label: Some("Download chess.com games"),
+                        tooltip_text: Some("Download bughouse games played live on chess.com, with their partner games, by game id"),
+                        clicked => DownloadChessComGames,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("avatar-default"),
+                        label: Some("Download a chess.com player's games"),
+                        tooltip_text: Some("Download the bughouse games of a chess.com player from their monthly archives"),
+                        clicked => DownloadChessComArchives,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some(if self.model.generation_paused { "media-playback-start" } else { "media-playback-pause" }),
+                        label: Some(if self.model.generation_paused { "Resume generation" } else { "Pause generation" }),
+                        sensitive: !self.model.queue.is_empty(),
+                        clicked => ToggleGenerationPause,
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("applications-science"),
+                        label: Some("Analyze"),
+                        tooltip_text: Some("Move the pieces of both sides freely, Undo taking back the moves, and come back to the puzzle when done"),
+                        active: self.model.free_analysis.is_some(),
+                        toggled(button) => ToggleAnalysis(button.get_active()),
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("utilities-system-monitor"),
+                        label: Some("Evaluation"),
+                        toggled(button) => ToggleEvaluation(button.get_active()),
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("view-dual"),
+                        label: Some("Partner's board"),
+                        tooltip_text: Some("Show the board of the partner, who plays the other color"),
+                        active: true,
+                        toggled(button) => TogglePartnerBoard(button.get_active()),
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("view-restore"),
+                        label: Some("Picture in picture"),
+                        tooltip_text: Some("Show the partner's board over a corner of your board, for small screens"),
+                        active: self.model.settings.partner_board.picture_in_picture,
+                        toggled(button) => SetPictureInPicture(button.get_active()),
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("go-next"),
+                        label: Some("Switch board"),
+                        tooltip_text: Some("Play the moves on the other board (F6)"),
+                        clicked => SwitchBoard,
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("system-users"),
+                        label: Some("Engine plays the replies"),
+                        tooltip_text: Some("The opponent's replies are chosen by the engine: keep finding winning moves until the goal is reached"),
+                        toggled(button) => SetEngineReplies(button.get_active()),
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("dialog-warning"),
+                        label: Some("Opponent has all pieces in hand"),
+                        tooltip_text: Some("Only accept solutions that work even if the opponent has a full set of pieces to drop"),
+                        toggled(button) => SetFullOpponentPocket(button.get_active()),
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("edit-find"),
+                        label: Some("Known tactics only"),
+                        tooltip_text: Some("Generate puzzles only from known tactics (checked fork, trapped rook, smothered and battery drop mates), which is much faster than the engine search"),
+                        toggled(button) => SetPatternsOnly(button.get_active()),
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("go-bottom"),
+                        label: Some("Drops only"),
+                        tooltip_text: Some("Only serve the puzzles whose key move is a drop, and show your pocket in large"),
+                        toggled(button) => SetDropsOnly(button.get_active()),
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("view-conceal"),
+                        label: Some("Blindfold"),
+                        tooltip_text: Some("Hide your board, showing only the last move and the pockets, and write your moves in SAN"),
+                        toggled(button) => SetBlindfold(button.get_active()),
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("view-sort-ascending"),
+                        label: Some("Sort by difficulty"),
+                        clicked => SortByDifficulty,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("preferences-system"),
+                        label: Some("Settings"),
+                        clicked => OpenSettings,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("media-playback-pause"),
+                        label: Some("Sit"),
+                        clicked => Sit,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("appointment-soon"),
+                        label: Some("Puzzle rush"),
+                        tooltip_text: Some("Solve as many puzzles as possible in a few minutes, until the third wrong answer"),
+                        clicked => StartRush,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("go-up"),
+                        label: Some("Streak"),
+                        tooltip_text: Some(self.model.streak_tooltip.as_str()),
+                        clicked => StartStreak,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("x-office-calendar"),
+                        label: Some("Puzzle of the day"),
+                        tooltip_text: Some(self.model.daily_tooltip.as_str()),
+                        clicked => DailyPuzzle,
+                    },
+                    gtk::ToggleToolButton {
+                        icon_name: Some("emblem-important"),
+                        label: Some("Review"),
+                        tooltip_text: Some(self.model.review_tooltip.as_str()),
+                        active: self.model.reviewing,
+                        toggled(button) => SetReviewing(button.get_active()),
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("x-office-address-book"),
+                        label: Some("Course"),
+                        tooltip_text: Some("Train chapter by chapter, each one being unlocked by solving enough puzzles of the previous one"),
+                        clicked => OpenCourse,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("bookmark-new"),
+                        label: Some("Set chapter"),
+                        tooltip_text: Some("Put the puzzle in a chapter of the course"),
+                        clicked => SetChapter,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("edit-undo"),
+                        label: Some("Undo"),
+                        tooltip_text: Some("Take back your last move and the reply"),
+                        clicked => Undo,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("view-refresh"),
+                        label: Some("Retry"),
+                        tooltip_text: Some("Start the puzzle again"),
+                        clicked => Retry,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("dialog-question"),
+                        label: Some("Hint"),
+                        tooltip_text: Some("Show the piece to move, then the move"),
+                        clicked => Hint,
+                    },
+                    gtk::ToolButton {
+                        icon_name: Some("media-playback-start"),
+                        label: Some("Show solution"),
+                        tooltip_text: Some("Play the rest of the solution on the board, counting the puzzle as failed"),
+                        clicked => ShowSolution,
+                    },
                     gtk::ToolButton {
                         icon_name: Some("application-exit"),
                         label: Some("Quit"),
                         clicked => Quit,
                     },
                 },
-                #[name="ground"]
-                Ground {
-                    UserMove(orig, dest, promotion) => MovePlayed(orig, dest, promotion),
-                    UserDrop(piece, to) => PieceDrop(piece, to),
+                #[name="boards_box"]
+                gtk::Box {
+                    orientation: Horizontal,
+                    // The player's share of the evaluation is at the bottom, like the player's pieces.
+                    gtk::ProgressBar {
+                        orientation: Vertical,
+                        inverted: true,
+                        fraction: self.model.evaluation_fraction,
+                        show_text: true,
+                        text: Some(self.model.evaluation_text.as_str()),
+                        visible: self.model.show_evaluation,
+                    },
+                    // The partner's board is moved over a corner in picture-in-picture.
+                    #[name="board_overlay"]
+                    gtk::Overlay {
+                        // The board receiving the moves is highlighted.
+                        gtk::Frame {
+                            label: Some(if self.model.partner_focused { "Your board" } else { "▶ Your board" }),
+                            visible: !self.model.blindfold,
+                            shadow_type: if self.model.partner_focused { ShadowType::None } else { ShadowType::EtchedIn },
+                            tooltip_text: Some("Press F6 to play on the other board"),
+                            #[name="ground"]
+                            Ground {
+                                UserMove(orig, dest, promotion) => MovePlayed(orig, dest, promotion),
+                                UserDrop(piece, to) => PieceDrop(piece, to),
+                            },
+                        },
+                    },
+                    #[name="partner_column"]
+                    gtk::Box {
+                        orientation: Vertical,
+                        gtk::Frame {
+                            label: Some(if self.model.partner_focused { "▶ Partner's board" } else { "Partner's board" }),
+                            shadow_type: if self.model.partner_focused { ShadowType::EtchedIn } else { ShadowType::None },
+                            tooltip_text: Some("Press F6 to play on the other board"),
+                            #[name="partner_ground"]
+                            Ground {
+                                UserMove(orig, dest, promotion) => PartnerMovePlayed(orig, dest, promotion),
+                                UserDrop(piece, to) => PartnerPieceDrop(piece, to),
+                            },
+                        },
+                        #[name="timeline"]
+                        gtk::DrawingArea {
+                            tooltip_text: Some("Pieces arriving in the pockets during the puzzle: your pocket below the line, your opponent's above"),
+                        },
+                        gtk::Label {
+                            text: &self.model.partner_clock,
+                            tooltip_text: Some("Time left to your partner against their opponent"),
+                            visible: !self.model.partner_clock.is_empty(),
+                        },
+                    },
+                },
+                gtk::Label {
+                    line_wrap: true,
+                    text: &self.model.blindfold_status,
+                    visible: self.model.blindfold,
+                },
+                gtk::Label {
+                    markup: &self.model.pocket_status,
+                    visible: self.model.drops_only || self.model.blindfold,
+                },
+                #[name="move_entry"]
+                gtk::Entry {
+                    placeholder_text: Some("Type your move in SAN, e.g. Nxf7+ or N@f3, and press Enter"),
+                    tooltip_text: Some("Moves are played on the board receiving the moves, the partner's one after F6. Outside of this box, type a drop as the piece, the file and the rank, e.g. P e 6"),
+                    activate(entry) => EnterMove(entry.get_text().map(|text| text.to_string()).unwrap_or_default()),
+                },
+                gtk::Label {
+                    text: &self.model.transfer_status,
+                },
+                gtk::Label {
+                    line_wrap: true,
+                    text: &self.model.partner_messages,
+                    visible: !self.model.partner_messages.is_empty(),
                 },
                 gtk::ButtonBox {
                     gtk::Button {
                         label: "Précédent",
+                        sensitive: self.model.rush.is_none(),
                         clicked => PreviousPuzzle,
                     },
                     gtk::Button {
                         label: "Suivant",
+                        sensitive: self.model.rush.is_none(),
                         clicked => NextPuzzle,
                     },
+                    gtk::Button {
+                        label: "Play your partner's mate",
+                        tooltip_text: Some("Play the mate enabled by your capture on the partner's board, against the scripted defense"),
+                        visible: self.model.can_play_partner_mate,
+                        clicked => PlayPartnerMate,
+                    },
+                    gtk::Button {
+                        label: "Open source game",
+                        tooltip_text: Some("Open the game the puzzle comes from in the browser"),
+                        sensitive: self.model.source_url.is_some(),
+                        clicked => OpenSource,
+                    },
+                },
+                gtk::Label {
+                    selectable: true,
+                    text: &self.model.puzzle_info,
+                },
+                gtk::Label {
+                    line_wrap: true,
+                    selectable: true,
+                    text: &self.model.move_list,
+                },
+                gtk::Label {
+                    text: &self.model.generation_status,
+                },
+                gtk::Box {
+                    orientation: Horizontal,
+                    spacing: 6,
+                    visible: self.model.import.is_some(),
+                    gtk::ProgressBar {
+                        fraction: self.model.import_fraction,
+                        show_text: true,
+                        text: Some(self.model.import_status.as_str()),
+                        child: {
+                            expand: true,
+                        },
+                    },
+                    gtk::Button {
+                        label: "Cancel",
+                        tooltip_text: Some("Stop reading the games, keeping the puzzles already found"),
+                        clicked => CancelImport,
+                    },
+                },
+                gtk::Box {
+                    orientation: Horizontal,
+                    spacing: 6,
+                    visible: self.model.can_request,
+                    gtk::Label {
+                        text: "Ask your partner for:",
+                    },
+                    gtk::Button {
+                        label: "Pawn",
+                        clicked => RequestPiece(Role::Pawn),
+                    },
+                    gtk::Button {
+                        label: "Knight",
+                        clicked => RequestPiece(Role::Knight),
+                    },
+                    gtk::Button {
+                        label: "Bishop",
+                        clicked => RequestPiece(Role::Bishop),
+                    },
+                    gtk::Button {
+                        label: "Rook",
+                        clicked => RequestPiece(Role::Rook),
+                    },
+                    gtk::Button {
+                        label: "Queen",
+                        clicked => RequestPiece(Role::Queen),
+                    },
+                },
+                gtk::Label {
+                    text: &self.model.goal_status,
+                },
+                gtk::Label {
+                    text: &self.model.rush_status,
+                    visible: !self.model.rush_status.is_empty(),
+                },
+                gtk::Label {
+                    text: &self.model.clock_status,
+                    visible: !self.model.clock_status.is_empty(),
                 },
                 #[name="label"]
                 gtk::Label {
                     text: &self.model.text,
                 },
+                gtk::Box {
+                    orientation: Horizontal,
+                    spacing: 6,
+                    gtk::Button {
+                        label: "Show refutation",
+                        sensitive: self.model.wrong_move.is_some(),
+                        clicked => ShowRefutation,
+                    },
+                    gtk::Button {
+                        label: "Prove me wrong",
+                        tooltip_text: Some("Play your move out against the engine"),
+                        sensitive: self.model.wrong_move.is_some(),
+                        clicked => ProveMeWrong,
+                    },
+                    gtk::Button {
+                        label: "Continue my line",
+                        tooltip_text: Some("Play on your slower mate against the engine"),
+                        visible: self.model.slower_mate.is_some() && self.model.can_play && self.model.rush.is_none(),
+                        clicked => ContinueLine,
+                    },
+                    gtk::Button {
+                        label: "Keep playing",
+                        tooltip_text: Some("Play on against the engine from the solved position"),
+                        visible: self.model.solved && self.model.demonstration.is_none(),
+                        clicked => KeepPlaying,
+                    },
+                    gtk::Button {
+                        label: "Back to puzzle",
+                        visible: self.model.demonstration.is_some(),
+                        clicked => BackToPuzzle,
+                    },
+                    gtk::Label {
+                        selectable: true,
+                        text: &self.model.refutation,
+                    },
+                },
             },
             delete_event(_, _) => (Quit, Inhibit(false)),
+            key_press_event(_, key) => (KeyPress(key.clone()), Inhibit(false)),
         }
     }
 }
 
-struct Puzzle {
-    moves: Vec<Move>,
-    position: Bughouse,
+/// Add a row with a text entry to a dialog grid.
+fn add_entry(grid: &Grid, row: i32, label: &str) -> Entry {
+    let label = Label::new(Some(label));
+    label.set_xalign(0.0);
+    let entry = Entry::new();
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(&entry, 1, row, 1, 1);
+    entry
 }
 
-struct FENImporter {
-    current_position: Bughouse,
-    puzzles: Vec<Puzzle>,
+/// Add a row to the settings grid, where 0 means no value.
+fn add_setting(grid: &Grid, row: i32, label: &str, value: Option<u64>, max: f64, step: f64) -> SpinButton {
+    let label = Label::new(Some(label));
+    label.set_xalign(0.0);
+    let spin_button = SpinButton::new_with_range(0.0, max, step);
+    spin_button.set_value(value.unwrap_or(0) as f64);
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(&spin_button, 1, row, 1, 1);
+    spin_button
 }
 
-impl FENImporter {
-    fn new() -> Self {
-        Self {
-            current_position: Bughouse::default(),
-            puzzles: vec![],
-        }
+/// Add the choice of the corner of the board of the user where the partner's board is in
+/// picture-in-picture.
+fn add_corner_setting(grid: &Grid, row: i32, selected: Corner) -> ComboBoxText {
+    let label = Label::new(Some("Corner of the partner's board in picture-in-picture"));
+    label.set_xalign(0.0);
+    let combo_box = ComboBoxText::new();
+    for &(corner, name) in &[(Corner::TopLeft, "Top left"), (Corner::TopRight, "Top right"),
+        (Corner::BottomLeft, "Bottom left"), (Corner::BottomRight, "Bottom right")]
+    {
+        combo_box.append(Some(&format!("{:?}", corner)), name);
     }
+    combo_box.set_active_id(Some(&format!("{:?}", selected)));
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(&combo_box, 1, row, 1, 1);
+    combo_box
 }
 
-impl Visitor for FENImporter {
-    type Result = ();
+/// Add the choice of the board of the BPGN games the puzzles are played from.
+fn add_board_setting(grid: &Grid, row: i32, selected: Board) -> ComboBoxText {
+    let label = Label::new(Some("Play the BPGN puzzles from"));
+    label.set_xalign(0.0);
+    let combo_box = ComboBoxText::new();
+    combo_box.append(Some("A"), "Board A");
+    combo_box.append(Some("B"), "Board B");
+    combo_box.set_active_id(Some(&format!("{:?}", selected)));
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(&combo_box, 1, row, 1, 1);
+    combo_box
+}
 
-    fn begin_game(&mut self) {
+/// Add the engine choice to the settings grid: the engine of the environment, with the id
+/// "default", or one of the returned configurations, with its index as id.
+fn add_engine_setting(grid: &Grid, row: i32, engines: &[DiscoveredEngine], selected: Option<&EngineConfig>)
+    -> (ComboBoxText, Vec<EngineConfig>)
+{
+    let label = Label::new(Some("Engine"));
+    label.set_xalign(0.0);
+    let combo_box = ComboBoxText::new();
+    combo_box.append(Some("default"), &format!("Default ({})", engine::config_from_env()));
+    let mut configs = vec![];
+    for discovered in engines {
+        combo_box.append(Some(&configs.len().to_string()), &discovered.label());
+        configs.push(discovered.config.clone());
     }
+    // The saved engine may not have been discovered, e.g. when it is not installed anymore.
+    let active_id =
+        match selected {
+            Some(config) => {
+                let index =
+                    match configs.iter().position(|engine_config| engine_config == config) {
+                        Some(index) => index,
+                        None => {
+                            combo_box.append(Some(&configs.len().to_string()), &config.to_string());
+                            configs.push(config.clone());
+                            configs.len() - 1
+                        },
+                    };
+                index.to_string()
+            },
+            None => "default".to_string(),
+        };
+    combo_box.set_active_id(Some(&active_id));
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(&combo_box, 1, row, 1, 1);
+    (combo_box, configs)
+}
 
-    fn end_game(&mut self) -> Self::Result {
+/// Tell where the piece captured by `mov`, if any, goes, from the point of view of the `player`.
+fn transfer_message(position: &Bughouse, mov: &Move, player: Color, on_partner_board: bool) -> Option<String> {
+    let role = partner::captured_role(position, mov)?;
+    let name =
+        match role {
+            Role::Pawn => "pawn",
+            Role::Knight => "knight",
+            Role::Bishop => "bishop",
+            Role::Rook => "rook",
+            Role::Queen => "queen",
+            Role::King => "king",
+        };
+    // The partner plays the other color.
+    let message =
+        match (on_partner_board, position.turn() == player) {
+            (false, true) => format!("You captured a {}: it goes to your partner's pocket", name),
+            (false, false) => format!("Your opponent captured a {}: it goes to your partner's opponent", name),
+            (true, false) => format!("Your partner captured a {}: it goes to your pocket", name),
+            (true, true) => format!("Your partner's opponent captured a {}: it goes to your opponent's pocket", name),
+        };
+    Some(message)
+}
+
+/// Pieces in the pocket of the `player`, as large symbols, e.g. "Your pocket: ♞ ♟ ♟".
+fn pocket_markup(position: &Bughouse, player: Color) -> String {
+    let pieces: Vec<&str> =
+        match position.pockets() {
+            Some(pockets) => POCKET_ROLES.iter()
+                .flat_map(|&role| (0..pockets.by_color(player).by_role(role)).map(move |_| image::symbol(role)))
+                .collect(),
+            None => vec![],
+        };
+    if pieces.is_empty() {
+        "Your pocket is empty".to_string()
     }
+    else {
+        format!("Your pocket: <span size=\"xx-large\">{}</span>", pieces.join(" "))
+    }
+}
 
-    fn header(&mut self, key: &[u8], value: RawHeader) {
-        if key == b"FEN" {
-            let fen = value.as_bytes();
-            match fen.iter().position(|&byte| byte == b'|') {
-                Some(index) => {
-                    let player = &fen[..index - 1];
-                    let partner = &fen[index + 1..];
-                    match Fen::from_ascii(player) {
-                        Ok(fen) => {
-                            match Bughouse::from_setup(&fen) {
-                                Ok(setup) => {
-                                    self.current_position = setup.clone();
-                                    self.puzzles.push(Puzzle {
-                                        moves: vec![],
-                                        position: setup,
-                                    });
-                                },
-                                Err(error) => {
-                                    eprintln!("Error setup position: {}", error);
-                                },
-                            }
-                        },
-                        Err(error) => {
-                            eprintln!("Error parsing FEN: {}", error);
-                        },
+/// Partial credit of an attempt, e.g. "Score: 2/3 moves (66%)".
+fn score_text(found: usize, total: usize) -> String {
+    format!("Score: {}/{} moves ({}%)", found, total, found * 100 / total)
+}
+
+/// Pieces on the board, by color, e.g. "White: Kg1 Qd1 Nf3 f2 g2 h2 — Black: Kg8 Rf8 g7 h7".
+fn piece_list(position: &Bughouse) -> String {
+    let roles = [Role::King, Role::Queen, Role::Rook, Role::Bishop, Role::Knight, Role::Pawn];
+    let sides: Vec<String> = [Color::White, Color::Black].iter()
+        .map(|&color| {
+            let pieces: Vec<String> = roles.iter()
+                .flat_map(|&role| position.board().by_piece(role.of(color)).into_iter().map(move |square| {
+                    if role == Role::Pawn {
+                        square.to_string()
                     }
-                },
-                None => {
-                    eprintln!("Cannot find | in FEN.");
-                },
-            }
-        }
+                    else {
+                        format!("{}{}", role.upper_char(), square)
+                    }
+                }))
+                .collect();
+            format!("{}: {}", if color == Color::White { "White" } else { "Black" }, pieces.join(" "))
+        })
+        .collect();
+    sides.join(" — ")
+}
+
+/// Hint of the piece to drop, which is in the pocket.
+fn drop_hint(role: Role) -> &'static str {
+    match role {
+        Role::Pawn => "Hint: drop a pawn",
+        Role::Knight => "Hint: drop a knight",
+        Role::Bishop => "Hint: drop a bishop",
+        Role::Rook => "Hint: drop a rook",
+        Role::Queen => "Hint: drop a queen",
+        Role::King => "Hint: drop a king",
     }
+}
 
-    fn san(&mut self, san_plus: SanPlus) {
-        if let Some(puzzle) = self.puzzles.last_mut() {
-            match san_plus.san.to_move(&self.current_position) {
-                Ok(mov) => {
-                    self.current_position.play_unchecked(&mov);
-                    puzzle.moves.push(mov);
-                },
-                Err(error) => eprintln!("Error playing move: {:?}", error),
-            }
-        }
+fn setting_value(spin_button: &SpinButton) -> Option<u64> {
+    let value = spin_button.get_value() as u64;
+    if value == 0 {
+        None
+    }
+    else {
+        Some(value)
     }
 }
 
+fn local_generator(relm: &Relm<Win>, cache: &SharedCache, id: usize, settings: &Settings) -> Generator {
+    let stream = relm.stream().clone();
+    let worker = Worker::new(cache.clone(), move |generation, response| stream.emit(GeneratorResponse(id, generation, response)));
+    worker.configure(settings);
+    Generator {
+        game: None,
+        id,
+        remote: false,
+        worker,
+    }
+}
+
+fn remote_listener(relm: &Relm<Win>, port: u16) -> Option<Channel<TcpStream>> {
+    let stream = relm.stream().clone();
+    match remote::listen(port, move |tcp_stream| stream.emit(RemoteWorkerConnected(tcp_stream))) {
+        Ok(listener) => Some(listener),
+        Err(error) => {
+            eprintln!("Cannot listen for remote workers on port {}: {}", port, error);
+            None
+        },
+    }
+}
+
+fn generation_status(queue: &Queue, paused: bool) -> String {
+    if queue.is_empty() {
+        return String::new();
+    }
+
+    let state =
+        if paused {
+            "Generation paused"
+        }
+        else {
+            "Generating puzzles…"
+        };
+    format!("{}: {} puzzles found in {} games", state, queue.puzzles.len(), queue.analyzed_games)
+}
+
 fn main() {
-    Win::run(()).expect("window run");
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("worker") {
+        let address =
+            match args.get(2) {
+                Some(address) => address,
+                None => {
+                    eprintln!("Usage: {} worker HOST:PORT", args[0]);
+                    process::exit(1);
+                },
+            };
+        if let Err(error) = remote::run_worker(address) {
+            eprintln!("Remote worker error: {}", error);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let files = args.iter().skip(1).map(PathBuf::from).collect();
+    Win::run(files).expect("window run");
 }
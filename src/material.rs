@@ -0,0 +1,88 @@
+use shakmaty::{
+    Color,
+    fen::{self, Fen},
+    FromSetup,
+    Material,
+    MaterialSide,
+    position::Bughouse,
+    Role,
+    Setup,
+};
+
+pub const POCKET_ROLES: [Role; 5] = [Role::Pawn, Role::Knight, Role::Bishop, Role::Rook, Role::Queen];
+
+/// Material of `color` minus the material of its opponent, in pawns, counting the pieces in
+/// the pockets.
+pub fn material_balance(position: &Bughouse, color: Color) -> i32 {
+    let mut balance = 0;
+    for (_, piece) in position.board().pieces() {
+        let value = role_value(piece.role);
+        if piece.color == color {
+            balance += value;
+        }
+        else {
+            balance -= value;
+        }
+    }
+    if let Some(pockets) = position.pockets() {
+        for &role in &POCKET_ROLES {
+            let value = role_value(role);
+            balance += i32::from(pockets.by_color(color).by_role(role)) * value;
+            balance -= i32::from(pockets.by_color(!color).by_role(role)) * value;
+        }
+    }
+    balance
+}
+
+pub fn role_value(role: Role) -> i32 {
+    match role {
+        Role::Pawn => 1,
+        Role::Knight | Role::Bishop => 3,
+        Role::Rook => 5,
+        Role::Queen => 9,
+        Role::King => 0,
+    }
+}
+
+/// Create a copy of `position` with a different setup.
+pub fn modify_setup<F>(position: &Bughouse, modify: F) -> Result<Bughouse, String>
+where F: FnOnce(&mut Fen)
+{
+    let mut setup: Fen = fen::fen(position).parse()
+        .map_err(|error| format!("Error parsing FEN: {}", error))?;
+    modify(&mut setup);
+    Bughouse::from_setup(&setup).map_err(|error| format!("Error setup position: {}", error))
+}
+
+/// Create a copy of `position` with different pockets.
+pub fn modify_pockets<F>(position: &Bughouse, modify: F) -> Result<Bughouse, String>
+where F: FnOnce(&mut Material)
+{
+    modify_setup(position, |setup| modify(setup.pockets.get_or_insert_with(Material::new)))
+}
+
+/// Create a copy of `position` where the side to move sits, i.e. passes its turn.
+pub fn pass_turn(position: &Bughouse) -> Result<Bughouse, String> {
+    modify_setup(position, |setup| {
+        setup.turn = !setup.turn;
+        setup.ep_square = None;
+    })
+}
+
+pub fn pocket_mut(pockets: &mut Material, color: Color) -> &mut MaterialSide {
+    match color {
+        Color::White => &mut pockets.white,
+        Color::Black => &mut pockets.black,
+    }
+}
+
+pub fn add_to_pocket(side: &mut MaterialSide, role: Role) {
+    match role {
+        Role::Pawn => side.pawns += 1,
+        Role::Knight => side.knights += 1,
+        Role::Bishop => side.bishops += 1,
+        Role::Rook => side.rooks += 1,
+        Role::Queen => side.queens += 1,
+        Role::King => side.kings += 1,
+    }
+}
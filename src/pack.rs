@@ -0,0 +1,128 @@
+/*
+ * Puzzle packs, to distribute puzzle sets: a zip archive with the extension ".buzzlepack"
+ * containing the set in the native format, a manifest giving its name, author, license and
+ * version, and optionally images previewing its first puzzles.
+ *
+ * The installed packs are copied in the user data directory, and their manifests saved in
+ * packs.json there, so that installing a newer version of a pack replaces the older one.
+ */
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use shakmaty::Setup;
+use zip::{ZipArchive, ZipWriter};
+use zip::write::FileOptions;
+
+use crate::data;
+use crate::image;
+use crate::puzzle::Puzzle;
+use crate::puzzle_set;
+
+pub const EXTENSION: &str = "buzzlepack";
+const MANIFEST: &str = "manifest.json";
+const PREVIEW_COUNT: usize = 3;
+const PUZZLES: &str = "puzzles.buzzle";
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Manifest {
+    pub author: String,
+    pub license: String,
+    /// Identifies the pack.
+    pub name: String,
+    /// Increased by the author at each release of the pack.
+    pub version: u32,
+}
+
+pub enum Installation {
+    /// Same or newer version already installed.
+    AlreadyInstalled(u32),
+    New,
+    /// Replaced this older version.
+    Updated(u32),
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct InstalledPacks {
+    packs: Vec<Manifest>,
+}
+
+impl InstalledPacks {
+    fn load() -> Self {
+        data::load_json("packs.json", "installed packs")
+    }
+
+    fn save(&self) -> Result<(), String> {
+        data::save_json("packs.json", self).map_err(|error| format!("Cannot save the installed packs: {}", error))
+    }
+}
+
+pub fn is_pack(path: &Path) -> bool {
+    path.extension().map(|extension| extension == EXTENSION).unwrap_or(false)
+}
+
+/// Write the `puzzles` in a pack, with the images of the first ones.
+pub fn export(path: &Path, manifest: &Manifest, puzzles: &[Puzzle]) -> Result<(), String> {
+    let write_error = |error: &dyn ToString| format!("Cannot write {}: {}", path.display(), error.to_string());
+    let file = File::create(path).map_err(|error| format!("Cannot create {}: {}", path.display(), error))?;
+    let mut zip = ZipWriter::new(file);
+    let manifest = serde_json::to_string_pretty(manifest).map_err(|error| error.to_string())?;
+    let mut entries = vec![
+        (MANIFEST.to_string(), manifest.into_bytes()),
+        (PUZZLES.to_string(), puzzle_set::to_json(puzzles)?.into_bytes()),
+    ];
+    for (index, puzzle) in puzzles.iter().take(PREVIEW_COUNT).enumerate() {
+        let shapes = puzzle.annotation(0).map(|annotation| annotation.shapes.as_slice()).unwrap_or(&[]);
+        entries.push((format!("previews/{}.png", index + 1), image::png(&puzzle.position, shapes, puzzle.position.turn())?));
+    }
+    for (name, content) in entries {
+        zip.start_file(name, FileOptions::default()).map_err(|error| write_error(&error))?;
+        zip.write_all(&content).map_err(|error| write_error(&error))?;
+    }
+    zip.finish().map_err(|error| write_error(&error))?;
+    Ok(())
+}
+
+/// Install the pack of `path` in the library unless the same or a newer version is already
+/// installed, giving its puzzles.
+pub fn install(path: &Path) -> Result<(Manifest, Installation, Vec<Puzzle>), String> {
+    let file = File::open(path).map_err(|error| format!("Cannot open {}: {}", path.display(), error))?;
+    let mut archive = ZipArchive::new(file).map_err(|error| format!("Cannot open the pack {}: {}", path.display(), error))?;
+    let manifest: Manifest = {
+        let entry = archive.by_name(MANIFEST).map_err(|_| format!("No manifest in the pack {}", path.display()))?;
+        serde_json::from_reader(entry).map_err(|error| format!("Cannot read the manifest of {}: {}", path.display(), error))?
+    };
+    let mut json = vec![];
+    archive.by_name(PUZZLES).map_err(|_| format!("No puzzles in the pack {}", path.display()))?
+        .read_to_end(&mut json)
+        .map_err(|error| format!("Cannot read the puzzles of {}: {}", path.display(), error))?;
+    let puzzles = puzzle_set::read(json.as_slice())?;
+
+    let mut installed = InstalledPacks::load();
+    let installation =
+        match installed.packs.iter().position(|pack| pack.name == manifest.name) {
+            Some(index) if installed.packs[index].version >= manifest.version =>
+                return Ok((manifest, Installation::AlreadyInstalled(installed.packs[index].version), puzzles)),
+            Some(index) => Installation::Updated(installed.packs.remove(index).version),
+            None => Installation::New,
+        };
+    let library = library_path(&manifest.name).ok_or("No data directory")?;
+    if let Some(dir) = library.parent() {
+        fs::create_dir_all(dir).map_err(|error| format!("Cannot create {}: {}", dir.display(), error))?;
+    }
+    fs::write(&library, json).map_err(|error| format!("Cannot save {}: {}", library.display(), error))?;
+    installed.packs.push(manifest.clone());
+    installed.save()?;
+    Ok((manifest, installation, puzzles))
+}
+
+/// Puzzle set of an installed pack.
+fn library_path(name: &str) -> Option<PathBuf> {
+    let file_name: String = name.chars()
+        .map(|character| if character.is_alphanumeric() || character == '-' { character } else { '_' })
+        .collect();
+    data::path("packs").map(|dir| dir.join(format!("{}.{}", file_name, puzzle_set::EXTENSION)))
+}
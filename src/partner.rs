@@ -0,0 +1,186 @@
+/*
+ * Analysis of the partner's board: in bughouse, the pieces captured on one board are given to the
+ * partner, who can use them to checkmate on the other board.
+ *
+ * Likewise, the pieces lost by the player are given to the partner's opponent, so a solution
+ * losing a piece that allows a mate on the partner board is not a solution.
+ */
+
+use std::io;
+
+use shakmaty::{
+    Color,
+    Move,
+    Position,
+    position::Bughouse,
+    Role,
+    Setup,
+};
+
+use crate::engine::{self, Engine, Score, SearchLimits};
+use crate::material::{add_to_pocket, modify_pockets, pocket_mut};
+
+/// Longest partner's mate kept as the script of a cross-board puzzle.
+const MAX_MATE_PLIES: usize = 15;
+
+/// Positions of the two boards of the game: the board of the player and the partner's board,
+/// when known. The pieces captured on a board go to the pocket of the partner.
+#[derive(Clone, Default)]
+pub struct Boards {
+    pub partner: Option<Bughouse>,
+    pub position: Bughouse,
+}
+
+impl Boards {
+    pub fn new(position: Bughouse, partner: Option<Bughouse>) -> Self {
+        Self {
+            partner,
+            position,
+        }
+    }
+
+    /// Play `mov` on the board of the player, giving its capture to the partner.
+    pub fn play(&mut self, mov: &Move) -> Result<(), String> {
+        if let Some(ref mut partner) = self.partner {
+            if let Some(received) = pass_capture(&self.position, mov, partner)? {
+                *partner = received;
+            }
+        }
+        self.position.play_unchecked(mov);
+        Ok(())
+    }
+
+    /// Play `mov` on the partner's board, giving its capture to the player.
+    pub fn play_partner(&mut self, mov: &Move) -> Result<(), String> {
+        let partner = self.partner.as_mut().ok_or("Partner's moves without the partner's board")?;
+        if let Some(received) = pass_capture(partner, mov, &self.position)? {
+            self.position = received;
+        }
+        partner.play_unchecked(mov);
+        Ok(())
+    }
+
+    /// Check if the partner checkmates by playing `moves` on their board, with the replies of
+    /// their opponent. Every move must be legal.
+    pub fn partner_mates(&self, moves: &[Move]) -> bool {
+        let mut boards = self.clone();
+        for mov in moves {
+            let legal = boards.partner.as_ref().map(|partner| partner.is_legal(mov)).unwrap_or(false);
+            if !legal || boards.play_partner(mov).is_err() {
+                return false;
+            }
+        }
+        boards.partner.map(|partner| partner.is_checkmate()).unwrap_or(false)
+    }
+}
+
+/// Check if `color` has a forced mate on the partner board, after receiving `roles`.
+pub fn can_mate(engine: &mut dyn Engine, partner: &Bughouse, color: Color, roles: &[Role], limits: &SearchLimits)
+    -> io::Result<bool>
+{
+    let position = give_pieces(partner, color, roles)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    engine.set_position(&position)?;
+    engine.search(limits)?;
+    let mate =
+        match engine.score() {
+            Some(Score::Mate(moves)) => {
+                if position.turn() == color {
+                    moves > 0
+                }
+                else {
+                    moves < 0
+                }
+            },
+            _ => false,
+        };
+    Ok(mate)
+}
+
+/// Forced mate of `color` on the partner board after receiving `roles`, with the best replies of
+/// the opponent, as found by the engine. Empty when there is none within `MAX_MATE_PLIES`.
+pub fn mate_line(engine: &mut dyn Engine, partner: &Bughouse, color: Color, roles: &[Role], limits: &SearchLimits)
+    -> io::Result<Vec<Move>>
+{
+    let mut position = give_pieces(partner, color, roles)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    let mut moves = vec![];
+    while moves.len() < MAX_MATE_PLIES {
+        if position.is_checkmate() {
+            return Ok(moves);
+        }
+        if position.is_game_over() {
+            break;
+        }
+        let mov = engine::best_move(engine, &position, limits)?;
+        position.play_unchecked(&mov);
+        moves.push(mov);
+    }
+    Ok(vec![])
+}
+
+/// Check if the opponent's captures in `moves` give the partner's opponent, who plays the same
+/// color as the player, the pieces to force mate.
+pub fn is_fatal_to_partner(engine: &mut dyn Engine, position: &Bughouse, moves: &[Move], partner: &Bughouse,
+    limits: &SearchLimits) -> io::Result<bool>
+{
+    let player = position.turn();
+    let mut position = position.clone();
+    let mut given_roles = vec![];
+    for mov in moves {
+        if position.turn() != player {
+            if let Some(role) = mov.capture() {
+                given_roles.push(role);
+                if can_mate(engine, partner, player, &given_roles, limits)? {
+                    return Ok(true);
+                }
+            }
+        }
+        position.play_unchecked(mov);
+    }
+    Ok(false)
+}
+
+/// Check if the opponent's best reply to `mov` captures a piece allowing the partner's opponent to
+/// force mate.
+pub fn gives_fatal_piece(engine: &mut dyn Engine, position: &Bughouse, mov: &Move, partner: &Bughouse,
+    limits: &SearchLimits) -> io::Result<bool>
+{
+    let mut after_move = position.clone();
+    after_move.play_unchecked(mov);
+    if after_move.is_game_over() {
+        return Ok(false);
+    }
+
+    let reply = engine::best_move(engine, &after_move, limits)?;
+    is_fatal_to_partner(engine, position, &[mov.clone(), reply], partner, limits)
+}
+
+/// Partner's board after the piece captured by `mov` in `position`, if any, is given to the
+/// pocket of the capturing player's partner. A promoted piece is a pawn again when captured.
+pub fn pass_capture(position: &Bughouse, mov: &Move, partner: &Bughouse) -> Result<Option<Bughouse>, String> {
+    match captured_role(position, mov) {
+        // The partner plays the color of the captured piece.
+        Some(role) => give_pieces(partner, !position.turn(), &[role]).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Role of the piece captured by `mov` in `position` as it goes to the pocket of the partner.
+pub fn captured_role(position: &Bughouse, mov: &Move) -> Option<Role> {
+    mov.capture()
+        .map(|role| if position.promoted().contains(mov.to()) { Role::Pawn } else { role })
+}
+
+pub fn give_pieces(position: &Bughouse, color: Color, roles: &[Role]) -> Result<Bughouse, String> {
+    if roles.is_empty() {
+        return Ok(position.clone());
+    }
+
+    modify_pockets(position, |pockets| {
+        let pocket = pocket_mut(pockets, color);
+        for &role in roles {
+            add_to_pocket(pocket, role);
+        }
+    })
+}
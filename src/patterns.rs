@@ -0,0 +1,222 @@
+/*
+ * Library of known bughouse tactics, recognized from the board and the pockets, and a miner
+ * scanning games for the positions where they work.
+ *
+ * Unlike the generator, the miner doesn't need a deep engine search: each pattern proves its
+ * solution by trying every reply of the opponent, which is cheap since the tactics are short.
+ */
+
+use std::cmp::min;
+use std::io;
+
+use shakmaty::{
+    attacks,
+    Bitboard,
+    Color,
+    Move,
+    Position,
+    position::Bughouse,
+    Role,
+    Setup,
+};
+
+use crate::generator::{self, MATERIAL_WIN, Options};
+use crate::importer::Game;
+use crate::material::material_balance;
+use crate::puzzle::{Goal, Puzzle};
+use crate::solution::Solution;
+
+/// Predicate on a position giving the goal and the solution when the tactic works.
+type Pattern = fn(&Bughouse) -> Option<(Goal, Vec<Move>)>;
+
+// Mates are tried first.
+const PATTERNS: [Pattern; 4] = [smothered_drop_mate, diagonal_battery_mate, checked_fork, trapped_rook];
+
+pub fn mine(game: &Game, options: Options) -> io::Result<Vec<Puzzle>> {
+    let mut puzzles = vec![];
+    let mut position = game.position.clone();
+    let mut index = 0;
+    while index < game.moves.len() {
+        let analyzed_position =
+            if options.full_opponent_pocket {
+                generator::fill_opponent_pocket(&position)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+            }
+            else {
+                position.clone()
+            };
+        let puzzle = find_pattern(&analyzed_position)
+            .map(|(goal, moves)| Puzzle {
+                clock: game.clock(index),
+                source: game.source.clone(),
+                ..Puzzle::new(analyzed_position, game.partner.clone(), goal, Solution::from_line(&moves))
+            })
+            .filter(generator::has_time);
+        match puzzle {
+            Some(puzzle) => {
+                // Don't create another puzzle from the positions of the solution.
+                let end = min(index + puzzle.solution.len(), game.moves.len());
+                puzzles.push(puzzle);
+                for mov in &game.moves[index..end] {
+                    position.play_unchecked(mov);
+                }
+                index = end;
+            },
+            None => {
+                position.play_unchecked(&game.moves[index]);
+                index += 1;
+            },
+        }
+    }
+    Ok(puzzles)
+}
+
+fn find_pattern(position: &Bughouse) -> Option<(Goal, Vec<Move>)> {
+    if position.is_game_over() {
+        return None;
+    }
+
+    PATTERNS.iter()
+        .filter_map(|pattern| pattern(position))
+        .next()
+}
+
+/// A knight dropped with check on a king surrounded by its own pieces.
+fn smothered_drop_mate(position: &Bughouse) -> Option<(Goal, Vec<Move>)> {
+    let opponent = !position.turn();
+    drop_mates(position, Role::Knight)
+        .find(|(_, after_drop)| {
+            let board = after_drop.board();
+            board.king_of(opponent)
+                .map(|king| (attacks::king_attacks(king) & !board.by_color(opponent)).is_empty())
+                .unwrap_or(false)
+        })
+        .map(|(mov, _)| (Goal::Mate, vec![mov]))
+}
+
+/// A bishop dropped next to the king in front of a queen on the same diagonal, so that the king
+/// cannot take it.
+fn diagonal_battery_mate(position: &Bughouse) -> Option<(Goal, Vec<Move>)> {
+    let player = position.turn();
+    let queens = position.board().by_piece(Role::Queen.of(player));
+    drop_mates(position, Role::Bishop)
+        .find(|(mov, after_drop)| {
+            after_drop.board().king_of(!player)
+                .map(|king| {
+                    queens.into_iter().any(|queen| {
+                        attacks::bishop_attacks(queen, Bitboard(0)).contains(king) &&
+                            attacks::between(queen, king).contains(mov.to())
+                    })
+                })
+                .unwrap_or(false)
+        })
+        .map(|(mov, _)| (Goal::Mate, vec![mov]))
+}
+
+/// A check by a piece which also attacks another piece, taken once the king is safe.
+fn checked_fork(position: &Bughouse) -> Option<(Goal, Vec<Move>)> {
+    position.legals().iter()
+        .filter(|mov| {
+            let mut after_move = position.clone();
+            after_move.play_unchecked(mov);
+            after_move.checkers().contains(mov.to()) && !after_move.is_checkmate()
+        })
+        .filter_map(|mov| forced_capture(position, mov, |capture| capture.is_capture() && capture.from() == Some(mov.to())))
+        .next()
+}
+
+/// A rook stuck on its back rank, e.g. on a8 behind a pawn on b7, attacked when it cannot escape.
+fn trapped_rook(position: &Bughouse) -> Option<(Goal, Vec<Move>)> {
+    let player = position.turn();
+    let occupied = position.board().occupied();
+    // A rook already attacked is simply hanging.
+    let rooks: Bitboard = (position.board().by_piece(Role::Rook.of(!player)) & Bitboard::BACKRANKS).into_iter()
+        .filter(|&rook| position.board().attacks_to(rook, player, occupied).is_empty())
+        .collect();
+    if rooks.is_empty() {
+        return None;
+    }
+
+    position.legals().iter()
+        .filter(|mov| !mov.is_capture())
+        .filter(|mov| {
+            let mut after_move = position.clone();
+            after_move.play_unchecked(mov);
+            let board = after_move.board();
+            let replies = after_move.legals();
+            rooks.into_iter().any(|rook| {
+                !board.attacks_to(rook, player, board.occupied()).is_empty() &&
+                    !replies.iter().any(|reply| reply.from() == Some(rook))
+            })
+        })
+        .filter_map(|mov| forced_capture(position, mov, |capture| capture.capture() == Some(Role::Rook) && rooks.contains(capture.to())))
+        .next()
+}
+
+/// Legal drops of `role` giving checkmate, with the position after the drop.
+fn drop_mates<'a>(position: &'a Bughouse, role: Role) -> impl Iterator<Item=(Move, Bughouse)> + 'a {
+    position.legals().into_iter()
+        .filter(move |mov| {
+            match *mov {
+                Move::Put { role: dropped, .. } => dropped == role,
+                _ => false,
+            }
+        })
+        .map(move |mov| {
+            let mut after_drop = position.clone();
+            after_drop.play_unchecked(&mov);
+            (mov, after_drop)
+        })
+        .filter(|(_, after_drop)| after_drop.is_checkmate())
+}
+
+/// Check that, whatever the reply to `mov`, one of the captures accepted by `is_capture` wins at
+/// least `MATERIAL_WIN` pawns, even after the opponent takes back. The solution follows the reply
+/// losing the least material.
+fn forced_capture<F>(position: &Bughouse, mov: &Move, is_capture: F) -> Option<(Goal, Vec<Move>)>
+where F: Fn(&Move) -> bool
+{
+    let player = position.turn();
+    let initial_balance = material_balance(position, player);
+    let mut after_move = position.clone();
+    after_move.play_unchecked(mov);
+    if after_move.is_game_over() {
+        return None;
+    }
+
+    let mut solution: Option<(i32, Vec<Move>)> = None;
+    for reply in after_move.legals() {
+        let mut after_reply = after_move.clone();
+        after_reply.play_unchecked(&reply);
+        let best_capture = after_reply.legals().into_iter()
+            .filter(|capture| is_capture(capture))
+            .map(|capture| {
+                let mut after_capture = after_reply.clone();
+                after_capture.play_unchecked(&capture);
+                (kept_balance(&after_capture, player) - initial_balance, capture)
+            })
+            .max_by_key(|&(gain, _)| gain);
+        match best_capture {
+            Some((gain, capture)) if gain >= MATERIAL_WIN => {
+                let is_worst = solution.as_ref().map(|&(worst_gain, _)| gain < worst_gain).unwrap_or(true);
+                if is_worst {
+                    solution = Some((gain, vec![mov.clone(), reply, capture]));
+                }
+            },
+            _ => return None,
+        }
+    }
+    solution.map(|(gain, moves)| (Goal::WinMaterial(gain), moves))
+}
+
+/// Material balance of `player` after the reply of the opponent, to move, that is best for them.
+fn kept_balance(position: &Bughouse, player: Color) -> i32 {
+    position.legals().iter()
+        .map(|reply| {
+            let mut after_reply = position.clone();
+            after_reply.play_unchecked(reply);
+            material_balance(&after_reply, player)
+        })
+        .min()
+        .unwrap_or_else(|| material_balance(position, player))
+}
@@ -0,0 +1,702 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    Color,
+    fen::{self, Fen},
+    FromSetup,
+    Move,
+    Position,
+    position::Bughouse,
+    Role,
+    san::San,
+    Setup,
+};
+
+use crate::annotation::{self, Annotation};
+use crate::bfen;
+use crate::bpgn::Board;
+use crate::hash;
+use crate::material::material_balance;
+use crate::partner::{self, Boards};
+use crate::solution::Solution;
+use crate::zobrist;
+
+/// Time left, in seconds, under which a player is about to lose on time.
+const LOW_TIME: f32 = 15.0;
+
+/// Time left, in seconds, when the position occurred in the game.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Clock {
+    pub opponent: f32,
+    pub player: f32,
+}
+
+impl Clock {
+    /// Check if the player is about to lose on time, having less time than the opponent.
+    pub fn is_low(&self) -> bool {
+        self.player < LOW_TIME && self.player < self.opponent
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} against {}", format_time(self.player), format_time(self.opponent))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Goal {
+    /// Find the only move avoiding the mate, then hold for this many plies of the scripted attack.
+    Defend(usize),
+    Mate,
+    /// Capture one of these pieces so that the partner can force mate on their board.
+    PartnerMate(#[serde(with = "roles")] Vec<Role>),
+    /// Don't move, because every move loses.
+    Sit,
+    /// Delay the mate as long as possible: the opponent can mate in this many moves.
+    Survive(i32),
+    /// Win at least this much material, in pawns.
+    WinMaterial(i32),
+}
+
+/// Goals are written in the Goal header of the PGN, e.g. "Mate", "PartnerMate nq", "Defend 3" or
+/// "WinMaterial 3".
+impl fmt::Display for Goal {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Goal::Defend(plies) => write!(formatter, "Defend {}", plies),
+            Goal::Mate => write!(formatter, "Mate"),
+            Goal::PartnerMate(ref roles) => {
+                let letters: String = roles.iter().map(|role| role.char()).collect();
+                write!(formatter, "PartnerMate {}", letters)
+            },
+            Goal::Sit => write!(formatter, "Sit"),
+            Goal::Survive(moves) => write!(formatter, "Survive {}", moves),
+            Goal::WinMaterial(gain) => write!(formatter, "WinMaterial {}", gain),
+        }
+    }
+}
+
+impl FromStr for Goal {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut words = text.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let argument = words.next().unwrap_or("");
+        let number = || argument.parse().map_err(|_| format!("Invalid goal {}", text));
+        match name {
+            "Defend" => Ok(Goal::Defend(number()?)),
+            "Mate" => Ok(Goal::Mate),
+            "PartnerMate" => argument.chars()
+                .map(|letter| Role::from_char(letter).ok_or_else(|| format!("Invalid goal {}", text)))
+                .collect::<Result<_, _>>()
+                .map(Goal::PartnerMate),
+            "Sit" => Ok(Goal::Sit),
+            "Survive" => Ok(Goal::Survive(number()?)),
+            "WinMaterial" => Ok(Goal::WinMaterial(number()?)),
+            _ => Err(format!("Unknown goal {}", text)),
+        }
+    }
+}
+
+/// Move of the partner's board, played after the first `ply` moves of the main line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartnerMove {
+    pub mov: Move,
+    pub ply: usize,
+}
+
+/// Message of the partner, shown once `ply` moves of the main line are played.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PartnerMessage {
+    pub ply: usize,
+    pub text: String,
+}
+
+/// Piece captured on the partner's board, arriving in a pocket of the board of the puzzle once
+/// `ply` moves of the main line are played.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arrival {
+    /// Color of the pocket.
+    pub color: Color,
+    pub ply: usize,
+    pub role: Role,
+}
+
+/// Names of the players, white first.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Players {
+    /// Players of the board of the puzzle.
+    pub board: [String; 2],
+    /// Board of the puzzle in the BPGN game.
+    #[serde(default)]
+    pub main_board: Board,
+    /// Players of the partner's board.
+    pub partner: [String; 2],
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(into = "PuzzleRecord", try_from = "PuzzleRecord")]
+pub struct Puzzle {
+    /// Arrows and circles to show during the main line, from the comments of the PGN.
+    pub annotations: Vec<Annotation>,
+    /// Best share of the moves of the solution found before the first mistake, in percent, for
+    /// the statistics.
+    pub best_score: Option<u32>,
+    /// Chapter of the training course the puzzle belongs to, e.g. "Smothered drop mates".
+    pub chapter: Option<String>,
+    /// Clocks in the game the puzzle comes from, when known.
+    pub clock: Option<Clock>,
+    /// Estimated rating of the puzzle.
+    pub difficulty: Option<u32>,
+    /// Number of times the user failed the puzzle, e.g. by watching the solution, for the statistics.
+    pub failures: u32,
+    pub goal: Goal,
+    /// Number of hints shown to the user in this puzzle, for the statistics.
+    pub hints: u32,
+    /// Messages of the partner shown during the main line, e.g. asking not to trade.
+    pub messages: Vec<PartnerMessage>,
+    /// Notes of the user.
+    pub notes: String,
+    pub partner: Option<Bughouse>,
+    /// Clocks of the partner's board, the partner being the player.
+    pub partner_clock: Option<Clock>,
+    /// Moves played on the partner's board during the main line, in a puzzle from BPGN, or the
+    /// partner's mate after the capture, when the goal is the partner's mate.
+    pub partner_moves: Vec<PartnerMove>,
+    /// Players of the game the puzzle comes from, when known.
+    pub players: Option<Players>,
+    pub position: Bughouse,
+    /// Piece to ask the partner for before the first move, when it is part of the solution.
+    pub request: Option<Role>,
+    /// Tree of the correct moves and of the scripted replies, e.g. with another first move mating
+    /// as fast. Its main line is the one of the game.
+    pub solution: Solution,
+    /// Where the puzzle comes from, e.g. the URL of the game.
+    pub source: Option<String>,
+    /// Number of times the user solved the puzzle, for the statistics.
+    pub successes: u32,
+    /// Themes of the puzzle chosen by the user, e.g. "fork".
+    pub tags: Vec<String>,
+}
+
+impl Puzzle {
+    /// Puzzle to reach the `goal` by playing the `solution` from `position`, without metadata
+    /// and statistics.
+    pub fn new(position: Bughouse, partner: Option<Bughouse>, goal: Goal, solution: Solution) -> Self {
+        Self {
+            annotations: vec![],
+            best_score: None,
+            chapter: None,
+            clock: None,
+            difficulty: None,
+            failures: 0,
+            goal,
+            hints: 0,
+            messages: vec![],
+            notes: String::new(),
+            partner,
+            partner_clock: None,
+            partner_moves: vec![],
+            players: None,
+            position,
+            request: None,
+            solution,
+            source: None,
+            successes: 0,
+            tags: vec![],
+        }
+    }
+
+    /// Check if `mov` is a correct answer after the moves already `played`.
+    pub fn is_correct(&self, mov: &Move, played: &[Move]) -> bool {
+        if self.solution.accepts(mov, played) {
+            return true;
+        }
+        match self.goal {
+            Goal::PartnerMate(ref roles) => {
+                played.is_empty() && mov.capture().map(|role| roles.contains(&role)).unwrap_or(false)
+            },
+            Goal::Defend(_) | Goal::Mate | Goal::Sit | Goal::Survive(_) | Goal::WinMaterial(_) => false,
+        }
+    }
+
+    /// Check if the key move, the first move of the solution, is a drop.
+    pub fn starts_with_drop(&self) -> bool {
+        match self.solution.first() {
+            Some(Move::Put { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Line of the solution reaching the same position as the moves `played` when they leave the
+    /// solution, e.g. with two drops played in another order.
+    pub fn transposed_line(&self, played: &[Move]) -> Option<Vec<Move>> {
+        if played.is_empty() || self.solution.after(played).is_some() {
+            return None;
+        }
+        let position_after = |moves: &[Move]| {
+            let mut position = self.position.clone();
+            for mov in moves {
+                position.play_unchecked(mov);
+            }
+            zobrist::hash(&position)
+        };
+        let hash = position_after(played);
+        self.solution.lines().into_iter()
+            .filter(|line| line.len() >= played.len())
+            .map(|mut line| {
+                line.truncate(played.len());
+                line
+            })
+            .find(|line| position_after(line) == hash)
+    }
+
+    /// Check if `mov`, played on the `boards` of the puzzle before any other move, gives the
+    /// partner what they need to play the scripted mate of the puzzle on their board. Without a
+    /// script, the capture of one of the roles of the goal is enough.
+    pub fn enables_partner_mate(&self, boards: &Boards, mov: &Move) -> bool {
+        let script = self.partner_mate();
+        if script.is_empty() {
+            return self.is_correct(mov, &[]);
+        }
+        let mut after_capture = boards.clone();
+        after_capture.play(mov).is_ok() && after_capture.partner_mates(&script)
+    }
+
+    /// Mate played by the partner on their board after the capture, in a puzzle whose goal is the
+    /// partner's mate.
+    pub fn partner_mate(&self) -> Vec<Move> {
+        match self.goal {
+            Goal::PartnerMate(_) => self.partner_moves.iter()
+                .filter(|partner_move| partner_move.ply > 0)
+                .map(|partner_move| partner_move.mov.clone())
+                .collect(),
+            Goal::Defend(_) | Goal::Mate | Goal::Sit | Goal::Survive(_) | Goal::WinMaterial(_) => vec![],
+        }
+    }
+
+    /// Check if the puzzle is solved after the moves `played` lead to `boards`.
+    pub fn is_solved(&self, boards: &Boards, played: &[Move]) -> bool {
+        let position = &boards.position;
+        match self.goal {
+            // The attack may be played by the engine after a move leaving the script.
+            Goal::Defend(plies) => self.solution.ends_line(played) || (played.len() >= plies && !position.is_checkmate()),
+            // The final move may differ from the solution when the engine plays the replies.
+            Goal::Mate => position.is_checkmate(),
+            // Success is on the partner's board, once the partner played the scripted mate.
+            Goal::PartnerMate(_) if !self.partner_mate().is_empty() =>
+                boards.partner.as_ref().map(|partner| partner.is_checkmate()).unwrap_or(false),
+            Goal::PartnerMate(_) => self.solution.ends_line(played),
+            // Survival puzzles end when the opponent mates.
+            Goal::Sit | Goal::Survive(_) => false,
+            Goal::WinMaterial(gain) => {
+                let player = self.position.turn();
+                self.solution.ends_line(played) ||
+                    material_balance(position, player) - material_balance(&self.position, player) >= gain
+            },
+        }
+    }
+
+    /// Number of moves left to the player to mate after the moves already `played`, following the
+    /// main line.
+    pub fn mate_in(&self, played: &[Move]) -> Option<usize> {
+        match self.goal {
+            // The player plays the odd plies of the line.
+            Goal::Mate => Some(((self.solution.len() + 1) / 2).saturating_sub((played.len() + 1) / 2)),
+            Goal::Defend(_) | Goal::PartnerMate(_) | Goal::Sit | Goal::Survive(_) | Goal::WinMaterial(_) => None,
+        }
+    }
+
+    /// Scripted move following the moves already `played`, in their branch of the solution.
+    pub fn next_move(&self, played: &[Move]) -> Option<&Move> {
+        self.solution.next_move(played)
+    }
+
+    /// Scripted reply of the opponent to the moves already `played`, picked at random among the
+    /// replies of their branch losing as fast, so that solving the puzzle again is not only
+    /// memorization. The main line is followed when the partner's moves depend on it.
+    pub fn opponent_reply(&self, played: &[Move]) -> Option<&Move> {
+        if !self.partner_moves.is_empty() {
+            return self.next_move(played);
+        }
+
+        self.solution.longest_moves_after(played)
+            .choose(&mut rand::thread_rng())
+            .cloned()
+    }
+
+    /// Pieces arriving in the pockets of the board of the puzzle during the main line, from the
+    /// captures of the partner's moves.
+    pub fn pocket_arrivals(&self) -> Vec<Arrival> {
+        let mut arrivals = vec![];
+        let plies: Vec<usize> = self.partner_moves.iter().map(|partner_move| partner_move.ply).collect();
+        let main_line = self.solution.main_line();
+        let result = replay(&self.position, self.partner.as_ref(), main_line.len(), &plies, |on_partner, index, position| {
+            if !on_partner {
+                return Ok(main_line[index].clone());
+            }
+            let partner_move = &self.partner_moves[index];
+            // The captured piece goes to the player of its color on the other board.
+            if let Some(role) = partner::captured_role(position, &partner_move.mov) {
+                arrivals.push(Arrival {
+                    color: !position.turn(),
+                    ply: partner_move.ply,
+                    role,
+                });
+            }
+            Ok(partner_move.mov.clone())
+        });
+        if let Err(error) = result {
+            eprintln!("Cannot replay the partner's moves: {}", error);
+        }
+        arrivals
+    }
+
+    /// Check if the partner is about to lose on time, in which case the player cannot sit and must
+    /// play forcing moves.
+    pub fn partner_low_on_time(&self) -> bool {
+        self.partner_clock.map(|clock| clock.is_low()).unwrap_or(false)
+    }
+
+    /// Identifier of the puzzle, which stays the same across runs, exports and imports: a FNV-1a
+    /// hash of the BFEN of the puzzle and of its main line.
+    pub fn id(&self) -> String {
+        let content = format!("{} {}", bfen::format(&self.position, self.partner.as_ref()), to_san(&self.position, &self.solution.main_line()).join(" "));
+        format!("{:016x}", hash::fnv1a(content.as_bytes()))
+    }
+
+    /// Annotation of the position once `ply` moves of the main line are played.
+    pub fn annotation(&self, ply: usize) -> Option<&Annotation> {
+        self.annotations.iter().find(|annotation| annotation.ply == ply)
+    }
+
+    /// Messages of the partner up to the moves `played`, as long as they follow the main line.
+    pub fn partner_messages<'a>(&'a self, played: &[Move]) -> impl Iterator<Item=&'a PartnerMessage> + 'a {
+        let ply = self.solution.main_line_ply(played);
+        self.messages.iter().filter(move |message| message.ply <= ply)
+    }
+
+    /// Moves `played` in SAN, with the NAGs and the comments of those following the main line,
+    /// e.g. "{Mate in 2} 1. Qxf7+! {The king must take} Kxf7".
+    pub fn move_list(&self, played: &[Move]) -> String {
+        let mut tokens = vec![];
+        let mut position = self.position.clone();
+        let mut on_main_line = true;
+        for ply in 0..=played.len() {
+            on_main_line = on_main_line && self.solution.on_main_line(&played[..ply]);
+            if ply > 0 {
+                let mov = &played[ply - 1];
+                let mut token = San::from_move(&position, mov).to_string();
+                if position.turn() == Color::White {
+                    tokens.push(format!("{}.", u32::from(position.fullmoves())));
+                }
+                else if ply == 1 {
+                    tokens.push(format!("{}...", u32::from(position.fullmoves())));
+                }
+                if on_main_line {
+                    for &nag in self.annotation(ply).map(|annotation| annotation.nags.as_slice()).unwrap_or(&[]) {
+                        token.push_str(&annotation::nag_symbol(nag));
+                    }
+                }
+                tokens.push(token);
+                position.play_unchecked(mov);
+            }
+            if let Some(annotation) = self.annotation(ply).filter(|_| on_main_line) {
+                if !annotation.comment.is_empty() {
+                    tokens.push(format!("{{{}}}", annotation.comment));
+                }
+            }
+        }
+        tokens.join(" ")
+    }
+}
+
+/// Serialized form of a puzzle, with the positions in FEN and the moves in SAN.
+#[derive(Deserialize, Serialize)]
+struct PuzzleRecord {
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+    #[serde(default)]
+    best_score: Option<u32>,
+    #[serde(default)]
+    chapter: Option<String>,
+    #[serde(default)]
+    clock: Option<Clock>,
+    #[serde(default)]
+    difficulty: Option<u32>,
+    #[serde(default)]
+    failures: u32,
+    fen: String,
+    goal: Goal,
+    #[serde(default)]
+    hints: u32,
+    /// Written for the other tools, the identifier being computed from the puzzle.
+    #[serde(default, skip_deserializing)]
+    id: String,
+    #[serde(default)]
+    messages: Vec<PartnerMessage>,
+    moves: Vec<String>,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    partner: Option<String>,
+    #[serde(default)]
+    partner_clock: Option<Clock>,
+    #[serde(default)]
+    partner_moves: Vec<PartnerMoveRecord>,
+    #[serde(default)]
+    players: Option<Players>,
+    #[serde(default, with = "request")]
+    request: Option<Role>,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    successes: u32,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Lines of the solution other than the main line.
+    #[serde(default)]
+    variations: Vec<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PartnerMoveRecord {
+    ply: usize,
+    san: String,
+}
+
+impl From<Puzzle> for PuzzleRecord {
+    fn from(puzzle: Puzzle) -> Self {
+        let id = puzzle.id();
+        let mut moves = vec![];
+        let mut partner_moves = vec![];
+        let plies: Vec<usize> = puzzle.partner_moves.iter().map(|partner_move| partner_move.ply).collect();
+        let main_line = puzzle.solution.main_line();
+        let result = replay(&puzzle.position, puzzle.partner.as_ref(), main_line.len(), &plies, |on_partner, index, position| {
+            let mov = if on_partner { &puzzle.partner_moves[index].mov } else { &main_line[index] };
+            let san = San::from_move(position, mov).to_string();
+            if on_partner {
+                partner_moves.push(PartnerMoveRecord {
+                    ply: plies[index],
+                    san,
+                });
+            }
+            else {
+                moves.push(san);
+            }
+            Ok(mov.clone())
+        });
+        if let Err(error) = result {
+            eprintln!("Cannot save the partner's moves: {}", error);
+            moves = to_san(&puzzle.position, &main_line);
+            partner_moves.clear();
+        }
+        Self {
+            annotations: puzzle.annotations,
+            best_score: puzzle.best_score,
+            chapter: puzzle.chapter,
+            clock: puzzle.clock,
+            difficulty: puzzle.difficulty,
+            failures: puzzle.failures,
+            fen: fen::fen(&puzzle.position),
+            goal: puzzle.goal,
+            hints: puzzle.hints,
+            id,
+            messages: puzzle.messages,
+            moves,
+            notes: puzzle.notes,
+            partner: puzzle.partner.as_ref().map(|partner| fen::fen(partner)),
+            partner_clock: puzzle.partner_clock,
+            partner_moves,
+            players: puzzle.players,
+            request: puzzle.request,
+            source: puzzle.source,
+            successes: puzzle.successes,
+            tags: puzzle.tags,
+            variations: puzzle.solution.variations().iter()
+                .map(|variation| to_san(&puzzle.position, variation))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<PuzzleRecord> for Puzzle {
+    type Error = String;
+
+    fn try_from(record: PuzzleRecord) -> Result<Self, Self::Error> {
+        let position = parse_position(&record.fen)?;
+        let partner =
+            match record.partner {
+                Some(ref partner) => Some(parse_position(partner)?),
+                None => None,
+            };
+        let mut moves = vec![];
+        let mut partner_moves = vec![];
+        let plies: Vec<usize> = record.partner_moves.iter().map(|partner_move| partner_move.ply).collect();
+        replay(&position, partner.as_ref(), record.moves.len(), &plies, |on_partner, index, board| {
+            let san = if on_partner { &record.partner_moves[index].san } else { &record.moves[index] };
+            let mov = parse_san(board, san)?;
+            if on_partner {
+                partner_moves.push(PartnerMove {
+                    mov: mov.clone(),
+                    ply: plies[index],
+                });
+            }
+            else {
+                moves.push(mov.clone());
+            }
+            Ok(mov)
+        })?;
+        let mut solution = Solution::from_line(&moves);
+        for variation in &record.variations {
+            solution.add_line(&from_san(&position, variation)?);
+        }
+        Ok(Self {
+            annotations: record.annotations,
+            best_score: record.best_score,
+            chapter: record.chapter,
+            clock: record.clock,
+            difficulty: record.difficulty,
+            failures: record.failures,
+            goal: record.goal,
+            hints: record.hints,
+            messages: record.messages,
+            notes: record.notes,
+            partner,
+            partner_clock: record.partner_clock,
+            partner_moves,
+            players: record.players,
+            position,
+            request: record.request,
+            solution,
+            source: record.source,
+            successes: record.successes,
+            tags: record.tags,
+        })
+    }
+}
+
+/// Format a time in seconds as "m:ss".
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Remove the `puzzles` which have the same position and first move as a puzzle of `existing` or
+/// a previous one, giving the number of duplicates removed.
+pub fn remove_duplicates(existing: &[Puzzle], puzzles: &mut Vec<Puzzle>) -> usize {
+    let key = |puzzle: &Puzzle| (zobrist::hash(&puzzle.position), puzzle.solution.first().cloned());
+    let mut keys: HashSet<_> = existing.iter().map(key).collect();
+    let count = puzzles.len();
+    puzzles.retain(|puzzle| keys.insert(key(puzzle)));
+    count - puzzles.len()
+}
+
+pub fn parse_position(fen: &str) -> Result<Bughouse, String> {
+    let fen = Fen::from_ascii(fen.as_bytes()).map_err(|error| format!("Error parsing FEN: {}", error))?;
+    Bughouse::from_setup(&fen).map_err(|error| format!("Error setup position: {}", error))
+}
+
+pub fn to_san(position: &Bughouse, moves: &[Move]) -> Vec<String> {
+    let mut position = position.clone();
+    moves.iter()
+        .map(|mov| {
+            let san = San::from_move(&position, mov).to_string();
+            position.play_unchecked(mov);
+            san
+        })
+        .collect()
+}
+
+pub fn from_san(position: &Bughouse, moves: &[String]) -> Result<Vec<Move>, String> {
+    let mut position = position.clone();
+    let mut result = vec![];
+    for san in moves {
+        let mov = parse_san(&position, san)?;
+        position.play_unchecked(&mov);
+        result.push(mov);
+    }
+    Ok(result)
+}
+
+/// Parse the legal move `san` of the `position`, e.g. "Nxf7+" or the drop "N@f3".
+pub fn parse_san(position: &Bughouse, san: &str) -> Result<Move, String> {
+    San::from_ascii(san.as_bytes()).ok()
+        .and_then(|parsed| parsed.to_move(position).ok())
+        .ok_or_else(|| format!("Invalid move {}", san))
+}
+
+/// Replay the main line of `moves_count` moves and the partner's moves played after the main
+/// line moves of `partner_plies`, in the order they were played. `play` gives each move from the
+/// position before it, whether it is on the partner's board and its index in its line. The
+/// captured pieces are given to the partner of the capturing player.
+pub fn replay<F>(position: &Bughouse, partner: Option<&Bughouse>, moves_count: usize, partner_plies: &[usize], mut play: F)
+    -> Result<(), String>
+where F: FnMut(bool, usize, &Bughouse) -> Result<Move, String>
+{
+    let mut boards = Boards::new(position.clone(), partner.cloned());
+    let mut partner_index = 0;
+    for index in 0..=moves_count {
+        while partner_plies.get(partner_index).map(|&ply| ply <= index).unwrap_or(false) {
+            let partner_position = boards.partner.as_ref().ok_or("Partner's moves without the partner's board")?;
+            let mov = play(true, partner_index, partner_position)?;
+            boards.play_partner(&mov)?;
+            partner_index += 1;
+        }
+        if index < moves_count {
+            let mov = play(false, index, &boards.position)?;
+            boards.play(&mov)?;
+        }
+    }
+    Ok(())
+}
+
+/// The requested role is serialized as its piece letter, e.g. "n".
+mod request {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+    use shakmaty::Role;
+
+    pub fn serialize<S: Serializer>(role: &Option<Role>, serializer: S) -> Result<S::Ok, S::Error> {
+        match *role {
+            Some(role) => serializer.serialize_some(&role.char().to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Role>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(letter) => {
+                let role = letter.chars().next().and_then(Role::from_char);
+                role.map(Some).ok_or_else(|| D::Error::custom(format!("invalid piece {}", letter)))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Roles are serialized as a string of piece letters, e.g. "nq".
+mod roles {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+    use shakmaty::Role;
+
+    pub fn serialize<S: Serializer>(roles: &[Role], serializer: S) -> Result<S::Ok, S::Error> {
+        let letters: String = roles.iter().map(|role| role.char()).collect();
+        serializer.serialize_str(&letters)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Role>, D::Error> {
+        String::deserialize(deserializer)?
+            .chars()
+            .map(|letter| Role::from_char(letter).ok_or_else(|| D::Error::custom(format!("invalid piece {}", letter))))
+            .collect()
+    }
+}
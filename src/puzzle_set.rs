@@ -0,0 +1,54 @@
+/*
+ * Native format of the puzzle sets, ".buzzle" files: JSON keeping everything known about the
+ * puzzles, like their solution lines, difficulty, tags, source and notes, which PGN loses.
+ *
+ * The version is increased when the format changes in a way older versions cannot read.
+ */
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::puzzle::Puzzle;
+
+pub const EXTENSION: &str = "buzzle";
+const VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize)]
+struct PuzzleSet {
+    puzzles: Vec<Puzzle>,
+    version: u32,
+}
+
+pub fn is_puzzle_set(path: &Path) -> bool {
+    path.extension().map(|extension| extension == EXTENSION).unwrap_or(false)
+}
+
+pub fn load(path: &Path) -> Result<Vec<Puzzle>, String> {
+    let file = File::open(path).map_err(|error| format!("Cannot open {}: {}", path.display(), error))?;
+    read(file).map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+/// Read a puzzle set, e.g. from a file or a pack.
+pub fn read<R: Read>(reader: R) -> Result<Vec<Puzzle>, String> {
+    let set: PuzzleSet = serde_json::from_reader(reader)
+        .map_err(|error| format!("Cannot read the puzzle set: {}", error))?;
+    if set.version > VERSION {
+        return Err("The puzzle set needs a newer version of buzzle".to_string());
+    }
+    Ok(set.puzzles)
+}
+
+pub fn save(path: &Path, puzzles: &[Puzzle]) -> Result<(), String> {
+    fs::write(path, to_json(puzzles)?).map_err(|error| format!("Cannot save {}: {}", path.display(), error))
+}
+
+pub fn to_json(puzzles: &[Puzzle]) -> Result<String, String> {
+    let set = PuzzleSet {
+        puzzles: puzzles.to_vec(),
+        version: VERSION,
+    };
+    serde_json::to_string_pretty(&set).map_err(|error| error.to_string())
+}
@@ -0,0 +1,112 @@
+/*
+ * Score the quality of a generated puzzle, from 0 to 100, so that the trivial puzzles and the ones
+ * with several solutions can be pruned.
+ *
+ * A good puzzle has a single solution, forces the replies of the opponent and, ideally, contains
+ * a sacrifice or a quiet move. Taking a hanging piece in one move is trivial.
+ */
+
+use std::io;
+
+use shakmaty::{
+    Move,
+    Position,
+};
+
+use crate::difficulty;
+use crate::engine::{Engine, Score, SearchLimits, WINNING_SCORE};
+use crate::puzzle::{Goal, Puzzle};
+
+pub const DEFAULT_MIN_QUALITY: u32 = 40;
+const FORCING_QUALITY: u32 = 30;
+// The opponent is forced when they have at most this many legal replies.
+const FORCED_REPLIES: usize = 3;
+// Number of lines searched to find other solutions.
+const MULTI_PV: usize = 3;
+const QUIET_MOVE_QUALITY: u32 = 10;
+const SACRIFICE_QUALITY: u32 = 20;
+const UNIQUENESS_QUALITY: u32 = 40;
+
+pub fn score(engine: &mut dyn Engine, puzzle: &Puzzle, limits: &SearchLimits) -> io::Result<u32> {
+    let first_move =
+        match puzzle.solution.first() {
+            Some(mov) => mov,
+            // Sitting is the only solution.
+            None => return Ok(UNIQUENESS_QUALITY + FORCING_QUALITY),
+        };
+    if puzzle.solution.len() == 1 && first_move.is_capture() && puzzle.goal != Goal::Mate {
+        return Ok(0);
+    }
+
+    let solutions = puzzle.solution.first_moves().count() + other_solutions(engine, puzzle, limits)?;
+    let (quiet_moves, sacrifices) = difficulty::count_moves(puzzle);
+    let mut quality = UNIQUENESS_QUALITY / solutions as u32 + forcing_quality(puzzle);
+    if sacrifices > 0 {
+        quality += SACRIFICE_QUALITY;
+    }
+    if quiet_moves > 0 {
+        quality += QUIET_MOVE_QUALITY;
+    }
+    Ok(quality)
+}
+
+/// Count the first moves reaching the goal which are not in the solution.
+fn other_solutions(engine: &mut dyn Engine, puzzle: &Puzzle, limits: &SearchLimits) -> io::Result<usize> {
+    let best_score =
+        match puzzle.goal {
+            // The other captures don't give the piece the partner needs.
+            Goal::PartnerMate(_) | Goal::Sit => return Ok(0),
+            // The defense is only kept when every other move allows an immediate mate.
+            Goal::Defend(_) => return Ok(0),
+            Goal::Mate => Score::Mate(((puzzle.solution.len() + 1) / 2) as i32),
+            Goal::Survive(moves) => Score::Mate(-moves),
+            Goal::WinMaterial(_) => Score::Centipawns(WINNING_SCORE),
+        };
+    let first_moves: Vec<&Move> = puzzle.solution.first_moves().collect();
+
+    engine.set_multi_pv(MULTI_PV)?;
+    engine.set_position(&puzzle.position)?;
+    let result = engine.search(limits);
+    let count = engine.lines().iter()
+        .filter(|line| line.score.map(|score| reaches(score, best_score)).unwrap_or(false))
+        .filter_map(|line| line.pv.first())
+        .filter_map(|uci| uci.to_move(&puzzle.position).ok())
+        .filter(|mov| !first_moves.contains(&mov))
+        .count();
+    engine.set_multi_pv(1)?;
+    result?;
+    Ok(count)
+}
+
+/// Check if `score` is as good as `best_score` for the goal of the puzzle.
+fn reaches(score: Score, best_score: Score) -> bool {
+    match (score, best_score) {
+        (Score::Mate(moves), Score::Mate(best)) if best > 0 => moves > 0 && moves <= best,
+        (Score::Mate(moves), Score::Mate(best)) => moves < 0 && moves <= best,
+        (Score::Centipawns(_), Score::Mate(_)) => false,
+        (Score::Mate(moves), Score::Centipawns(_)) => moves > 0,
+        (Score::Centipawns(centipawns), Score::Centipawns(best)) => centipawns >= best,
+    }
+}
+
+/// Share of the replies of the opponent in the main line of the solution which are forced.
+fn forcing_quality(puzzle: &Puzzle) -> u32 {
+    let mut position = puzzle.position.clone();
+    let mut replies = 0;
+    let mut forced_replies = 0;
+    for (index, mov) in puzzle.solution.main_line().iter().enumerate() {
+        if index % 2 != 0 {
+            replies += 1;
+            if position.legals().len() <= FORCED_REPLIES {
+                forced_replies += 1;
+            }
+        }
+        position.play_unchecked(mov);
+    }
+    if replies == 0 {
+        FORCING_QUALITY
+    }
+    else {
+        FORCING_QUALITY * forced_replies / replies
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Queue of game files to generate puzzles from.
+ *
+ * Generating puzzles from thousands of games takes hours, so the queue is saved to disk after
+ * every analyzed game: the generation can be paused, the application closed and the generation
+ * resumed later without analyzing the same games again.
+ */
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+use crate::filter::GameFilter;
+use crate::importer::{self, Game};
+use crate::puzzle::Puzzle;
+
+const FILE: &str = "queue.json";
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Queue {
+    /// Number of games analyzed since the queue was created.
+    pub analyzed_games: usize,
+    /// Number of games analyzed in this session: games are numbered from there.
+    #[serde(skip)]
+    committed: usize,
+    /// Number of games of `games` given to the generator.
+    #[serde(skip)]
+    dispatched: usize,
+    /// Games which could not be analyzed and must be given again.
+    #[serde(skip)]
+    failed: BTreeSet<usize>,
+    /// Games not analyzed yet of the loaded jobs.
+    #[serde(skip)]
+    games: VecDeque<Game>,
+    jobs: VecDeque<Job>,
+    /// Number of games not analyzed yet of the first jobs, whose games are loaded.
+    #[serde(skip)]
+    loaded: VecDeque<usize>,
+    /// Puzzles found in the games already analyzed.
+    pub puzzles: Vec<Puzzle>,
+    /// Puzzles of the games analyzed while a previous game is still being analyzed.
+    #[serde(skip)]
+    results: BTreeMap<usize, Vec<Puzzle>>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Job {
+    /// Filter of the games when the file was added, so that the same games are skipped.
+    #[serde(default)]
+    filter: GameFilter,
+    path: PathBuf,
+    /// Number of games of the file already analyzed.
+    processed: usize,
+}
+
+impl Queue {
+    pub fn load() -> Self {
+        data::load_json(FILE, "generation queue")
+    }
+
+    /// Save the queue, or remove the file when there's nothing left to generate.
+    pub fn save(&self) -> io::Result<()> {
+        if self.is_empty() {
+            let path = data::path(FILE).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+            return match fs::remove_file(path) {
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+
+        data::save_json(FILE, self)
+    }
+
+    pub fn add(&mut self, path: PathBuf, filter: GameFilter) {
+        self.jobs.push_back(Job {
+            filter,
+            path,
+            processed: 0,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Next game to analyze, with its number. It stays in the queue until `complete` is called
+    /// with this number, so that it is analyzed again if the application is closed in the meantime.
+    /// A file which cannot be read is removed from the queue.
+    pub fn next_game(&mut self) -> Result<Option<(usize, Game)>, String> {
+        if let Some(&number) = self.failed.iter().next() {
+            self.failed.remove(&number);
+            return Ok(Some((number, self.games[number - self.committed].clone())));
+        }
+
+        while self.dispatched == self.games.len() {
+            let index = self.loaded.len();
+            let job =
+                match self.jobs.get(index) {
+                    Some(job) => job,
+                    None => return Ok(None),
+                };
+            match importer::read_games(&job.path, &job.filter) {
+                Ok(games) => {
+                    let games: Vec<Game> = games.into_iter().skip(job.processed).collect();
+                    self.loaded.push_back(games.len());
+                    self.games.extend(games);
+                    self.remove_finished_jobs();
+                },
+                Err(error) => {
+                    let error = format!("Cannot read {}: {}", job.path.display(), error);
+                    self.jobs.remove(index);
+                    return Err(error);
+                },
+            }
+        }
+
+        let number = self.committed + self.dispatched;
+        self.dispatched += 1;
+        Ok(Some((number, self.games[number - self.committed].clone())))
+    }
+
+    /// Give the game `number` again to the generator, since its analysis failed.
+    pub fn fail(&mut self, number: usize) {
+        if number >= self.committed {
+            self.failed.insert(number);
+        }
+    }
+
+    /// Record the puzzles found in the game `number`. Since games can be analyzed in any order,
+    /// the puzzles are only added once the previous games are analyzed too: they are returned
+    /// then, in the order of the games.
+    pub fn complete(&mut self, number: usize, puzzles: Vec<Puzzle>) -> Vec<Puzzle> {
+        if number >= self.committed {
+            self.results.insert(number, puzzles);
+        }
+
+        let mut new_puzzles = vec![];
+        while let Some(puzzles) = self.results.remove(&self.committed) {
+            self.games.pop_front();
+            self.dispatched -= 1;
+            self.committed += 1;
+            if let Some(job) = self.jobs.front_mut() {
+                job.processed += 1;
+            }
+            if let Some(remaining) = self.loaded.front_mut() {
+                *remaining -= 1;
+            }
+            self.remove_finished_jobs();
+            self.analyzed_games += 1;
+            new_puzzles.extend(puzzles);
+        }
+        self.puzzles.extend(new_puzzles.iter().cloned());
+        new_puzzles
+    }
+
+    fn remove_finished_jobs(&mut self) {
+        while self.loaded.front() == Some(&0) {
+            self.loaded.pop_front();
+            self.jobs.pop_front();
+        }
+    }
+}
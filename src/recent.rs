@@ -0,0 +1,47 @@
+/*
+ * Files imported recently, saved as JSON in the user data directory, to import them again in one
+ * click. Whether the puzzles were appended to the current ones is remembered with each file.
+ */
+
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+
+const MAX_FILES: usize = 10;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RecentFile {
+    /// Whether the puzzles were appended to the current ones.
+    pub append: bool,
+    pub path: PathBuf,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RecentFiles {
+    /// The most recent first.
+    pub files: Vec<RecentFile>,
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        data::load_json("recent.json", "recent files")
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        data::save_json("recent.json", self)
+    }
+
+    /// Put the file first, with its new options.
+    pub fn add(&mut self, path: PathBuf, append: bool) {
+        self.files.retain(|file| file.path != path);
+        self.files.insert(0, RecentFile {
+            append,
+            path,
+        });
+        self.files.truncate(MAX_FILES);
+    }
+}
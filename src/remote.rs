@@ -0,0 +1,143 @@
+/*
+ * Distributed generation: other machines run `buzzle worker HOST:PORT` to connect to the
+ * application, the coordinator, which sends them games from its queue like to its local
+ * generators. This makes mining large dumps of bughouse-db feasible.
+ *
+ * The messages are JSON, one per line: the coordinator sends a job with a game and the generation
+ * options, the worker answers with the puzzles found or an error. A worker opens one connection
+ * per engine it runs.
+ */
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use relm::Channel;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::engine::{self, Cache, CachedEngine, Engine, SharedCache};
+use crate::generator::Options;
+use crate::importer::Game;
+use crate::puzzle::Puzzle;
+use crate::settings::Settings;
+use crate::worker;
+
+#[derive(Deserialize, Serialize)]
+struct Job {
+    game: Game,
+    options: Options,
+}
+
+/// Puzzles found by a worker, or its error.
+type JobResult = Result<Vec<Puzzle>, String>;
+
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        let writer = stream.try_clone()?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+
+    fn receive<T: DeserializeOwned>(&mut self) -> io::Result<T> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        serde_json::from_str(&line)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    fn send<T: Serialize>(&mut self, message: &T) -> io::Result<()> {
+        let mut line = serde_json::to_string(message)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Accept the connections of remote workers on `port`, giving them to `callback` in the GUI
+/// thread. The returned channel must be kept alive.
+pub fn listen<F: Fn(TcpStream) + 'static>(port: u16, callback: F) -> io::Result<Channel<TcpStream>> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    let (channel, sender) = Channel::new(callback);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break;
+                    }
+                },
+                Err(error) => eprintln!("Cannot accept remote worker: {}", error),
+            }
+        }
+    });
+    Ok(channel)
+}
+
+/// Have the remote worker find the puzzles of `game`.
+pub fn generate(connection: &mut Connection, game: Game, options: Options) -> io::Result<Vec<Puzzle>> {
+    connection.send(&Job {
+        game,
+        options,
+    })?;
+    let result: JobResult = connection.receive()?;
+    result.map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+}
+
+/// Work for the coordinator at `address` with one engine per generator of the settings, until
+/// the coordinator closes the connections.
+pub fn run_worker(address: &str) -> io::Result<()> {
+    let settings = Settings::load();
+    let cache = Arc::new(Mutex::new(Cache::load(&settings.engine_fingerprint(), settings.cache_size())));
+    let mut threads = vec![];
+    for _ in 0..settings.generator_count() {
+        let connection = Connection::new(TcpStream::connect(address)?)?;
+        let cache = cache.clone();
+        let settings = settings.clone();
+        threads.push(thread::spawn(move || work(connection, cache, &settings)));
+    }
+    eprintln!("Connected to {} with {} engines", address, threads.len());
+
+    let mut result = Ok(());
+    for thread in threads {
+        match thread.join() {
+            Ok(Ok(())) => (),
+            Ok(Err(error)) => result = Err(error),
+            Err(_) => result = Err(io::Error::new(io::ErrorKind::Other, "worker thread panicked")),
+        }
+    }
+    if let Ok(cache) = cache.lock() {
+        if let Err(error) = cache.save() {
+            eprintln!("Cannot save the engine cache: {}", error);
+        }
+    }
+    result
+}
+
+fn work(mut connection: Connection, cache: SharedCache, settings: &Settings) -> io::Result<()> {
+    let mut engine = CachedEngine::new(engine::new(&settings.engine_config()), cache);
+    engine.start()?;
+    engine.set_options(&settings.engine)?;
+    loop {
+        let job: Job =
+            match connection.receive() {
+                Ok(job) => job,
+                Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(error) => return Err(error),
+            };
+        let result: JobResult = worker::generate(&mut engine, &job.game, job.options)
+            .map_err(|error| error.to_string());
+        connection.send(&result)?;
+    }
+}
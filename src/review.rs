@@ -0,0 +1,76 @@
+/*
+ * Review of the mistakes: the puzzles failed by the user are queued until they are solved without
+ * mistake twice in a row.
+ *
+ * The queue is saved as JSON in the user data directory, with the puzzles identified by their id
+ * so that it applies to any puzzle set containing them.
+ */
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+
+// Solves without mistake in a row that remove a puzzle from the queue.
+const SOLVES_TO_CLEAR: u32 = 2;
+
+#[derive(Deserialize, Serialize)]
+struct ReviewEntry {
+    id: String,
+    /// Solves without mistake in a row since the last mistake.
+    solves: u32,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ReviewQueue {
+    /// Puzzles to review, from the oldest mistake.
+    entries: Vec<ReviewEntry>,
+}
+
+impl ReviewQueue {
+    pub fn load() -> Self {
+        data::load_json("review.json", "review queue")
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        data::save_json("review.json", self)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.entries.iter().any(|entry| entry.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Queue the puzzle of this id after a mistake, or start counting its solves again if it is
+    /// already queued.
+    pub fn mistake(&mut self, id: String) {
+        match self.entries.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) => entry.solves = 0,
+            None => self.entries.push(ReviewEntry {
+                id,
+                solves: 0,
+            }),
+        }
+    }
+
+    /// Count a solve without mistake of the puzzle of this id, removing it from the queue once it
+    /// is solved twice in a row. Return whether it was removed.
+    pub fn solve(&mut self, id: &str) -> bool {
+        let index =
+            match self.entries.iter().position(|entry| entry.id == id) {
+                Some(index) => index,
+                None => return false,
+            };
+        self.entries[index].solves += 1;
+        if self.entries[index].solves >= SOLVES_TO_CLEAR {
+            self.entries.remove(index);
+            return true;
+        }
+        false
+    }
+}
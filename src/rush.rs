@@ -0,0 +1,160 @@
+/*
+ * Puzzle rush: the puzzles are served one after the other, from the easiest, for a few minutes,
+ * and the run ends at the third wrong answer. A streak has no time limit, but ends at the first
+ * wrong answer.
+ *
+ * The best scores of each duration and the longest streak are saved as JSON in the user data
+ * directory.
+ */
+
+use std::io;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+use crate::puzzle::Puzzle;
+
+/// Durations of a run offered to the user, in minutes.
+pub const DURATIONS: [u64; 2] = [3, 5];
+const MAX_MISTAKES: u32 = 3;
+const MAX_STREAK_MISTAKES: u32 = 1;
+// Best scores kept for each duration.
+const MAX_SCORES: usize = 10;
+
+pub struct Rush {
+    /// Duration of the run, or none for a streak.
+    pub minutes: Option<u64>,
+    pub mistakes: u32,
+    /// Ids of the puzzles to serve, from the easiest, so that the run is not affected when the
+    /// puzzles move.
+    order: Vec<String>,
+    /// Number of puzzles solved.
+    pub score: u32,
+    /// Number of puzzles of `order` already served.
+    served: usize,
+    start: Instant,
+}
+
+impl Rush {
+    /// Run over the `puzzles`, whose ids are `ids`.
+    pub fn new(minutes: Option<u64>, puzzles: &[Puzzle], ids: Vec<String>) -> Self {
+        let mut indices: Vec<usize> = (0..puzzles.len()).collect();
+        indices.sort_by_key(|&index| puzzles[index].difficulty.unwrap_or(u32::max_value()));
+        let order = indices.into_iter()
+            .map(|index| ids[index].clone())
+            .collect();
+        Self {
+            minutes,
+            mistakes: 0,
+            order,
+            score: 0,
+            served: 0,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.mistakes >= self.max_mistakes() || self.remaining() == Some(Duration::from_secs(0))
+    }
+
+    pub fn is_streak(&self) -> bool {
+        self.minutes.is_none()
+    }
+
+    fn max_mistakes(&self) -> u32 {
+        if self.is_streak() { MAX_STREAK_MISTAKES } else { MAX_MISTAKES }
+    }
+
+    /// Id of the next puzzle to serve, if any is left.
+    pub fn next_puzzle(&mut self) -> Option<String> {
+        let id = self.order.get(self.served).cloned();
+        self.served += 1;
+        id
+    }
+
+    /// Time left, except in a streak.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.minutes.map(|minutes| {
+            Duration::from_secs(minutes * 60).checked_sub(self.start.elapsed())
+                .unwrap_or_else(|| Duration::from_secs(0))
+        })
+    }
+
+    /// Score, mistakes and time left, e.g. "Rush: 12 solved, 1/3 mistakes, 2:31 left".
+    pub fn status(&self) -> String {
+        match self.remaining() {
+            Some(remaining) => {
+                let seconds = remaining.as_secs();
+                format!("Rush: {} solved, {}/{} mistakes, {}:{:02} left", self.score, self.mistakes, MAX_MISTAKES,
+                    seconds / 60, seconds % 60)
+            },
+            None => format!("Streak: {} solved", self.score),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BestScore {
+    pub minutes: u64,
+    pub score: u32,
+    /// When the run ended, in seconds since the Unix epoch.
+    pub time: u64,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BestScores {
+    /// Number of puzzles solved in a row in the longest streak.
+    pub longest_streak: u32,
+    /// The best first, for each duration.
+    pub scores: Vec<BestScore>,
+}
+
+impl BestScores {
+    pub fn load() -> Self {
+        data::load_json("rush.json", "best scores")
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        data::save_json("rush.json", self)
+    }
+
+    /// Record the score of the finished `rush`, keeping the best ones of its duration or the
+    /// longest streak.
+    pub fn add(&mut self, rush: &Rush) {
+        let minutes =
+            match rush.minutes {
+                Some(minutes) => minutes,
+                None => {
+                    self.longest_streak = self.longest_streak.max(rush.score);
+                    return;
+                },
+            };
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        self.scores.push(BestScore {
+            minutes,
+            score: rush.score,
+            time,
+        });
+        // The most recent first among the equal scores.
+        self.scores.sort_by(|score1, score2| score2.score.cmp(&score1.score).then(score2.time.cmp(&score1.time)));
+        let mut kept = vec![0; DURATIONS.len()];
+        self.scores.retain(|score| {
+            match DURATIONS.iter().position(|&minutes| minutes == score.minutes) {
+                Some(index) => {
+                    kept[index] += 1;
+                    kept[index] <= MAX_SCORES
+                },
+                None => false,
+            }
+        });
+    }
+
+    /// Best scores of a run of `minutes`, the best first.
+    pub fn of_duration(&self, minutes: u64) -> impl Iterator<Item=&BestScore> {
+        self.scores.iter().filter(move |score| score.minutes == minutes)
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * User settings, saved as JSON in the user config directory (e.g. ~/.config/buzzle/settings.json).
+ *
+ * The search limits allow users on slow machines to trade the quality of the generated puzzles
+ * for speed.
+ */
+
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bpgn::Board;
+use crate::course;
+use crate::engine::{self, DEFAULT_CACHE_SIZE, EngineConfig, EngineOptions, SearchLimits};
+use crate::filter::GameFilter;
+use crate::generator;
+use crate::quality;
+
+pub const DEFAULT_PARTNER_BOARD_SIZE: u32 = 240;
+const DEFAULT_SOLUTION_DELAY: u32 = 1000;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Delay before going to the next unsolved puzzle after a success, in milliseconds. The
+    /// puzzle stays when not set.
+    pub auto_advance_delay: Option<u32>,
+    /// Board of the BPGN games the imported puzzles are played from.
+    pub bpgn_board: Board,
+    /// Maximum number of engine searches kept in the cache.
+    pub cache_size: Option<usize>,
+    /// Share of the puzzles of a chapter of the course to solve, in percent, to unlock the next
+    /// chapter.
+    pub chapter_unlock_rate: u32,
+    pub engine: EngineOptions,
+    /// Engine chosen by the user instead of the one of the environment.
+    pub engine_config: Option<EngineConfig>,
+    /// Games to generate puzzles from.
+    pub game_filter: GameFilter,
+    /// Limits of every search done while generating or validating puzzles.
+    pub generation: SearchLimits,
+    /// Number of engines analyzing games in parallel, by default one per CPU core.
+    pub generators: Option<usize>,
+    /// Generated puzzles of a lower quality, from 0 to 100, are pruned.
+    pub min_quality: u32,
+    /// A wrong move fails the puzzle and locks the board until it is retried, to discourage
+    /// trial and error.
+    pub one_try: bool,
+    pub partner_board: PartnerBoardLayout,
+    /// Time to solve each puzzle, in seconds, after which it fails. No clock when not set.
+    pub puzzle_clock: Option<u32>,
+    /// Port on which remote workers connect to help generating puzzles. They are not accepted
+    /// when not set.
+    pub remote_port: Option<u16>,
+    /// Delay between the moves when showing the solution, in milliseconds.
+    pub solution_delay: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            auto_advance_delay: None,
+            bpgn_board: Board::A,
+            cache_size: None,
+            chapter_unlock_rate: course::DEFAULT_UNLOCK_RATE,
+            engine: EngineOptions::default(),
+            engine_config: None,
+            game_filter: GameFilter::default(),
+            generation: generator::Options::default().limits,
+            generators: None,
+            min_quality: quality::DEFAULT_MIN_QUALITY,
+            one_try: false,
+            partner_board: PartnerBoardLayout::default(),
+            puzzle_clock: None,
+            remote_port: None,
+            solution_delay: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Corner {
+    BottomLeft,
+    BottomRight,
+    TopLeft,
+    TopRight,
+}
+
+/// Where the partner's board is shown: beside the board of the user, or over one of its corners
+/// for small screens.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct PartnerBoardLayout {
+    /// Corner of the board of the user where the partner's board is in picture-in-picture.
+    pub corner: Corner,
+    pub picture_in_picture: bool,
+    /// Size of the partner's board, in pixels.
+    pub size: u32,
+}
+
+impl Default for PartnerBoardLayout {
+    fn default() -> Self {
+        Self {
+            corner: Corner::TopRight,
+            picture_in_picture: false,
+            size: DEFAULT_PARTNER_BOARD_SIZE,
+        }
+    }
+}
+
+impl Settings {
+    /// Load the settings, falling back to the default settings if there's no config file.
+    pub fn load() -> Self {
+        let path =
+            match path() {
+                Some(path) => path,
+                None => return Self::default(),
+            };
+        match File::open(&path) {
+            Ok(file) =>
+                serde_json::from_reader(file).unwrap_or_else(|error| {
+                    eprintln!("Cannot parse settings file {}: {}", path.display(), error);
+                    Self::default()
+                }),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(error) => {
+                eprintln!("Cannot open settings file {}: {}", path.display(), error);
+                Self::default()
+            },
+        }
+    }
+
+    pub fn cache_size(&self) -> usize {
+        self.cache_size.unwrap_or(DEFAULT_CACHE_SIZE)
+    }
+
+    pub fn engine_config(&self) -> EngineConfig {
+        self.engine_config.clone().unwrap_or_else(engine::config_from_env)
+    }
+
+    /// Identify the engine and its settings, since changing them makes the cached searches
+    /// obsolete.
+    pub fn engine_fingerprint(&self) -> String {
+        let config = self.engine_config();
+        format!("{} {:?} {:?}", config.path, config.protocol, self.engine)
+    }
+
+    pub fn generator_count(&self) -> usize {
+        self.generators.unwrap_or_else(num_cpus::get).max(1)
+    }
+
+    pub fn solution_delay(&self) -> u32 {
+        self.solution_delay.unwrap_or(DEFAULT_SOLUTION_DELAY)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    glib::get_user_config_dir()
+        .map(|dir| dir.join("buzzle").join("settings.json"))
+}
@@ -0,0 +1,176 @@
+/*
+ * Tree of the solution of a puzzle: after each ply, the children are the moves accepted from the
+ * player, or the scripted replies of the opponent, each with the rest of its line. Several moves
+ * of the player can be correct at any ply and each can be followed by its own reply.
+ *
+ * The first child is the main line, which is the line of the game and the one shown with the
+ * annotations; the other children are the variations.
+ */
+
+use shakmaty::Move;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Solution {
+    children: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Node {
+    mov: Move,
+    next: Solution,
+}
+
+impl Solution {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Solution with the single line `moves`.
+    pub fn from_line(moves: &[Move]) -> Self {
+        let mut solution = Self::new();
+        solution.add_line(moves);
+        solution
+    }
+
+    /// Add the line `moves`, sharing the moves it starts with with the existing lines. The first
+    /// line added is the main line.
+    pub fn add_line(&mut self, moves: &[Move]) {
+        let mut solution = self;
+        for mov in moves {
+            let index =
+                match solution.children.iter().position(|node| node.mov == *mov) {
+                    Some(index) => index,
+                    None => {
+                        solution.children.push(Node {
+                            mov: mov.clone(),
+                            next: Self::new(),
+                        });
+                        solution.children.len() - 1
+                    },
+                };
+            solution = &mut solution.children[index].next;
+        }
+    }
+
+    /// Subtree after the moves `played`, if they follow one of the lines.
+    pub fn after(&self, played: &[Move]) -> Option<&Self> {
+        let mut solution = self;
+        for mov in played {
+            solution = &solution.children.iter().find(|node| node.mov == *mov)?.next;
+        }
+        Some(solution)
+    }
+
+    /// Check if `mov` follows one of the lines after the moves `played`.
+    pub fn accepts(&self, mov: &Move, played: &[Move]) -> bool {
+        self.moves_after(played).any(|next| next == mov)
+    }
+
+    /// Moves following the moves `played` in the lines, the main line first.
+    pub fn moves_after<'a>(&'a self, played: &[Move]) -> impl Iterator<Item=&'a Move> + 'a {
+        self.after(played)
+            .map(|solution| solution.children.as_slice())
+            .unwrap_or(&[])
+            .iter()
+            .map(|node| &node.mov)
+    }
+
+    /// Scripted move following the moves `played`: the first one of their branch.
+    pub fn next_move(&self, played: &[Move]) -> Option<&Move> {
+        self.moves_after(played).next()
+    }
+
+    /// Moves following the moves `played` whose lines are as long as the main line of their
+    /// branch, e.g. the replies of the opponent losing as fast.
+    pub fn longest_moves_after(&self, played: &[Move]) -> Vec<&Move> {
+        let solution =
+            match self.after(played) {
+                Some(solution) => solution,
+                None => return vec![],
+            };
+        let len = solution.len();
+        solution.children.iter()
+            .filter(|node| node.next.len() + 1 == len)
+            .map(|node| &node.mov)
+            .collect()
+    }
+
+    /// Check if the moves `played` end one of the lines.
+    pub fn ends_line(&self, played: &[Move]) -> bool {
+        !played.is_empty() && self.after(played).map(Self::is_empty).unwrap_or(false)
+    }
+
+    /// Check if the moves `played` follow the main line.
+    pub fn on_main_line(&self, played: &[Move]) -> bool {
+        let mut solution = self;
+        for mov in played {
+            match solution.children.first() {
+                Some(node) if node.mov == *mov => solution = &node.next,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Number of moves of the moves `played` following the main line.
+    pub fn main_line_ply(&self, played: &[Move]) -> usize {
+        (0..=played.len()).rev()
+            .find(|&ply| self.on_main_line(&played[..ply]))
+            .unwrap_or(0)
+    }
+
+    pub fn main_line(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        let mut solution = self;
+        while let Some(node) = solution.children.first() {
+            moves.push(node.mov.clone());
+            solution = &node.next;
+        }
+        moves
+    }
+
+    /// Every line from the start to the end of a branch, the main line first.
+    pub fn lines(&self) -> Vec<Vec<Move>> {
+        if self.is_empty() {
+            return vec![vec![]];
+        }
+        let mut lines = vec![];
+        for node in &self.children {
+            for mut line in node.next.lines() {
+                line.insert(0, node.mov.clone());
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    /// Lines other than the main line.
+    pub fn variations(&self) -> Vec<Vec<Move>> {
+        self.lines().into_iter().skip(1).collect()
+    }
+
+    /// First move of the main line.
+    pub fn first(&self) -> Option<&Move> {
+        self.children.first().map(|node| &node.mov)
+    }
+
+    /// Correct first moves of the player.
+    pub fn first_moves(&self) -> impl Iterator<Item=&Move> {
+        self.children.iter().map(|node| &node.mov)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Number of moves of the main line.
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        let mut solution = self;
+        while let Some(node) = solution.children.first() {
+            len += 1;
+            solution = &node.next;
+        }
+        len
+    }
+}
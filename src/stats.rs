@@ -0,0 +1,64 @@
+/*
+ * Statistics of the puzzles: the best score, successes, failures and hints of each puzzle.
+ *
+ * They are saved as JSON in the user data directory, with the puzzles identified by their id so
+ * that they are restored in any puzzle set containing them.
+ */
+
+use std::collections::BTreeMap;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data;
+use crate::puzzle::Puzzle;
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct Stats {
+    best_score: Option<u32>,
+    failures: u32,
+    hints: u32,
+    successes: u32,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PuzzleStats {
+    /// Statistics of the puzzles, by id.
+    puzzles: BTreeMap<String, Stats>,
+}
+
+impl PuzzleStats {
+    pub fn load() -> Self {
+        data::load_json("statistics.json", "puzzle statistics")
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        data::save_json("statistics.json", self)
+    }
+
+    /// Save the statistics of the `puzzle` of this id.
+    pub fn record(&mut self, id: String, puzzle: &Puzzle) {
+        self.puzzles.insert(id, Stats {
+            best_score: puzzle.best_score,
+            failures: puzzle.failures,
+            hints: puzzle.hints,
+            successes: puzzle.successes,
+        });
+    }
+
+    /// Give back its saved statistics to the `puzzle` of this id. The statistics it already has,
+    /// e.g. from a puzzle set file, are kept when they are higher.
+    pub fn restore(&self, id: &str, puzzle: &mut Puzzle) {
+        let stats =
+            match self.puzzles.get(id) {
+                Some(stats) => stats,
+                None => return,
+            };
+        puzzle.best_score = puzzle.best_score.max(stats.best_score);
+        puzzle.failures = puzzle.failures.max(stats.failures);
+        puzzle.hints = puzzle.hints.max(stats.hints);
+        puzzle.successes = puzzle.successes.max(stats.successes);
+    }
+}
@@ -0,0 +1,105 @@
+/*
+ * Timeline of the pieces arriving in the pockets during the puzzle, drawn under the partner's
+ * board: the plies of the main line go from left to right, with the pieces arriving in the pocket
+ * of the opponent above the line and those arriving in the pocket of the player below it, like on
+ * the board.
+ *
+ * It shows when the pieces became available in the game, which matters to decide whether to sit
+ * and wait for a piece.
+ */
+
+use cairo::Context;
+use shakmaty::{Color, Role};
+
+use crate::image;
+use crate::puzzle::{Arrival, Puzzle};
+
+pub const HEIGHT: i32 = 90;
+
+const AXIS_COLOR: (f64, f64, f64) = (0.4, 0.4, 0.4);
+const CURRENT_PLY_COLOR: (f64, f64, f64) = (0.08, 0.47, 0.11);
+const MARGIN: f64 = 12.0;
+const PIECE_SIZE: f64 = 14.0;
+
+#[derive(Default)]
+pub struct Timeline {
+    arrivals: Vec<Arrival>,
+    /// Number of moves of the main line.
+    length: usize,
+    player: Option<Color>,
+    /// Number of moves played.
+    ply: usize,
+}
+
+impl Timeline {
+    pub fn new(puzzle: &Puzzle) -> Self {
+        Self {
+            arrivals: puzzle.pocket_arrivals(),
+            length: puzzle.solution.len(),
+            player: Some(puzzle.position.turn()),
+            ply: 0,
+        }
+    }
+
+    pub fn set_ply(&mut self, ply: usize) {
+        self.ply = ply;
+    }
+
+    pub fn draw(&self, context: &Context, width: f64, height: f64) {
+        let player =
+            match self.player {
+                Some(player) => player,
+                None => return,
+            };
+        let axis_y = height / 2.0;
+        let step = (width - 2.0 * MARGIN) / self.length.max(1) as f64;
+        let ply_x = |ply: usize| MARGIN + ply.min(self.length) as f64 * step;
+
+        context.set_source_rgb(AXIS_COLOR.0, AXIS_COLOR.1, AXIS_COLOR.2);
+        context.set_line_width(1.0);
+        context.move_to(MARGIN, axis_y);
+        context.line_to(width - MARGIN, axis_y);
+        for ply in 0..=self.length {
+            context.move_to(ply_x(ply), axis_y - 3.0);
+            context.line_to(ply_x(ply), axis_y + 3.0);
+        }
+        context.stroke();
+
+        context.set_source_rgb(CURRENT_PLY_COLOR.0, CURRENT_PLY_COLOR.1, CURRENT_PLY_COLOR.2);
+        context.set_line_width(2.0);
+        context.move_to(ply_x(self.ply), 0.0);
+        context.line_to(ply_x(self.ply), height);
+        context.stroke();
+
+        context.set_font_size(PIECE_SIZE);
+        for &color in &[player, !player] {
+            let mut stacked = vec![0; self.length + 1];
+            for arrival in self.arrivals.iter().filter(|arrival| arrival.color == color) {
+                let ply = arrival.ply.min(self.length);
+                // The pieces of a ply are stacked away from the axis.
+                let offset = (stacked[ply] + 1) as f64 * PIECE_SIZE;
+                stacked[ply] += 1;
+                let y = if color == player { axis_y + offset } else { axis_y - offset + PIECE_SIZE };
+                draw_piece(context, arrival.role, color, ply_x(ply), y);
+            }
+        }
+    }
+}
+
+/// Draw the piece with its baseline at `y`, centered on `x`.
+fn draw_piece(context: &Context, role: Role, color: Color, x: f64, y: f64) {
+    let symbol = image::symbol(role);
+    let extents = context.text_extents(symbol);
+    context.move_to(x - extents.width / 2.0 - extents.x_bearing, y - 2.0);
+    context.text_path(symbol);
+    let (fill, outline) =
+        match color {
+            Color::White => (1.0, 0.0),
+            Color::Black => (0.0, 1.0),
+        };
+    context.set_source_rgb(fill, fill, fill);
+    context.fill_preserve();
+    context.set_source_rgb(outline, outline, outline);
+    context.set_line_width(0.5);
+    context.stroke();
+}
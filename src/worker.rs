@@ -0,0 +1,381 @@
+/*
+ * Run the engine on a background thread so that the GUI stays responsive during long analyses.
+ *
+ * Requests are sent to the thread via a channel and the responses come back to the relm event
+ * loop. Cancelling increments the generation: requests of an older generation are skipped and
+ * their responses must be ignored.
+ */
+
+use std::io;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use relm::{Channel, Sender};
+use shakmaty::{
+    Move,
+    Position,
+    position::Bughouse,
+    san::San,
+};
+
+use crate::difficulty;
+use crate::engine::{self, CachedEngine, Engine, EngineConfig, EngineOptions, Score, SearchLimits, SharedCache, StopHandle};
+use crate::generator::{self, Options};
+use crate::importer::{self, Game};
+use crate::partner;
+use crate::patterns;
+use crate::puzzle::{Goal, Puzzle};
+use crate::quality;
+use crate::remote::{self, Connection};
+use crate::settings::Settings;
+
+pub enum Request {
+    BestMove(Bughouse),
+    Evaluate(Bughouse),
+    /// Check a move which is not in the scripted solution, but may still reach the goal.
+    CheckMove {
+        goal: Goal,
+        mov: Move,
+        partner: Option<Bughouse>,
+        position: Bughouse,
+    },
+    Generate(Game, Options),
+    /// Find why a move is wrong.
+    Refute(Bughouse, Move),
+    Validate(Vec<Puzzle>, Options),
+}
+
+pub enum Response {
+    /// Best move, with the score from the point of view of the side playing it.
+    BestMove(Move, Option<Score>),
+    Error(String),
+    /// Score from the point of view of the side to move.
+    Evaluation(Option<Score>),
+    MoveChecked {
+        correct: bool,
+        fatal_to_partner: bool,
+        mov: Move,
+        /// The move is wrong, but still forces mate, slower than the solution.
+        slower_mate: bool,
+    },
+    /// Puzzles found in a game.
+    Generated(Vec<Puzzle>),
+    Puzzles(Vec<Puzzle>),
+    Refutation {
+        /// Moves in SAN, starting with the user's move.
+        pv: Vec<String>,
+        /// Score from the point of view of the user.
+        score: Option<Score>,
+    },
+}
+
+/// Engine to run, with its options.
+type Configuration = (EngineConfig, EngineOptions);
+
+pub struct Worker {
+    _channel: Channel<(usize, Response)>,
+    configuration: Arc<Mutex<Configuration>>,
+    generation: Arc<AtomicUsize>,
+    requests: mpsc::Sender<(usize, Request)>,
+    stop_handle: Arc<Mutex<Option<StopHandle>>>,
+}
+
+impl Worker {
+    pub fn new<F: Fn(usize, Response) + 'static>(cache: SharedCache, callback: F) -> Self {
+        let (channel, sender) = Channel::new(move |(generation, response)| callback(generation, response));
+        let (requests, receiver) = mpsc::channel();
+        let configuration = Arc::new(Mutex::new(default_configuration()));
+        let generation = Arc::new(AtomicUsize::new(0));
+        let stop_handle = Arc::new(Mutex::new(None));
+        {
+            let configuration = configuration.clone();
+            let generation = generation.clone();
+            let stop_handle = stop_handle.clone();
+            thread::spawn(move || run(receiver, sender, cache, generation, configuration, stop_handle));
+        }
+        Self {
+            _channel: channel,
+            configuration,
+            generation,
+            requests,
+            stop_handle,
+        }
+    }
+
+    /// Worker sending the generation requests to a remote worker connected with `stream`. Only
+    /// the requests to generate puzzles are supported.
+    pub fn remote<F: Fn(usize, Response) + 'static>(stream: TcpStream, callback: F) -> io::Result<Self> {
+        let connection = Connection::new(stream)?;
+        let (channel, sender) = Channel::new(move |(generation, response)| callback(generation, response));
+        let (requests, receiver) = mpsc::channel();
+        let generation = Arc::new(AtomicUsize::new(0));
+        {
+            let generation = generation.clone();
+            thread::spawn(move || run_remote(receiver, sender, connection, generation));
+        }
+        Ok(Self {
+            _channel: channel,
+            configuration: Arc::new(Mutex::new(default_configuration())),
+            generation,
+            requests,
+            stop_handle: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Abort the running search and ignore the pending requests.
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        if let Ok(stop_handle) = self.stop_handle.lock() {
+            if let Some(ref stop_handle) = *stop_handle {
+                stop_handle.stop();
+            }
+        }
+    }
+
+    /// Set the engine and its options, applied before the next request. The engine is restarted
+    /// when it changes.
+    pub fn configure(&self, settings: &Settings) {
+        if let Ok(mut configuration) = self.configuration.lock() {
+            *configuration = (settings.engine_config(), settings.engine);
+        }
+    }
+
+    pub fn is_current(&self, generation: usize) -> bool {
+        self.generation.load(Ordering::SeqCst) == generation
+    }
+
+    pub fn send(&self, request: Request) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        if self.requests.send((generation, request)).is_err() {
+            eprintln!("Engine thread stopped");
+        }
+    }
+}
+
+fn run(receiver: Receiver<(usize, Request)>, sender: Sender<(usize, Response)>, cache: SharedCache,
+    generation: Arc<AtomicUsize>, configuration: Arc<Mutex<Configuration>>, stop_handle: Arc<Mutex<Option<StopHandle>>>)
+{
+    let mut engine: Option<Box<dyn Engine + Send>> = None;
+    let mut applied_options = None;
+    let mut running_config = None;
+    for (request_generation, request) in receiver {
+        let is_cancelled = || generation.load(Ordering::SeqCst) != request_generation;
+        if is_cancelled() {
+            continue;
+        }
+
+        let (config, options) = configuration.lock()
+            .map(|configuration| configuration.clone())
+            .unwrap_or_else(|_| default_configuration());
+        if running_config.as_ref() != Some(&config) {
+            engine = None;
+            applied_options = None;
+        }
+
+        if engine.is_none() {
+            let mut new_engine = CachedEngine::new(engine::new(&config), cache.clone());
+            match new_engine.start() {
+                Ok(()) => {
+                    if let Ok(mut stop_handle) = stop_handle.lock() {
+                        *stop_handle = new_engine.stop_handle();
+                    }
+                    engine = Some(Box::new(new_engine));
+                    running_config = Some(config);
+                },
+                Err(error) => eprintln!("Cannot start engine: {}", error),
+            }
+        }
+
+        if let Some(ref mut engine) = engine {
+            if applied_options != Some(options) {
+                if let Err(error) = engine.set_options(&options) {
+                    eprintln!("Cannot set engine options: {}", error);
+                }
+                applied_options = Some(options);
+            }
+        }
+
+        let response =
+            match engine.as_mut() {
+                Some(engine) => handle(engine.as_mut(), request, &is_cancelled)
+                    .unwrap_or_else(|error| Response::Error(error.to_string())),
+                None => without_engine(request),
+            };
+        if !is_cancelled() && sender.send((request_generation, response)).is_err() {
+            break;
+        }
+    }
+}
+
+/// The thread stops when the connection is lost, after sending an error.
+fn run_remote(receiver: Receiver<(usize, Request)>, sender: Sender<(usize, Response)>, mut connection: Connection,
+    generation: Arc<AtomicUsize>)
+{
+    for (request_generation, request) in receiver {
+        let is_cancelled = || generation.load(Ordering::SeqCst) != request_generation;
+        if is_cancelled() {
+            continue;
+        }
+
+        let (response, connected) =
+            match request {
+                Request::Generate(game, options) =>
+                    match remote::generate(&mut connection, game, options) {
+                        Ok(puzzles) => (Response::Generated(puzzles), true),
+                        Err(error) => (Response::Error(format!("Remote worker: {}", error)), false),
+                    },
+                _ => (Response::Error("Remote workers only generate puzzles".to_string()), true),
+            };
+        let sent = is_cancelled() || sender.send((request_generation, response)).is_ok();
+        if !sent || !connected {
+            break;
+        }
+    }
+}
+
+fn handle(engine: &mut dyn Engine, request: Request, is_cancelled: &dyn Fn() -> bool) -> io::Result<Response> {
+    let analysis_limits = SearchLimits::depth(engine::ANALYSIS_DEPTH);
+    let validation_limits = SearchLimits::depth(engine::VALIDATION_DEPTH);
+    let response =
+        match request {
+            Request::BestMove(position) => {
+                let mov = engine::best_move(engine, &position, &validation_limits)?;
+                Response::BestMove(mov, engine.score())
+            },
+            Request::CheckMove { goal, mov, partner, position } => {
+                let correct =
+                    match goal {
+                        Goal::Defend(_) => engine::avoids_mate(engine, &position, &mov, &validation_limits)?,
+                        Goal::Mate => engine::keeps_mate(engine, &position, &mov, &validation_limits)?,
+                        Goal::Survive(_) => engine::survives_longest(engine, &position, &mov, &validation_limits)?,
+                        Goal::WinMaterial(gain) =>
+                            engine::keeps_material_win(engine, &position, &mov, gain, &validation_limits)?,
+                        Goal::PartnerMate(_) | Goal::Sit => true,
+                    };
+                let fatal_to_partner =
+                    match partner {
+                        Some(ref partner) if correct =>
+                            partner::gives_fatal_piece(engine, &position, &mov, partner, &validation_limits)?,
+                        _ => false,
+                    };
+                let slower_mate =
+                    match goal {
+                        Goal::Mate if !correct => engine::forces_slower_mate(engine, &position, &mov, &validation_limits)?,
+                        _ => false,
+                    };
+                Response::MoveChecked {
+                    correct,
+                    fatal_to_partner,
+                    mov,
+                    slower_mate,
+                }
+            },
+            Request::Evaluate(position) => {
+                engine.set_position(&position)?;
+                engine.search(&analysis_limits)?;
+                Response::Evaluation(engine.score())
+            },
+            Request::Generate(game, options) => Response::Generated(generate(engine, &game, options)?),
+            Request::Refute(position, mov) => {
+                let mut after_move = position.clone();
+                after_move.play_unchecked(&mov);
+                let mut pv = vec![San::from_move(&position, &mov).to_string()];
+                let mut score = None;
+                if !after_move.is_game_over() {
+                    engine.set_position(&after_move)?;
+                    engine.search(&analysis_limits)?;
+                    pv.extend(engine::pv_to_san(&after_move, engine.principal_variation()));
+                    score = engine.score().map(Score::negate);
+                }
+                Response::Refutation {
+                    pv,
+                    score,
+                }
+            },
+            Request::Validate(puzzles, options) => {
+                let mut valid_puzzles = vec![];
+                for puzzle in puzzles {
+                    if is_cancelled() {
+                        break;
+                    }
+                    match importer::validate(engine, &puzzle, options) {
+                        Ok(true) => valid_puzzles.push(puzzle),
+                        Ok(false) => eprintln!("Skipping puzzle refuted by the engine."),
+                        Err(error) => {
+                            eprintln!("Error validating puzzle: {}", error);
+                            valid_puzzles.push(puzzle);
+                        },
+                    }
+                }
+                rate(Some(engine), &mut valid_puzzles);
+                Response::Puzzles(valid_puzzles)
+            },
+        };
+    Ok(response)
+}
+
+fn rate(mut engine: Option<&mut dyn Engine>, puzzles: &mut [Puzzle]) {
+    for puzzle in puzzles {
+        match difficulty::estimate(engine.as_mut().map(|engine| &mut **engine), puzzle) {
+            Ok(difficulty) => puzzle.difficulty = Some(difficulty),
+            Err(error) => eprintln!("Error estimating difficulty: {}", error),
+        }
+    }
+}
+
+/// Find the puzzles of a game, pruned and rated.
+pub fn generate(engine: &mut dyn Engine, game: &Game, options: Options) -> io::Result<Vec<Puzzle>> {
+    let puzzles =
+        if options.patterns_only {
+            let mut puzzles = vec![];
+            for puzzle in patterns::mine(game, options)? {
+                if generator::is_sound(engine, &puzzle)? {
+                    puzzles.push(puzzle);
+                }
+            }
+            puzzles
+        }
+        else {
+            generator::generate(engine, game, options)?
+        };
+    let mut puzzles = prune(engine, puzzles, options);
+    rate(Some(engine), &mut puzzles);
+    Ok(puzzles)
+}
+
+/// Remove the puzzles of a quality lower than the minimum of the options.
+fn prune(engine: &mut dyn Engine, puzzles: Vec<Puzzle>, options: Options) -> Vec<Puzzle> {
+    if options.min_quality == 0 {
+        return puzzles;
+    }
+
+    puzzles.into_iter()
+        .filter(|puzzle| {
+            match quality::score(engine, puzzle, &options.limits) {
+                Ok(quality) => quality >= options.min_quality,
+                Err(error) => {
+                    eprintln!("Error scoring puzzle quality: {}", error);
+                    true
+                },
+            }
+        })
+        .collect()
+}
+
+fn default_configuration() -> Configuration {
+    (engine::config_from_env(), EngineOptions::default())
+}
+
+/// Without engine, imported puzzles are kept as is.
+fn without_engine(request: Request) -> Response {
+    match request {
+        Request::Validate(mut puzzles, _) => {
+            rate(None, &mut puzzles);
+            Response::Puzzles(puzzles)
+        },
+        _ => Response::Error("No engine available".to_string()),
+    }
+}
@@ -0,0 +1,68 @@
+/*
+ * Zobrist hashing of bughouse positions, to find duplicate puzzles and the cached engine searches
+ * without comparing FENs.
+ *
+ * Each piece on a square, each number of pieces of a role in a pocket, each promoted piece and
+ * the side to move has a pseudorandom key, and the hash of a position is the xor of the keys of
+ * its features. The keys are derived from their index so that the hashes don't change from one
+ * run to the next.
+ */
+
+use shakmaty::{
+    Color,
+    position::Bughouse,
+    Role,
+    Setup,
+};
+
+use crate::material::POCKET_ROLES;
+
+// Pockets hold at most the pieces of two sets.
+const MAX_POCKET_COUNT: u64 = 32;
+const POCKET_KEYS: u64 = 2 * 64 * 6;
+const PROMOTED_KEYS: u64 = POCKET_KEYS + 2 * 6 * (MAX_POCKET_COUNT + 1);
+const TURN_KEY: u64 = PROMOTED_KEYS + 64;
+
+pub fn hash(position: &Bughouse) -> u64 {
+    let mut hash = 0;
+    for (square, piece) in position.board().pieces() {
+        hash ^= key((color_index(piece.color) * 6 + role_index(piece.role)) * 64 + square as u64);
+    }
+    if let Some(pockets) = position.pockets() {
+        for &color in &[Color::White, Color::Black] {
+            for &role in &POCKET_ROLES {
+                let count = u64::from(pockets.by_color(color).by_role(role)).min(MAX_POCKET_COUNT);
+                if count > 0 {
+                    hash ^= key(POCKET_KEYS + (color_index(color) * 6 + role_index(role)) * (MAX_POCKET_COUNT + 1) + count);
+                }
+            }
+        }
+    }
+    for square in position.promoted() {
+        hash ^= key(PROMOTED_KEYS + square as u64);
+    }
+    if position.turn() == Color::Black {
+        hash ^= key(TURN_KEY);
+    }
+    hash
+}
+
+// The roles are numbered from 1.
+fn role_index(role: Role) -> u64 {
+    role as u64 - 1
+}
+
+fn color_index(color: Color) -> u64 {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// Key of the feature `index`, by the SplitMix64 generator.
+fn key(index: u64) -> u64 {
+    let mut value = index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}